@@ -1 +1,4 @@
 pub mod proxies;
+/// An in-process mock of the provider apis for offline testing.
+#[cfg(feature = "testing")]
+pub mod testing;
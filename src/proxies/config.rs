@@ -0,0 +1,776 @@
+use super::evomi::EvomiProduct;
+use super::ip::IpNet;
+use super::{
+    AwsMetadataResolver, AzureMetadataResolver, CredentialSource, DatainpulseConfiguration,
+    DnsRecord, DnsResolver, DotenvCredentials, EnvCredentials, EvomiConfiguration, FileCredentials,
+    GcpMetadataResolver, HttpEchoResolver, IPRoyaleConfiguration, IpResolvers, MappedCredentials,
+    Proxier, ProxierError, Result, StaticCredentials, StunResolver, WebShareConfiguration,
+    WebshareResolver,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The format of a configuration file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// A toml file.
+    Toml,
+    /// A yaml file.
+    Yaml,
+    /// A json file.
+    Json,
+}
+
+impl ConfigFormat {
+    /// The format from the file extension.
+    pub fn from_path(path: &Path) -> Option<ConfigFormat> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Where to read the credentials of an account from.
+#[derive(Deserialize, Clone)]
+#[serde(tag = "source", rename_all = "lowercase", deny_unknown_fields)]
+pub enum CredentialsConfig {
+    /// The process environment.
+    Env {
+        /// The env names by the provider key.
+        #[serde(default)]
+        keys: HashMap<String, String>,
+    },
+    /// The process environment or a `.env` file.
+    Dotenv {
+        /// The env names by the provider key.
+        #[serde(default)]
+        keys: HashMap<String, String>,
+    },
+    /// Files named after the key in a directory.
+    File {
+        /// The directory holding the secret files.
+        dir: PathBuf,
+        /// The file names by the provider key.
+        #[serde(default)]
+        keys: HashMap<String, String>,
+    },
+    /// The values inline in the configuration.
+    Static {
+        /// The credential values by the provider key.
+        values: HashMap<String, String>,
+    },
+}
+
+impl std::fmt::Debug for CredentialsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CredentialsConfig::Env { keys } => f.debug_struct("Env").field("keys", keys).finish(),
+            CredentialsConfig::Dotenv { keys } => {
+                f.debug_struct("Dotenv").field("keys", keys).finish()
+            }
+            CredentialsConfig::File { dir, keys } => f
+                .debug_struct("File")
+                .field("dir", dir)
+                .field("keys", keys)
+                .finish(),
+            // keep the secrets out of the logs.
+            CredentialsConfig::Static { values } => f
+                .debug_struct("Static")
+                .field(
+                    "values",
+                    &values.keys().collect::<std::collections::BTreeSet<_>>(),
+                )
+                .finish(),
+        }
+    }
+}
+
+impl CredentialsConfig {
+    /// Build the credential source.
+    pub fn source(&self) -> Arc<dyn CredentialSource> {
+        let (source, keys): (Arc<dyn CredentialSource>, _) = match self {
+            CredentialsConfig::Env { keys } => (Arc::new(EnvCredentials), keys),
+            CredentialsConfig::Dotenv { keys } => (Arc::new(DotenvCredentials), keys),
+            CredentialsConfig::File { dir, keys } => (Arc::new(FileCredentials::new(dir)), keys),
+            CredentialsConfig::Static { values } => {
+                let credentials = values
+                    .iter()
+                    .fold(StaticCredentials::new(), |credentials, (key, value)| {
+                        credentials.with(key, value)
+                    });
+                return Arc::new(credentials);
+            }
+        };
+
+        if keys.is_empty() {
+            source
+        } else {
+            Arc::new(MappedCredentials {
+                source,
+                keys: keys.clone(),
+            })
+        }
+    }
+}
+
+/// An account on a provider.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    /// The account name.
+    #[serde(default)]
+    pub account: Option<String>,
+    /// The api base url.
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// The credentials of the account.
+    #[serde(default)]
+    pub credentials: Option<CredentialsConfig>,
+}
+
+/// An account on iproyal.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct IPRoyaleAccountConfig {
+    /// The account name.
+    #[serde(default)]
+    pub account: Option<String>,
+    /// The api base url.
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// The credentials of the account.
+    #[serde(default)]
+    pub credentials: Option<CredentialsConfig>,
+    /// The residential user hash.
+    pub residential_user_hash: String,
+    /// The port.
+    #[serde(default)]
+    pub port: u16,
+    /// The configuration type.
+    #[serde(default)]
+    pub configuration: String,
+}
+
+/// An account on evomi.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct EvomiAccountConfig {
+    /// The account name.
+    #[serde(default)]
+    pub account: Option<String>,
+    /// The api base url.
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// The credentials of the account.
+    #[serde(default)]
+    pub credentials: Option<CredentialsConfig>,
+    /// The products to whitelist on.
+    #[serde(default)]
+    pub products: Vec<EvomiProduct>,
+}
+
+/// A resolver to find the server ip with.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ResolverConfig {
+    /// An http echo service returning the ip as text.
+    Http {
+        /// The url of the echo service.
+        url: String,
+    },
+    /// The webshare whatsmyip api.
+    Webshare {
+        /// The api base url.
+        #[serde(default)]
+        base_url: Option<String>,
+        /// The credentials of the account.
+        #[serde(default)]
+        credentials: Option<CredentialsConfig>,
+    },
+    /// A dns server answering with the address of the asker.
+    Dns {
+        /// The dns server address with the port.
+        server: SocketAddr,
+        /// The name to look up.
+        name: String,
+        /// The record holding the ip. Defaults to an a record.
+        #[serde(default)]
+        record: DnsRecord,
+    },
+    /// The opendns `myip.opendns.com` lookup.
+    Opendns,
+    /// The google `o-o.myaddr.l.google.com` lookup.
+    Google,
+    /// The aws instance metadata service.
+    Aws {
+        /// The metadata base url.
+        #[serde(default)]
+        base_url: Option<String>,
+    },
+    /// The gcp metadata server.
+    Gcp {
+        /// The metadata base url.
+        #[serde(default)]
+        base_url: Option<String>,
+    },
+    /// The azure instance metadata service.
+    Azure {
+        /// The metadata base url.
+        #[serde(default)]
+        base_url: Option<String>,
+    },
+    /// A stun binding request.
+    Stun {
+        /// The stun server as `host:port`. Defaults to the google stun server.
+        #[serde(default)]
+        server: Option<String>,
+    },
+}
+
+impl ResolverConfig {
+    /// Add the resolver to the set.
+    fn add_to(&self, resolvers: IpResolvers) -> IpResolvers {
+        match self {
+            ResolverConfig::Http { url } => resolvers.with(HttpEchoResolver::new(url)),
+            ResolverConfig::Webshare {
+                base_url,
+                credentials,
+            } => resolvers.with(WebshareResolver {
+                configuration: WebShareConfiguration {
+                    base_url: base_url.clone(),
+                    credentials: credentials.as_ref().map(CredentialsConfig::source),
+                    ..Default::default()
+                },
+            }),
+            ResolverConfig::Dns {
+                server,
+                name,
+                record,
+            } => resolvers.with(DnsResolver::new(*server, name, *record)),
+            ResolverConfig::Opendns => resolvers.with(DnsResolver::opendns()),
+            ResolverConfig::Google => resolvers.with(DnsResolver::google()),
+            ResolverConfig::Aws { base_url } => resolvers.with(AwsMetadataResolver {
+                base_url: base_url.clone(),
+                ..Default::default()
+            }),
+            ResolverConfig::Gcp { base_url } => resolvers.with(GcpMetadataResolver {
+                base_url: base_url.clone(),
+                ..Default::default()
+            }),
+            ResolverConfig::Azure { base_url } => resolvers.with(AzureMetadataResolver {
+                base_url: base_url.clone(),
+                ..Default::default()
+            }),
+            ResolverConfig::Stun { server } => {
+                resolvers.with(server.as_deref().map(StunResolver::new).unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// The declarative setup of a proxier.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProxierConfig {
+    /// The server ip NAT to whitelist. Resolved when empty.
+    #[serde(default)]
+    pub server_ip: Option<String>,
+    /// More server ips and cidr ranges to whitelist along with the server ip.
+    #[serde(default)]
+    pub server_ips: Vec<String>,
+    /// The ip echo service used to find the server ip.
+    #[serde(default)]
+    pub ip_echo_url: Option<String>,
+    /// The overall deadline in seconds for whitelisting and delisting.
+    #[serde(default)]
+    pub deadline_secs: Option<u64>,
+    /// The resolvers to find the server ip with, tried in order.
+    #[serde(default)]
+    pub resolvers: Vec<ResolverConfig>,
+    /// The amount of resolvers that need to agree on the server ip.
+    #[serde(default)]
+    pub resolver_quorum: Option<usize>,
+    /// The webshare accounts.
+    #[serde(default)]
+    pub webshare: Vec<AccountConfig>,
+    /// The iproyal accounts.
+    #[serde(default)]
+    pub iproyale: Vec<IPRoyaleAccountConfig>,
+    /// The datainpulse accounts.
+    #[serde(default)]
+    pub datainpulse: Vec<AccountConfig>,
+    /// The evomi accounts.
+    #[serde(default)]
+    pub evomi: Vec<EvomiAccountConfig>,
+}
+
+impl ProxierConfig {
+    /// Load the configuration from a file. The format is picked from the extension.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<ProxierConfig> {
+        let path = path.as_ref();
+        let format = ConfigFormat::from_path(path).ok_or_else(|| {
+            ProxierError::config(
+                "",
+                format!(
+                    "unknown config format for {}, use .toml, .yaml, .yml or .json",
+                    path.display()
+                ),
+            )
+        })?;
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            ProxierError::config("", format!("failed to read {}: {}", path.display(), err))
+        })?;
+
+        ProxierConfig::parse(&contents, format)
+    }
+
+    /// Parse and validate the configuration.
+    pub fn parse(contents: &str, format: ConfigFormat) -> Result<ProxierConfig> {
+        let config: ProxierConfig = match format {
+            ConfigFormat::Toml => serde_path_to_error::deserialize(toml::Deserializer::new(
+                contents,
+            ))
+            .map_err(|err| ProxierError::config(err.path().to_string(), err.inner().message()))?,
+            ConfigFormat::Yaml => {
+                serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(contents))
+                    .map_err(|err| {
+                        ProxierError::config(err.path().to_string(), err.inner().to_string())
+                    })?
+            }
+            ConfigFormat::Json => {
+                serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(contents))
+                    .map_err(|err| {
+                        ProxierError::config(err.path().to_string(), err.inner().to_string())
+                    })?
+            }
+        };
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Validate the values that the types alone do not catch.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(server_ip) = self.server_ip.as_deref() {
+            if !server_ip.is_empty() && super::ip::parse_public(server_ip).is_err() {
+                return Err(ProxierError::config("server_ip", "not a public ip address"));
+            }
+        }
+
+        for (i, server_ip) in self.server_ips.iter().enumerate() {
+            if server_ip
+                .parse::<IpNet>()
+                .and_then(super::ip::validate_public_net)
+                .is_err()
+            {
+                return Err(ProxierError::config(
+                    format!("server_ips[{}]", i),
+                    "not a public ip address or cidr range",
+                ));
+            }
+        }
+
+        if let Some(url) = self.ip_echo_url.as_deref() {
+            validate_url("ip_echo_url", url)?;
+        }
+
+        for (i, resolver) in self.resolvers.iter().enumerate() {
+            match resolver {
+                ResolverConfig::Http { url } => {
+                    validate_url(&format!("resolvers[{}].url", i), url)?
+                }
+                ResolverConfig::Webshare {
+                    base_url: Some(url),
+                    ..
+                }
+                | ResolverConfig::Aws {
+                    base_url: Some(url),
+                }
+                | ResolverConfig::Gcp {
+                    base_url: Some(url),
+                }
+                | ResolverConfig::Azure {
+                    base_url: Some(url),
+                } => validate_url(&format!("resolvers[{}].base_url", i), url)?,
+                _ => (),
+            }
+        }
+
+        if let Some(quorum) = self.resolver_quorum {
+            if quorum > self.resolvers.len() {
+                return Err(ProxierError::config(
+                    "resolver_quorum",
+                    format!(
+                        "a quorum of {} needs at least as many resolvers, found {}",
+                        quorum,
+                        self.resolvers.len()
+                    ),
+                ));
+            }
+        }
+
+        let accounts = self
+            .webshare
+            .iter()
+            .map(|a| ("webshare", &a.account, &a.base_url, &a.credentials))
+            .chain(
+                self.datainpulse
+                    .iter()
+                    .map(|a| ("datainpulse", &a.account, &a.base_url, &a.credentials)),
+            )
+            .chain(
+                self.evomi
+                    .iter()
+                    .map(|a| ("evomi", &a.account, &a.base_url, &a.credentials)),
+            )
+            .chain(
+                self.iproyale
+                    .iter()
+                    .map(|a| ("iproyale", &a.account, &a.base_url, &a.credentials)),
+            );
+
+        let mut seen = HashSet::new();
+        let mut index: HashMap<&str, usize> = HashMap::new();
+
+        for (provider, account, base_url, credentials) in accounts {
+            let i = index.entry(provider).or_default();
+            let path = format!("{}[{}]", provider, i);
+            *i += 1;
+
+            let account = account.as_deref().unwrap_or(super::DEFAULT_ACCOUNT);
+
+            if !seen.insert((provider, account)) {
+                return Err(ProxierError::config(
+                    format!("{}.account", path),
+                    format!("duplicate account {:?} for {}", account, provider),
+                ));
+            }
+
+            if let Some(url) = base_url.as_deref() {
+                validate_url(&format!("{}.base_url", path), url)?;
+            }
+
+            if let Some(CredentialsConfig::Static { values }) = credentials {
+                if let Some((key, _)) = values.iter().find(|(_, value)| value.is_empty()) {
+                    return Err(ProxierError::config(
+                        format!("{}.credentials.values.{}", path, key),
+                        "empty credential value",
+                    ));
+                }
+            }
+        }
+
+        for (i, iproyale) in self.iproyale.iter().enumerate() {
+            if iproyale.residential_user_hash.is_empty() {
+                return Err(ProxierError::config(
+                    format!("iproyale[{}].residential_user_hash", i),
+                    "must not be empty",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the proxier with all of the providers registered.
+    pub fn build(self) -> Proxier {
+        let mut proxier = Proxier::new(
+            self.server_ip
+                .as_deref()
+                .and_then(|server_ip| server_ip.trim().parse().ok()),
+        );
+
+        proxier.server_ips = self
+            .server_ips
+            .iter()
+            .filter_map(|server_ip| server_ip.parse().ok())
+            .collect();
+        proxier.ip_echo_url = self.ip_echo_url;
+        proxier.deadline = self.deadline_secs.map(Duration::from_secs);
+
+        if !self.resolvers.is_empty() {
+            let resolvers = self
+                .resolvers
+                .iter()
+                .fold(IpResolvers::new(), |resolvers, resolver| {
+                    resolver.add_to(resolvers)
+                });
+
+            proxier.with_resolvers(resolvers.with_quorum(self.resolver_quorum.unwrap_or_default()));
+        }
+
+        for a in self.webshare {
+            proxier.register(WebShareConfiguration {
+                account: a.account,
+                base_url: a.base_url,
+                credentials: a.credentials.as_ref().map(CredentialsConfig::source),
+                ..Default::default()
+            });
+        }
+
+        for a in self.datainpulse {
+            proxier.register(DatainpulseConfiguration {
+                account: a.account,
+                base_url: a.base_url,
+                credentials: a.credentials.as_ref().map(CredentialsConfig::source),
+                ..Default::default()
+            });
+        }
+
+        for a in self.iproyale {
+            proxier.register(IPRoyaleConfiguration {
+                residential_user_hash: a.residential_user_hash,
+                port: a.port,
+                configuration: a.configuration,
+                account: a.account,
+                base_url: a.base_url,
+                credentials: a.credentials.as_ref().map(CredentialsConfig::source),
+                ..Default::default()
+            });
+        }
+
+        for a in self.evomi {
+            proxier.register(EvomiConfiguration {
+                products: a.products,
+                account: a.account,
+                base_url: a.base_url,
+                credentials: a.credentials.as_ref().map(CredentialsConfig::source),
+                ..Default::default()
+            });
+        }
+
+        proxier
+    }
+}
+
+/// Validate a http url.
+fn validate_url(path: &str, url: &str) -> Result<()> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+        Ok(_) => Err(ProxierError::config(path, "the url must use http or https")),
+        Err(err) => Err(ProxierError::config(path, err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_redacts_static_credentials() {
+        let config = ProxierConfig::parse(
+            r#"
+            [[webshare]]
+            credentials = { source = "static", values = { PROXY_SHARE_PASSWORD = "secret-token" } }
+            "#,
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+
+        let debug = format!("{:?}", config);
+
+        assert!(debug.contains("PROXY_SHARE_PASSWORD"));
+        assert!(!debug.contains("secret-token"));
+    }
+
+    /// The key path of the error parsing the toml configuration.
+    fn error_path(contents: &str) -> String {
+        match ProxierConfig::parse(contents, ConfigFormat::Toml) {
+            Err(ProxierError::Config { path, .. }) => path,
+            other => panic!("expected a config error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn duplicate_account_is_reported_at_the_second_account() {
+        let path = error_path(
+            r#"
+            [[datainpulse]]
+            account = "main"
+
+            [[webshare]]
+
+            [[datainpulse]]
+            account = "main"
+            "#,
+        );
+
+        assert_eq!(path, "datainpulse[1].account");
+
+        // the same name on another provider is not a duplicate.
+        ProxierConfig::parse(
+            r#"
+            [[webshare]]
+            account = "main"
+
+            [[datainpulse]]
+            account = "main"
+            "#,
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn invalid_base_url_is_reported_at_the_account() {
+        assert_eq!(
+            error_path(
+                r#"
+                [[evomi]]
+
+                [[evomi]]
+                account = "second"
+                base_url = "not a url"
+                "#
+            ),
+            "evomi[1].base_url"
+        );
+        assert_eq!(
+            error_path(
+                r#"
+                [[webshare]]
+                base_url = "ftp://proxy.webshare.io"
+                "#
+            ),
+            "webshare[0].base_url"
+        );
+        assert_eq!(
+            error_path(
+                r#"
+                [[resolvers]]
+                type = "aws"
+                base_url = "metadata"
+                "#
+            ),
+            "resolvers[0].base_url"
+        );
+    }
+
+    #[test]
+    fn quorum_above_the_resolvers_is_reported() {
+        assert_eq!(
+            error_path(
+                r#"
+                resolver_quorum = 3
+
+                [[resolvers]]
+                type = "opendns"
+
+                [[resolvers]]
+                type = "google"
+                "#
+            ),
+            "resolver_quorum"
+        );
+
+        ProxierConfig::parse(
+            r#"
+            resolver_quorum = 2
+
+            [[resolvers]]
+            type = "opendns"
+
+            [[resolvers]]
+            type = "google"
+            "#,
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn empty_residential_user_hash_is_reported() {
+        assert_eq!(
+            error_path(
+                r#"
+                [[iproyale]]
+                residential_user_hash = "hash"
+
+                [[iproyale]]
+                account = "second"
+                residential_user_hash = ""
+                "#
+            ),
+            "iproyale[1].residential_user_hash"
+        );
+    }
+
+    #[test]
+    fn formats_read_the_same_configuration() {
+        let toml = r#"
+            server_ip = "203.0.113.10"
+            server_ips = ["198.51.100.0/24"]
+            deadline_secs = 30
+            resolver_quorum = 1
+
+            [[resolvers]]
+            type = "http"
+            url = "https://api.ipify.org"
+
+            [[webshare]]
+            account = "main"
+            credentials = { source = "env", keys = { PROXY_SHARE_PASSWORD = "WEBSHARE_TOKEN" } }
+
+            [[iproyale]]
+            residential_user_hash = "hash"
+            port = 12321
+        "#;
+        let yaml = r#"
+            server_ip: "203.0.113.10"
+            server_ips: ["198.51.100.0/24"]
+            deadline_secs: 30
+            resolver_quorum: 1
+            resolvers:
+              - type: http
+                url: "https://api.ipify.org"
+            webshare:
+              - account: main
+                credentials:
+                  source: env
+                  keys:
+                    PROXY_SHARE_PASSWORD: WEBSHARE_TOKEN
+            iproyale:
+              - residential_user_hash: hash
+                port: 12321
+        "#;
+        let json = r#"{
+            "server_ip": "203.0.113.10",
+            "server_ips": ["198.51.100.0/24"],
+            "deadline_secs": 30,
+            "resolver_quorum": 1,
+            "resolvers": [{ "type": "http", "url": "https://api.ipify.org" }],
+            "webshare": [{
+                "account": "main",
+                "credentials": { "source": "env", "keys": { "PROXY_SHARE_PASSWORD": "WEBSHARE_TOKEN" } }
+            }],
+            "iproyale": [{ "residential_user_hash": "hash", "port": 12321 }]
+        }"#;
+
+        let dir = std::env::temp_dir().join(format!("proxier-config-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let configs: Vec<String> = [("toml", toml), ("yaml", yaml), ("json", json)]
+            .into_iter()
+            .map(|(extension, contents)| {
+                let path = dir.join(format!("proxier.{}", extension));
+                std::fs::write(&path, contents).unwrap();
+                format!("{:?}", ProxierConfig::from_file(&path).unwrap())
+            })
+            .collect();
+
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(configs[0].contains("WEBSHARE_TOKEN"), "{}", configs[0]);
+        assert_eq!(configs[0], configs[1]);
+        assert_eq!(configs[0], configs[2]);
+    }
+}
@@ -0,0 +1,208 @@
+use super::{ProxierError, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A source for the provider credentials.
+pub trait CredentialSource: std::fmt::Debug + Send + Sync {
+    /// Get the credential for the key.
+    fn get(&self, key: &str) -> Option<String>;
+}
+
+/// Read the credentials from the process environment.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct EnvCredentials;
+
+impl CredentialSource for EnvCredentials {
+    fn get(&self, key: &str) -> Option<String> {
+        std::env::var(key).ok()
+    }
+}
+
+/// Read the credentials from the process environment or a `.env` file. This is the default.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct DotenvCredentials;
+
+impl CredentialSource for DotenvCredentials {
+    fn get(&self, key: &str) -> Option<String> {
+        dotenv::var(key).ok()
+    }
+}
+
+/// Read the credentials from files named after the key in a directory, like docker or kubernetes secret mounts.
+#[derive(Clone, Debug)]
+pub struct FileCredentials {
+    /// The directory holding the secret files.
+    pub dir: PathBuf,
+}
+
+impl FileCredentials {
+    /// Read the credentials from the directory.
+    pub fn new(dir: impl Into<PathBuf>) -> FileCredentials {
+        FileCredentials { dir: dir.into() }
+    }
+}
+
+impl Default for FileCredentials {
+    fn default() -> Self {
+        FileCredentials::new("/run/secrets")
+    }
+}
+
+impl CredentialSource for FileCredentials {
+    fn get(&self, key: &str) -> Option<String> {
+        std::fs::read_to_string(self.dir.join(key))
+            .ok()
+            .map(|value| value.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Credentials set explicitly in code.
+#[derive(Default, Clone)]
+pub struct StaticCredentials(HashMap<String, String>);
+
+impl StaticCredentials {
+    /// A new empty set of credentials.
+    pub fn new() -> StaticCredentials {
+        StaticCredentials::default()
+    }
+
+    /// Set the credential for the key.
+    pub fn with(mut self, key: &str, value: &str) -> StaticCredentials {
+        self.0.insert(key.into(), value.into());
+        self
+    }
+}
+
+impl std::fmt::Debug for StaticCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // keep the secrets out of the logs.
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+impl CredentialSource for StaticCredentials {
+    fn get(&self, key: &str) -> Option<String> {
+        self.0.get(key).cloned()
+    }
+}
+
+/// Read the credentials from another source under different keys, like `WEBSHARE_PREMIUM_TOKEN` for `PROXY_SHARE_PASSWORD`.
+#[derive(Clone, Debug)]
+pub struct MappedCredentials {
+    /// The source to read from.
+    pub source: Arc<dyn CredentialSource>,
+    /// The key names in the source by the provider key.
+    pub keys: HashMap<String, String>,
+}
+
+impl MappedCredentials {
+    /// Map the keys of the source.
+    pub fn new(source: Arc<dyn CredentialSource>) -> MappedCredentials {
+        MappedCredentials {
+            source,
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Read the provider key from the source key.
+    pub fn with(mut self, key: &str, source_key: &str) -> MappedCredentials {
+        self.keys.insert(key.into(), source_key.into());
+        self
+    }
+}
+
+impl CredentialSource for MappedCredentials {
+    fn get(&self, key: &str) -> Option<String> {
+        self.source
+            .get(self.keys.get(key).map_or(key, |key| key.as_str()))
+    }
+}
+
+/// The credential source of a configuration or the default dotenv source.
+pub(crate) fn or_default(credentials: &Option<Arc<dyn CredentialSource>>) -> &dyn CredentialSource {
+    match credentials {
+        Some(credentials) => credentials.as_ref(),
+        _ => &DotenvCredentials,
+    }
+}
+
+/// Get a required credential from the source.
+pub(crate) fn require(credentials: &dyn CredentialSource, key: &str) -> Result<String> {
+    match credentials.get(key) {
+        Some(value) if !value.is_empty() => Ok(value),
+        _ => Err(ProxierError::MissingCredentials(key.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_credentials_read_the_key_file() {
+        let dir = std::env::temp_dir().join(format!("proxier-credentials-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("PROXY_SHARE_PASSWORD"), "secret\r\n").unwrap();
+        std::fs::write(dir.join("EMPTY"), "").unwrap();
+
+        let credentials = FileCredentials::new(&dir);
+
+        assert_eq!(
+            credentials.get("PROXY_SHARE_PASSWORD").as_deref(),
+            Some("secret")
+        );
+        assert_eq!(credentials.get("MISSING"), None);
+        assert!(matches!(
+            require(&credentials, "EMPTY"),
+            Err(ProxierError::MissingCredentials(key)) if key == "EMPTY"
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn file_credentials_without_the_directory_are_missing() {
+        let credentials = FileCredentials::new(std::env::temp_dir().join("proxier-no-such-dir"));
+
+        assert_eq!(credentials.get("PROXY_SHARE_PASSWORD"), None);
+        assert!(matches!(
+            require(&credentials, "PROXY_SHARE_PASSWORD"),
+            Err(ProxierError::MissingCredentials(key)) if key == "PROXY_SHARE_PASSWORD"
+        ));
+    }
+
+    #[test]
+    fn mapped_credentials_read_the_source_key() {
+        let source = StaticCredentials::new()
+            .with("WEBSHARE_PREMIUM_TOKEN", "premium")
+            .with("PROXY_SHARE_PASSWORD", "default")
+            .with("IPROYALE_TOKEN", "iproyale");
+        let credentials = MappedCredentials::new(Arc::new(source))
+            .with("PROXY_SHARE_PASSWORD", "WEBSHARE_PREMIUM_TOKEN");
+
+        // a mapped key reads the source key only, unmapped keys read through.
+        assert_eq!(
+            credentials.get("PROXY_SHARE_PASSWORD").as_deref(),
+            Some("premium")
+        );
+        assert_eq!(
+            credentials.get("IPROYALE_TOKEN").as_deref(),
+            Some("iproyale")
+        );
+        assert_eq!(credentials.get("MISSING"), None);
+    }
+
+    #[test]
+    fn mapped_credentials_to_a_missing_key_are_missing() {
+        let source = StaticCredentials::new().with("PROXY_SHARE_PASSWORD", "default");
+        let credentials = MappedCredentials::new(Arc::new(source))
+            .with("PROXY_SHARE_PASSWORD", "WEBSHARE_PREMIUM_TOKEN");
+
+        assert!(matches!(
+            require(&credentials, "PROXY_SHARE_PASSWORD"),
+            Err(ProxierError::MissingCredentials(key)) if key == "PROXY_SHARE_PASSWORD"
+        ));
+    }
+}
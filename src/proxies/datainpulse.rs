@@ -1,69 +1,278 @@
+use super::credentials::{self, CredentialSource};
+use super::provider::{removed, same_ip};
+use super::{
+    DatainpulseConfiguration, DelistStatus, ProxierError, ProxyEntry, ProxyProvider, Result,
+    WhitelistStatus, DEFAULT_ACCOUNT,
+};
+use async_trait::async_trait;
 use reqwest::Client;
-use std::env;
+use std::net::IpAddr;
+
+/// The datainpulse api base url.
+pub const DEFAULT_BASE_URL: &str = "https://gw.dataimpulse.com:777";
+
+/// The credential key for the datainpulse user name.
+pub const USERNAME_KEY: &str = "DATA_INPULSE_USERNAME";
+/// The credential key for the datainpulse password.
+pub const PASSWORD_KEY: &str = "DATA_INPULSE_PASSWORD";
 
 /// Get the user name password.
-pub fn get_user_name_password() -> (String, String) {
-    (
-        env::var("DATA_INPULSE_USERNAME").unwrap_or_default(),
-        env::var("DATA_INPULSE_PASSWORD").unwrap_or_default(),
-    )
+pub fn get_user_name_password(credentials: &dyn CredentialSource) -> Result<(String, String)> {
+    let username = credentials::require(credentials, USERNAME_KEY)?;
+    let password = credentials::require(credentials, PASSWORD_KEY)?;
+
+    Ok((username, password))
+}
+
+/// A whitelisted ip on datainpulse.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct WhitelistIp {
+    /// The whitelisted ip.
+    pub ip: String,
+    /// The created at date.
+    #[serde(default)]
+    pub created_at: Option<String>,
+}
+
+/// The response when adding or removing a whitelisted ip.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct WhitelistResponse {
+    /// The call succeeded. Not every response reports it.
+    #[serde(default)]
+    pub success: Option<bool>,
+    /// The ip of the call.
+    #[serde(default)]
+    pub ip: Option<String>,
+    /// The message of the call.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// The whitelisted ips of the account.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct WhitelistIps {
+    /// The call succeeded. Not every response reports it.
+    #[serde(default)]
+    pub success: Option<bool>,
+    /// The whitelisted ips.
+    #[serde(default)]
+    pub ips: Vec<WhitelistIp>,
+}
+
+/// Read the successful add or remove response into the whitelisted ip.
+/// Only a body reporting `success: false` fails, the response may have no body at all.
+async fn whitelist_ip(response: reqwest::Response, ip: &str) -> Result<WhitelistIp> {
+    let status = response.status();
+    let body: WhitelistResponse = serde_json::from_str(&response.text().await?).unwrap_or_default();
+
+    match body.success {
+        Some(false) => Err(ProxierError::Status {
+            status,
+            body: body.message.unwrap_or_default(),
+        }),
+        _ => Ok(WhitelistIp {
+            ip: body.ip.unwrap_or_else(|| ip.into()),
+            created_at: None,
+        }),
+    }
+}
+
+/// List the whitelisted ips of the account.
+pub async fn list_whitelist_ips(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+) -> Result<Vec<WhitelistIp>> {
+    let url = format!("{}/api/whitelist_ip", base_url);
+    let (username, password) = get_user_name_password(credentials)?;
+
+    let response = client
+        .get(&url)
+        .basic_auth(username, Some(password))
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        let ips: WhitelistIps = response.json().await?;
+        Ok(ips.ips)
+    } else {
+        Err(ProxierError::from_response(response).await)
+    }
 }
 
 /// Create a new whitelist entry
-pub async fn create_whitelist_entry(client: &Client, ip: &str) {
-    let proxy_whitelist_url = format!("https://gw.dataimpulse.com:777/api/whitelist_ip/{}", ip);
+pub async fn create_whitelist_entry(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    ip: &str,
+) -> Result<WhitelistIp> {
+    super::ip::parse(ip)?;
 
-    let (username, password) = get_user_name_password();
+    let proxy_whitelist_url = format!("{}/api/whitelist_ip/{}", base_url, ip);
+    let (username, password) = get_user_name_password(credentials)?;
 
-    match client
+    let response = client
         .post(&proxy_whitelist_url)
         .basic_auth(username, Some(password))
         .send()
-        .await
-    {
-        Ok(response) => {
-            if response.status().is_success() {
-                let value: serde_json::Value = response.json().await.unwrap_or_default();
-                println!("Whitelisting datainpulse: {:?}", value);
-            } else {
-                if response.status() == 409 {
-                    // attempt to get the whitelist entry.
-                    println!("Failed to create whitelist entry: {}", response.status());
-                    Default::default()
-                } else {
-                    println!(
-                        "CRITICAL: Failed to create whitelist entry: {}",
-                        response.status()
-                    );
-                }
-            }
-        }
-        Err(err) => {
-            println!("Request error: {:?}", err);
-        }
+        .await?;
+
+    if response.status().is_success() {
+        whitelist_ip(response, ip).await
+    } else if response.status() == 409 {
+        Err(ProxierError::conflict(response).await)
+    } else {
+        Err(ProxierError::from_response(response).await)
     }
 }
 
 /// Delete the whitelist entry
-pub async fn delete_whitelist_entry(client: &Client, ip: &str) {
-    let url = format!("https://gw.dataimpulse.com:777/api/whitelist_ip/{}", ip);
-    let (username, password) = get_user_name_password();
+pub async fn delete_whitelist_entry(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    ip: &str,
+) -> Result<WhitelistIp> {
+    let url = format!("{}/api/whitelist_ip/{}", base_url, ip);
+    let (username, password) = get_user_name_password(credentials)?;
 
-    match client
+    let response = client
         .delete(&url)
         .basic_auth(username, Some(password))
         .send()
-        .await
-    {
-        Ok(response) => {
-            if response.status().is_success() {
-                println!("Whitelist entry deleted successfully.");
-            } else {
-                println!("Failed to delete whitelist entry: {}", response.status());
-            }
-        }
-        Err(err) => {
-            println!("Request error: {:?}", err);
+        .await?;
+
+    if response.status().is_success() {
+        whitelist_ip(response, ip).await
+    } else {
+        Err(ProxierError::from_response(response).await)
+    }
+}
+
+impl DatainpulseConfiguration {
+    /// The api base url to use.
+    pub fn api_url(&self) -> &str {
+        self.base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+    }
+
+    /// The credential source to use.
+    pub fn credentials(&self) -> &dyn CredentialSource {
+        credentials::or_default(&self.credentials)
+    }
+
+    /// Find the whitelisted ip on the account.
+    async fn find_entry(&self, client: &Client, ip: &str) -> Result<Option<WhitelistIp>> {
+        Ok(
+            list_whitelist_ips(client, self.api_url(), self.credentials())
+                .await?
+                .into_iter()
+                .find(|entry| entry.ip == ip),
+        )
+    }
+}
+
+#[async_trait]
+impl ProxyProvider for DatainpulseConfiguration {
+    fn name(&self) -> &str {
+        "datainpulse"
+    }
+
+    fn account(&self) -> &str {
+        self.account.as_deref().unwrap_or(DEFAULT_ACCOUNT)
+    }
+
+    fn supports_ipv6(&self) -> bool {
+        false
+    }
+
+    async fn whitelist(&mut self, client: &Client, addr: IpAddr) -> Result<WhitelistStatus> {
+        let ip = addr.to_string();
+        let ip = ip.as_str();
+
+        let (entry, status) = match self.find_entry(client, ip).await? {
+            Some(entry) => (Some(entry), WhitelistStatus::AlreadyPresent),
+            _ => match create_whitelist_entry(client, self.api_url(), self.credentials(), ip).await
+            {
+                Ok(entry) => (Some(entry), WhitelistStatus::Created),
+                // a 409 means the ip was added since the lookup, adopt it when it is listed now.
+                Err(err) if err.is_conflict() => match self.find_entry(client, ip).await? {
+                    Some(entry) => (Some(entry), WhitelistStatus::AlreadyPresent),
+                    _ => return Err(err),
+                },
+                Err(err) => return Err(err),
+            },
+        };
+
+        self.whitelist_entries
+            .retain(|tracked| !same_ip(&tracked.ip, addr));
+        self.whitelist_entries.extend(entry);
+
+        Ok(status)
+    }
+
+    async fn delist(&mut self, client: &Client, addr: IpAddr) -> Result<DelistStatus> {
+        let ip = addr.to_string();
+        let ip = ip.as_str();
+
+        if !self
+            .whitelist_entries
+            .iter()
+            .any(|tracked| same_ip(&tracked.ip, addr))
+        {
+            return Ok(DelistStatus::NotPresent);
         }
+
+        // an ip no longer listed is reported as not present instead of failing the delete.
+        let status = if self.find_entry(client, ip).await?.is_some() {
+            delete_whitelist_entry(client, self.api_url(), self.credentials(), ip).await?;
+            DelistStatus::Removed
+        } else {
+            DelistStatus::NotPresent
+        };
+
+        self.whitelist_entries
+            .retain(|tracked| !same_ip(&tracked.ip, addr));
+
+        Ok(status)
+    }
+
+    async fn list(&self, client: &Client) -> Result<Vec<ProxyEntry>> {
+        Ok(
+            list_whitelist_ips(client, self.api_url(), self.credentials())
+                .await?
+                .into_iter()
+                .map(|entry| ProxyEntry {
+                    ip: entry.ip,
+                    id: None,
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    fn entries(&self) -> Vec<ProxyEntry> {
+        self.whitelist_entries
+            .iter()
+            .map(|entry| ProxyEntry {
+                ip: entry.ip.clone(),
+                id: None,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    async fn remove(&mut self, client: &Client, entry: &ProxyEntry) -> Result<DelistStatus> {
+        let status = removed(
+            delete_whitelist_entry(client, self.api_url(), self.credentials(), &entry.ip).await,
+        )?;
+
+        self.whitelist_entries
+            .retain(|tracked| tracked.ip != entry.ip);
+
+        Ok(status)
     }
 }
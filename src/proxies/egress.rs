@@ -0,0 +1,277 @@
+use super::ip::{self, IpNet};
+use super::{HttpEchoResolver, IpResolvers, Proxier, ProxierError, Result};
+use futures::future::join_all;
+use reqwest::Client;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// The default timeout for resolving the egress of a local address.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// An address of a local network interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalAddr {
+    /// The name of the interface.
+    pub interface: String,
+    /// The address on the interface.
+    pub ip: IpAddr,
+}
+
+/// How the traffic from a local address reaches the internet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EgressKind {
+    /// The local address is public and egresses as itself.
+    Public,
+    /// The local address is translated by a nat to another public ip.
+    Nat,
+    /// The local address is in the carrier grade nat space, so the public ip is shared with other customers.
+    Cgnat,
+    /// None of the resolvers answered from the local address.
+    Unreachable,
+}
+
+/// The egress of a local address.
+#[derive(Debug)]
+pub struct EgressAddr {
+    /// The interface and address the traffic was sent from.
+    pub local: LocalAddr,
+    /// The public ip the resolvers saw.
+    pub public: Option<IpAddr>,
+    /// How the address reaches the internet.
+    pub kind: EgressKind,
+    /// The error resolving the public ip when the address is unreachable.
+    pub error: Option<ProxierError>,
+}
+
+/// The egress of every local address.
+#[derive(Debug, Default)]
+pub struct EgressReport {
+    /// The egress for each local address in interface order.
+    pub addrs: Vec<EgressAddr>,
+}
+
+impl EgressReport {
+    /// The distinct public ips the local addresses egress from.
+    pub fn ips(&self) -> Vec<IpAddr> {
+        let mut ips: Vec<IpAddr> = Vec::new();
+
+        for ip in self.addrs.iter().filter_map(|addr| addr.public) {
+            if ip::is_public(ip) && !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+
+        ips
+    }
+
+    /// The local addresses behind a nat or carrier grade nat.
+    pub fn translated(&self) -> impl Iterator<Item = &EgressAddr> {
+        self.addrs
+            .iter()
+            .filter(|addr| matches!(addr.kind, EgressKind::Nat | EgressKind::Cgnat))
+    }
+
+    /// The local addresses none of the resolvers answered from.
+    pub fn unreachable(&self) -> impl Iterator<Item = &EgressAddr> {
+        self.addrs
+            .iter()
+            .filter(|addr| addr.kind == EgressKind::Unreachable)
+    }
+}
+
+/// The local interface addresses that can egress, skipping the loopback and link local ones.
+pub fn local_addrs() -> Result<Vec<LocalAddr>> {
+    Ok(if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter(|interface| !interface.is_loopback() && !is_link_local(interface.ip()))
+        .map(|interface| LocalAddr {
+            ip: interface.ip(),
+            interface: interface.name,
+        })
+        .collect())
+}
+
+/// The address is only valid on its link.
+fn is_link_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+/// Classify the egress of the local address by the public ip the resolvers saw.
+pub fn classify(local: IpAddr, public: Option<IpAddr>) -> EgressKind {
+    match public {
+        None => EgressKind::Unreachable,
+        Some(public) if public == local => EgressKind::Public,
+        _ if ip::is_cgnat(local) => EgressKind::Cgnat,
+        _ => EgressKind::Nat,
+    }
+}
+
+/// Resolve the egress of every local address concurrently, sending from each address with a bound client.
+pub async fn discover(resolvers: &IpResolvers, timeout: Duration) -> Result<EgressReport> {
+    let addrs = local_addrs()?;
+
+    let calls = addrs.into_iter().map(|local| async move {
+        let resolved = match Client::builder()
+            .local_address(local.ip)
+            .timeout(timeout)
+            .build()
+        {
+            Ok(client) => resolvers.resolve_from(&client, local.ip).await,
+            Err(err) => Err(err.into()),
+        };
+
+        let (public, error) = match resolved {
+            Ok(public) => (Some(public), None),
+            Err(err) => (None, Some(err)),
+        };
+
+        EgressAddr {
+            kind: classify(local.ip, public),
+            local,
+            public,
+            error,
+        }
+    });
+
+    Ok(EgressReport {
+        addrs: join_all(calls).await,
+    })
+}
+
+impl Proxier {
+    /// Discover the public ips the local interface addresses egress from and whitelist all of them as server ips.
+    /// Uses the resolvers or the ip echo service.
+    pub async fn discover_egress(&mut self) -> Result<EgressReport> {
+        let echo;
+        let resolvers = match self.resolvers.as_ref() {
+            Some(resolvers) => resolvers,
+            _ => {
+                echo = IpResolvers::new().with(
+                    self.ip_echo_url
+                        .as_deref()
+                        .map(HttpEchoResolver::new)
+                        .unwrap_or_default(),
+                );
+                &echo
+            }
+        };
+
+        let report = discover(resolvers, DEFAULT_TIMEOUT).await?;
+
+        for ip in report.ips() {
+            if self.server_ip != Some(ip) && !self.server_ips.contains(&IpNet::from(ip)) {
+                self.server_ips.push(IpNet::from(ip));
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn egress(local: &str, public: Option<&str>) -> EgressAddr {
+        let local = ip(local);
+        let public = public.map(ip);
+
+        EgressAddr {
+            local: LocalAddr {
+                interface: "eth0".into(),
+                ip: local,
+            },
+            public,
+            kind: classify(local, public),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn classify_by_the_public_ip_seen() {
+        // the address egresses as itself only when the resolvers saw it.
+        assert_eq!(
+            classify(ip("203.0.113.10"), Some(ip("203.0.113.10"))),
+            EgressKind::Public
+        );
+        assert_eq!(
+            classify(ip("2001:db8::10"), Some(ip("2001:db8::10"))),
+            EgressKind::Public
+        );
+        // a public address mapped to another public ip is still behind a nat.
+        assert_eq!(
+            classify(ip("203.0.113.10"), Some(ip("198.51.100.7"))),
+            EgressKind::Nat
+        );
+        assert_eq!(
+            classify(ip("10.0.0.5"), Some(ip("203.0.113.10"))),
+            EgressKind::Nat
+        );
+        assert_eq!(
+            classify(ip("169.254.10.1"), Some(ip("203.0.113.10"))),
+            EgressKind::Nat
+        );
+        assert_eq!(
+            classify(ip("127.0.0.1"), Some(ip("203.0.113.10"))),
+            EgressKind::Nat
+        );
+        assert_eq!(
+            classify(ip("100.64.12.1"), Some(ip("203.0.113.10"))),
+            EgressKind::Cgnat
+        );
+
+        for local in [
+            "203.0.113.10",
+            "10.0.0.5",
+            "169.254.10.1",
+            "127.0.0.1",
+            "::1",
+        ] {
+            assert_eq!(
+                classify(ip(local), None),
+                EgressKind::Unreachable,
+                "{}",
+                local
+            );
+        }
+    }
+
+    #[test]
+    fn ips_are_the_distinct_public_ips() {
+        let report = EgressReport {
+            addrs: vec![
+                egress("10.0.0.5", Some("203.0.113.10")),
+                egress("10.0.1.5", Some("203.0.113.10")),
+                egress("198.51.100.7", Some("198.51.100.7")),
+                egress("100.64.12.1", Some("198.51.100.99")),
+                egress("192.168.1.20", None),
+                // a resolver answering with a non public ip is not an egress ip.
+                egress("172.16.0.5", Some("172.16.0.1")),
+                egress("169.254.10.1", Some("127.0.0.1")),
+            ],
+        };
+
+        assert_eq!(
+            report.ips(),
+            [ip("203.0.113.10"), ip("198.51.100.7"), ip("198.51.100.99")]
+        );
+        assert_eq!(report.translated().count(), 5);
+        assert_eq!(report.unreachable().count(), 1);
+        assert!(EgressReport::default().ips().is_empty());
+    }
+
+    #[test]
+    fn link_local_addresses_are_detected() {
+        assert!(is_link_local(ip("169.254.10.1")));
+        assert!(is_link_local(ip("fe80::1")));
+        assert!(!is_link_local(ip("10.0.0.5")));
+        assert!(!is_link_local(ip("2001:db8::1")));
+    }
+}
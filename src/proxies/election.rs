@@ -0,0 +1,627 @@
+use super::ledger::update_locked;
+use super::redis::{Connection, Reply};
+use super::resolver::random_u64;
+use super::{ProviderOutcome, ProviderReport, Proxier, ProxierError, PruneReport, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The default time a leader holds the lease and its published state is followed.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+/// The default interval the followers check for the published state.
+pub const DEFAULT_POLL: Duration = Duration::from_millis(500);
+/// The default timeout for the redis commands.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+/// The default prefix of the redis keys.
+pub const DEFAULT_PREFIX: &str = "proxier:election:";
+
+/// A store shared by the replicas electing the one that performs a call on the providers.
+/// Each key has a lease held by a single replica and the state the last leader published.
+#[async_trait]
+pub trait LeaseStore: std::fmt::Debug + Send + Sync {
+    /// Take the lease of the key when it is free or expired, or extend it when the holder has it. Returns whether the holder leads.
+    async fn acquire(&self, key: &str, holder: &str, ttl: Duration) -> Result<bool>;
+    /// Give up the lease of the key when the holder has it.
+    async fn release(&self, key: &str, holder: &str) -> Result<()>;
+    /// Publish the state of the key for the followers until the ttl passes.
+    async fn publish(&self, key: &str, state: &str, ttl: Duration) -> Result<()>;
+    /// The state published for the key when it has not expired.
+    async fn state(&self, key: &str) -> Result<Option<String>>;
+    /// Drop the state published for the key, so the next call elects a leader again.
+    async fn clear(&self, key: &str) -> Result<()>;
+}
+
+/// The lease store of a proxier with the id this replica leads as.
+#[derive(Clone, Debug)]
+pub struct Election {
+    /// The shared store.
+    pub store: Arc<dyn LeaseStore>,
+    /// The id of this replica.
+    pub holder: String,
+    /// How long a leader holds the lease and its state is followed. Keep it above the time a call takes, or a follower takes over.
+    pub ttl: Duration,
+    /// How often the followers check for the published state.
+    pub poll: Duration,
+}
+
+impl Election {
+    /// An election in the store as the holder.
+    pub fn new(store: impl LeaseStore + 'static, holder: &str) -> Election {
+        Election {
+            store: Arc::new(store),
+            holder: holder.into(),
+            ttl: DEFAULT_TTL,
+            poll: DEFAULT_POLL,
+        }
+    }
+
+    /// An election in the store as a random holder.
+    pub fn random(store: impl LeaseStore + 'static) -> Election {
+        Election::new(store, &format!("{:016x}", random_u64()))
+    }
+
+    /// Wait until this replica leads the key or another replica published its state, within the deadline.
+    /// Returns the published state to follow, `Leader` when this replica leads, or `TimedOut` when the deadline passes first.
+    async fn elect(&self, key: String, deadline: Option<Duration>) -> Result<Role> {
+        let deadline = deadline.map(|deadline| tokio::time::Instant::now() + deadline);
+
+        loop {
+            if let Some(state) = self.store.state(&key).await? {
+                return Ok(Role::Follower(serde_json::from_str(&state)?));
+            }
+
+            if self.store.acquire(&key, &self.holder, self.ttl).await? {
+                // a leader may have published and released between the check and the acquire.
+                return match self.store.state(&key).await? {
+                    Some(state) => {
+                        self.store.release(&key, &self.holder).await?;
+                        Ok(Role::Follower(serde_json::from_str(&state)?))
+                    }
+                    _ => Ok(Role::Leader(key)),
+                };
+            }
+
+            // the store calls are not cut short, so a lease is never taken without being reported.
+            let poll = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+                    if left.is_zero() {
+                        return Ok(Role::TimedOut(ProxierError::Election(format!(
+                            "timed out waiting for the leader of {}",
+                            key
+                        ))));
+                    }
+                    left.min(self.poll)
+                }
+                _ => self.poll,
+            };
+
+            tokio::time::sleep(poll).await;
+        }
+    }
+
+    /// Publish the outcome for the followers and give up the lease.
+    async fn publish(&self, key: &str, providers: Vec<PublishedProvider>) -> Result<()> {
+        let published = Published {
+            leader: self.holder.clone(),
+            providers,
+        };
+
+        self.store
+            .publish(key, &serde_json::to_string(&published)?, self.ttl)
+            .await?;
+        self.store.release(key, &self.holder).await
+    }
+}
+
+/// The outcome the leader publishes for the followers.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct Published {
+    leader: String,
+    providers: Vec<PublishedProvider>,
+}
+
+/// The outcome of the leader on a provider account.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct PublishedProvider {
+    provider: String,
+    account: String,
+    #[serde(default)]
+    ip: Option<String>,
+    /// The failure of the leader, `None` when it succeeded.
+    #[serde(default)]
+    error: Option<String>,
+}
+
+impl Published {
+    /// The holder of the leader that published.
+    pub(crate) fn leader(&self) -> &str {
+        &self.leader
+    }
+
+    /// The outcomes of the leader as seen by a follower.
+    pub(crate) fn providers(&self) -> Vec<ProviderReport> {
+        self.providers
+            .iter()
+            .map(|published| ProviderReport {
+                provider: published.provider.clone(),
+                account: published.account.clone(),
+                ip: published.ip.clone(),
+                outcome: match &published.error {
+                    Some(error) => ProviderOutcome::Failed(ProxierError::Election(format!(
+                        "the leader {} failed: {}",
+                        self.leader, error
+                    ))),
+                    _ => ProviderOutcome::Followed(self.leader.clone()),
+                },
+            })
+            .collect()
+    }
+}
+
+/// The failure of an outcome to publish.
+fn failure(outcome: &ProviderOutcome) -> Option<String> {
+    match outcome {
+        ProviderOutcome::Failed(err) => Some(err.to_string()),
+        ProviderOutcome::TimedOut => Some("timed out".into()),
+        _ => None,
+    }
+}
+
+/// The provider reports to publish.
+pub(crate) fn published(reports: &[ProviderReport]) -> Vec<PublishedProvider> {
+    reports
+        .iter()
+        .map(|report| PublishedProvider {
+            provider: report.provider.clone(),
+            account: report.account.clone(),
+            ip: report.ip.clone(),
+            error: failure(&report.outcome),
+        })
+        .collect()
+}
+
+/// The role of this replica in a call.
+pub(crate) enum Role {
+    /// There is no election, every replica calls the providers.
+    Alone,
+    /// This replica leads the key and publishes its outcome.
+    Leader(String),
+    /// Another replica led and published its outcome.
+    Follower(Published),
+    /// The election failed and this replica calls the providers.
+    Failed(ProxierError),
+    /// The deadline passed waiting for the leader, so no replica's outcome is known.
+    TimedOut(ProxierError),
+}
+
+impl Proxier {
+    /// The election and the key of the call on the server ips, so replicas behind the same nat elect one leader.
+    /// An invalid set of server ips is not elected, the call itself reports it.
+    fn election_key(&self, call: &str) -> Option<(&Election, String)> {
+        let election = self.election.as_ref()?;
+        let ips = self.public_server_ips().ok()?;
+        let ips: Vec<String> = ips.iter().map(ToString::to_string).collect();
+
+        Some((election, format!("{}:{}", call, ips.join(","))))
+    }
+
+    /// Elect the replica performing the call on the server ips.
+    pub(crate) async fn elect(&self, call: &str) -> Role {
+        let (election, key) = match self.election_key(call) {
+            Some(election) => election,
+            _ => return Role::Alone,
+        };
+
+        election
+            .elect(key, self.deadline)
+            .await
+            .unwrap_or_else(Role::Failed)
+    }
+
+    /// Publish the outcome when leading. Returns the error of the election.
+    pub(crate) async fn lead(
+        &self,
+        role: Role,
+        providers: Vec<PublishedProvider>,
+    ) -> Option<ProxierError> {
+        match (role, self.election.as_ref()) {
+            (Role::Leader(key), Some(election)) => election.publish(&key, providers).await.err(),
+            (Role::Failed(err) | Role::TimedOut(err), _) => Some(err),
+            _ => None,
+        }
+    }
+
+    /// Give up the lead without publishing, so the next replica to elect calls the providers again.
+    pub(crate) async fn abandon(&self, key: &str) -> Option<ProxierError> {
+        let election = self.election.as_ref()?;
+
+        election.store.release(key, &election.holder).await.err()
+    }
+
+    /// Drop the outcome published for the call. Returns the error of the election.
+    pub(crate) async fn forget(&self, call: &str) -> Option<ProxierError> {
+        let (election, key) = self.election_key(call)?;
+
+        election.store.clear(&key).await.err()
+    }
+
+    /// Every registered provider timed out waiting for the leader.
+    pub(crate) fn timed_out(&self) -> Vec<ProviderReport> {
+        self.providers
+            .iter()
+            .map(|provider| ProviderReport {
+                provider: provider.name().to_string(),
+                account: provider.account().to_string(),
+                ip: None,
+                outcome: ProviderOutcome::TimedOut,
+            })
+            .collect()
+    }
+
+    /// The prune outcome of every registered provider to publish.
+    pub(crate) fn pruned(&self, report: &PruneReport) -> Vec<PublishedProvider> {
+        self.providers
+            .iter()
+            .map(|provider| {
+                let (name, account) = (provider.name(), provider.account());
+                let listed = report
+                    .providers
+                    .iter()
+                    .find(|report| report.provider == name && report.account == account)
+                    .and_then(|report| failure(&report.outcome));
+                let removed = report
+                    .entries
+                    .iter()
+                    .filter(|pruned| pruned.provider == name && pruned.account == account)
+                    .find_map(|pruned| match &pruned.outcome {
+                        super::PruneOutcome::Failed(err) => {
+                            Some(format!("removing {}: {}", pruned.entry.ip, err))
+                        }
+                        _ => None,
+                    });
+
+                PublishedProvider {
+                    provider: name.to_string(),
+                    account: account.to_string(),
+                    ip: None,
+                    error: listed.or(removed),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The lease and the published state of a key.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+struct Lease {
+    #[serde(default)]
+    holder: Option<String>,
+    /// The unix time in milliseconds the lease expires.
+    #[serde(default)]
+    expires_at: i64,
+    #[serde(default)]
+    state: Option<String>,
+    /// The unix time in milliseconds the state expires.
+    #[serde(default)]
+    state_expires_at: i64,
+}
+
+/// The leases of every key.
+type LeaseMap = BTreeMap<String, Lease>;
+
+/// The current unix time in milliseconds.
+pub(crate) fn now() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// The unix time in milliseconds after the ttl.
+pub(crate) fn after(ttl: Duration) -> i64 {
+    now().saturating_add(i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX))
+}
+
+/// Drop the expired leases and states.
+fn expire(leases: &mut LeaseMap) {
+    let now = now();
+
+    for lease in leases.values_mut() {
+        if lease.expires_at <= now {
+            lease.holder = None;
+        }
+        if lease.state_expires_at <= now {
+            lease.state = None;
+        }
+    }
+
+    leases.retain(|_, lease| lease.holder.is_some() || lease.state.is_some());
+}
+
+/// Take or extend the lease of the key for the holder.
+fn take(leases: &mut LeaseMap, key: &str, holder: &str, ttl: Duration) -> bool {
+    expire(leases);
+    let lease = leases.entry(key.into()).or_default();
+
+    if lease
+        .holder
+        .as_deref()
+        .is_some_and(|current| current != holder)
+    {
+        return false;
+    }
+
+    lease.holder = Some(holder.into());
+    lease.expires_at = after(ttl);
+    true
+}
+
+/// Give up the lease of the key when the holder has it.
+fn give_up(leases: &mut LeaseMap, key: &str, holder: &str) {
+    if let Some(lease) = leases.get_mut(key) {
+        if lease.holder.as_deref() == Some(holder) {
+            lease.holder = None;
+        }
+    }
+
+    expire(leases);
+}
+
+/// Set the published state of the key.
+fn put(leases: &mut LeaseMap, key: &str, state: String, ttl: Duration) {
+    expire(leases);
+    let lease = leases.entry(key.into()).or_default();
+    lease.state = Some(state);
+    lease.state_expires_at = after(ttl);
+}
+
+/// Drop the published state of the key.
+fn unset(leases: &mut LeaseMap, key: &str) {
+    if let Some(lease) = leases.get_mut(key) {
+        lease.state = None;
+    }
+
+    expire(leases);
+}
+
+/// The published state of the key when it has not expired.
+fn get(leases: &LeaseMap, key: &str) -> Option<String> {
+    leases
+        .get(key)
+        .filter(|lease| lease.state_expires_at > now())
+        .and_then(|lease| lease.state.clone())
+}
+
+/// An in process lease store, standing in for a shared store in tests and single host setups.
+/// Clones share the leases.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryLeaseStore {
+    leases: Arc<Mutex<LeaseMap>>,
+}
+
+impl MemoryLeaseStore {
+    /// An empty store.
+    pub fn new() -> MemoryLeaseStore {
+        MemoryLeaseStore::default()
+    }
+
+    /// The holder of the lease of the key when it has not expired.
+    pub fn leader(&self, key: &str) -> Option<String> {
+        self.lock()
+            .get(key)
+            .filter(|lease| lease.expires_at > now())
+            .and_then(|lease| lease.holder.clone())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LeaseMap> {
+        self.leases.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl LeaseStore for MemoryLeaseStore {
+    async fn acquire(&self, key: &str, holder: &str, ttl: Duration) -> Result<bool> {
+        Ok(take(&mut self.lock(), key, holder, ttl))
+    }
+
+    async fn release(&self, key: &str, holder: &str) -> Result<()> {
+        give_up(&mut self.lock(), key, holder);
+        Ok(())
+    }
+
+    async fn publish(&self, key: &str, state: &str, ttl: Duration) -> Result<()> {
+        put(&mut self.lock(), key, state.into(), ttl);
+        Ok(())
+    }
+
+    async fn state(&self, key: &str) -> Result<Option<String>> {
+        Ok(get(&self.lock(), key))
+    }
+
+    async fn clear(&self, key: &str) -> Result<()> {
+        unset(&mut self.lock(), key);
+        Ok(())
+    }
+}
+
+/// A json file of the leases shared by the replicas on a host or a shared volume.
+/// Every change takes an exclusive lock on a `.lock` file next to it.
+#[derive(Clone, Debug)]
+pub struct FileLeaseStore {
+    /// The path of the leases file.
+    path: PathBuf,
+}
+
+impl FileLeaseStore {
+    /// A store at the path. The file is created on the first acquire.
+    pub fn new(path: impl Into<PathBuf>) -> FileLeaseStore {
+        FileLeaseStore { path: path.into() }
+    }
+
+    /// The path of the leases file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read and change the leases under the file lock.
+    async fn locked<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut LeaseMap) -> T + Send + 'static,
+    ) -> Result<T> {
+        let path = self.path.clone();
+
+        tokio::task::spawn_blocking(move || update_locked(&path, f))
+            .await
+            .map_err(|err| ProxierError::Election(err.to_string()))?
+    }
+}
+
+#[async_trait]
+impl LeaseStore for FileLeaseStore {
+    async fn acquire(&self, key: &str, holder: &str, ttl: Duration) -> Result<bool> {
+        let (key, holder) = (key.to_string(), holder.to_string());
+        self.locked(move |leases| take(leases, &key, &holder, ttl))
+            .await
+    }
+
+    async fn release(&self, key: &str, holder: &str) -> Result<()> {
+        let (key, holder) = (key.to_string(), holder.to_string());
+        self.locked(move |leases| give_up(leases, &key, &holder))
+            .await
+    }
+
+    async fn publish(&self, key: &str, state: &str, ttl: Duration) -> Result<()> {
+        let (key, state) = (key.to_string(), state.to_string());
+        self.locked(move |leases| put(leases, &key, state, ttl))
+            .await
+    }
+
+    async fn state(&self, key: &str) -> Result<Option<String>> {
+        let key = key.to_string();
+        self.locked(move |leases| get(leases, &key)).await
+    }
+
+    async fn clear(&self, key: &str) -> Result<()> {
+        let key = key.to_string();
+        self.locked(move |leases| unset(leases, &key)).await
+    }
+}
+
+/// Take the lease when it is free or extend it when the holder has it.
+const ACQUIRE_SCRIPT: &str = "local holder = redis.call('GET', KEYS[1]) \
+if holder == ARGV[1] then redis.call('PEXPIRE', KEYS[1], ARGV[2]) return 1 end \
+if holder then return 0 end \
+redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2]) return 1";
+
+/// Delete the lease when the holder has it.
+const RELEASE_SCRIPT: &str = "if redis.call('GET', KEYS[1]) == ARGV[1] then \
+return redis.call('DEL', KEYS[1]) end return 0";
+
+/// The leases kept as expiring redis keys, shared by the replicas across hosts.
+#[derive(Clone, Debug)]
+pub struct RedisLeaseStore {
+    /// The redis server as `host:port`.
+    pub addr: String,
+    /// The password to authenticate with.
+    pub password: Option<String>,
+    /// The prefix of the keys.
+    pub prefix: String,
+    /// How long to wait for each command.
+    pub timeout: Duration,
+}
+
+impl RedisLeaseStore {
+    /// A store on the redis server.
+    pub fn new(addr: &str) -> RedisLeaseStore {
+        RedisLeaseStore {
+            addr: addr.into(),
+            password: None,
+            prefix: DEFAULT_PREFIX.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// The redis key of the lease or the state of the key.
+    fn key(&self, key: &str, kind: &str) -> String {
+        format!("{}{}:{}", self.prefix, key, kind)
+    }
+
+    /// A connection to the redis server.
+    fn connection(&self) -> Connection<'_> {
+        Connection {
+            addr: &self.addr,
+            password: self.password.as_deref(),
+            timeout: self.timeout,
+            error: ProxierError::Election,
+        }
+    }
+}
+
+#[async_trait]
+impl LeaseStore for RedisLeaseStore {
+    async fn acquire(&self, key: &str, holder: &str, ttl: Duration) -> Result<bool> {
+        let redis = self.connection();
+        let ttl = ttl.as_millis().max(1).to_string();
+
+        match redis
+            .call(&[
+                "EVAL",
+                ACQUIRE_SCRIPT,
+                "1",
+                &self.key(key, "lease"),
+                holder,
+                &ttl,
+            ])
+            .await?
+        {
+            Reply::Integer(leads) => Ok(leads == 1),
+            reply => Err(redis.unexpected(Some(&reply))),
+        }
+    }
+
+    async fn release(&self, key: &str, holder: &str) -> Result<()> {
+        let redis = self.connection();
+
+        match redis
+            .call(&["EVAL", RELEASE_SCRIPT, "1", &self.key(key, "lease"), holder])
+            .await?
+        {
+            Reply::Integer(_) => Ok(()),
+            reply => Err(redis.unexpected(Some(&reply))),
+        }
+    }
+
+    async fn publish(&self, key: &str, state: &str, ttl: Duration) -> Result<()> {
+        let redis = self.connection();
+        let ttl = ttl.as_millis().max(1).to_string();
+
+        match redis
+            .call(&["SET", &self.key(key, "state"), state, "PX", &ttl])
+            .await?
+        {
+            Reply::Status(status) if status == "OK" => Ok(()),
+            reply => Err(redis.unexpected(Some(&reply))),
+        }
+    }
+
+    async fn state(&self, key: &str) -> Result<Option<String>> {
+        let redis = self.connection();
+
+        match redis.call(&["GET", &self.key(key, "state")]).await? {
+            Reply::Bulk(state) => {
+                Ok(state.map(|state| String::from_utf8_lossy(&state).into_owned()))
+            }
+            reply => Err(redis.unexpected(Some(&reply))),
+        }
+    }
+
+    async fn clear(&self, key: &str) -> Result<()> {
+        let redis = self.connection();
+
+        match redis.call(&["DEL", &self.key(key, "state")]).await? {
+            Reply::Integer(_) => Ok(()),
+            reply => Err(redis.unexpected(Some(&reply))),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use reqwest::{Response, StatusCode};
+
+/// The result type for the proxy services.
+pub type Result<T> = std::result::Result<T, ProxierError>;
+
+/// The errors returned by the proxy services.
+#[derive(Debug)]
+pub enum ProxierError {
+    /// The credentials for the service are not set. Holds the missing key.
+    MissingCredentials(String),
+    /// The ip address to whitelist is invalid.
+    InvalidIp(String),
+    /// The request could not be sent or the response could not be read.
+    Transport(reqwest::Error),
+    /// The service responded with an unexpected status.
+    Status {
+        /// The status code of the response.
+        status: StatusCode,
+        /// The body of the response.
+        body: String,
+    },
+    /// The entry already exists on the service.
+    Conflict(String),
+    /// The response body could not be decoded.
+    Decode(String),
+    /// The configuration is invalid.
+    Config {
+        /// The path of the offending key.
+        path: String,
+        /// What is wrong with the key.
+        message: String,
+    },
+    /// The operation is not supported by the provider.
+    Unsupported(String),
+    /// The ledger file could not be read or written.
+    Io(std::io::Error),
+    /// The server ip could not be resolved or the resolvers did not agree.
+    Resolve(String),
+    /// The shared store of the ip holders failed.
+    Holders(String),
+    /// The leader election failed or the leader failed the call.
+    Election(String),
+}
+
+impl ProxierError {
+    /// Build a status error from the response.
+    pub(crate) async fn from_response(response: Response) -> ProxierError {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+
+        ProxierError::Status { status, body }
+    }
+
+    /// Build a conflict error from the response.
+    pub(crate) async fn conflict(response: Response) -> ProxierError {
+        ProxierError::Conflict(response.text().await.unwrap_or_default())
+    }
+
+    /// Build a configuration error for the key.
+    pub(crate) fn config(path: impl Into<String>, message: impl Into<String>) -> ProxierError {
+        ProxierError::Config {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+
+    /// The error is a conflict with an existing entry.
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, ProxierError::Conflict(_))
+    }
+
+    /// The service responded that the entry does not exist.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ProxierError::Status { status, .. } if *status == StatusCode::NOT_FOUND)
+    }
+
+    /// The error is from missing credentials.
+    pub fn is_missing_credentials(&self) -> bool {
+        matches!(self, ProxierError::MissingCredentials(_))
+    }
+}
+
+impl std::fmt::Display for ProxierError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxierError::MissingCredentials(key) => {
+                write!(f, "missing credentials: set {} to enable whitelisting", key)
+            }
+            ProxierError::InvalidIp(ip) => write!(f, "invalid ip address: {:?}", ip),
+            ProxierError::Transport(err) => write!(f, "request error: {}", err),
+            ProxierError::Status { status, body } => {
+                write!(f, "unexpected status {}: {}", status, body)
+            }
+            ProxierError::Conflict(body) => write!(f, "entry already exists: {}", body),
+            ProxierError::Decode(err) => write!(f, "failed to decode the response: {}", err),
+            ProxierError::Config { path, message } if path.is_empty() => {
+                write!(f, "invalid configuration: {}", message)
+            }
+            ProxierError::Config { path, message } => {
+                write!(f, "invalid configuration at {}: {}", path, message)
+            }
+            ProxierError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            ProxierError::Io(err) => write!(f, "io error: {}", err),
+            ProxierError::Resolve(message) => {
+                write!(f, "failed to resolve the server ip: {}", message)
+            }
+            ProxierError::Holders(message) => write!(f, "holder store error: {}", message),
+            ProxierError::Election(message) => write!(f, "leader election error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ProxierError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProxierError::Transport(err) => Some(err),
+            ProxierError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ProxierError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_decode() {
+            ProxierError::Decode(err.to_string())
+        } else {
+            ProxierError::Transport(err)
+        }
+    }
+}
+
+impl From<serde_json::Error> for ProxierError {
+    fn from(err: serde_json::Error) -> Self {
+        ProxierError::Decode(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for ProxierError {
+    fn from(err: std::io::Error) -> Self {
+        ProxierError::Io(err)
+    }
+}
@@ -1,110 +1,382 @@
+use super::credentials::{self, CredentialSource};
+use super::provider::{removed, same_ip};
+use super::{
+    DelistStatus, EvomiConfiguration, ProxierError, ProxyEntry, ProxyProvider, Result,
+    WhitelistStatus, DEFAULT_ACCOUNT,
+};
+use async_trait::async_trait;
 use reqwest::header::{CONTENT_TYPE, COOKIE};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
     Client,
 };
 use serde_json::json;
-use std::env;
+use std::net::IpAddr;
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
-struct ProxtIP {
-    /// The local ip address of the server.
-    ip_address: String,
+/// The evomi api base url.
+pub const DEFAULT_BASE_URL: &str = "https://api.evomi.com";
+
+/// The credential key for the evomi api token.
+pub const API_TOKEN_KEY: &str = "EVOMI_API_TOKEN";
+
+/// The evomi proxy products with their own ip whitelist.
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EvomiProduct {
+    /// Residential proxies.
+    #[default]
+    Residential,
+    /// Mobile proxies.
+    Mobile,
+    /// Datacenter proxies.
+    Datacenter,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
-pub struct ProxyIP {
-    /// The ip address of the request
-    pub ip_address: String,
-    /// The id of the whitelist
-    pub id: u128,
+impl EvomiProduct {
+    /// The product name used by the api.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvomiProduct::Residential => "residential",
+            EvomiProduct::Mobile => "mobile",
+            EvomiProduct::Datacenter => "datacenter",
+        }
+    }
+
+    /// The product from the api name.
+    pub fn from_name(name: &str) -> Option<EvomiProduct> {
+        match name {
+            "residential" => Some(EvomiProduct::Residential),
+            "mobile" => Some(EvomiProduct::Mobile),
+            "datacenter" => Some(EvomiProduct::Datacenter),
+            _ => None,
+        }
+    }
+}
+
+/// A whitelisted ip on an evomi product.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct WhitelistEntry {
+    /// The whitelisted ip.
+    pub ip: String,
+    /// The product of the entry.
+    #[serde(default)]
+    pub product: EvomiProduct,
+    /// The created at date.
     #[serde(default)]
-    /// The created at date
     pub created_at: Option<String>,
+}
+
+/// The whitelisted ips of a product.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct WhitelistEntries {
+    /// The whitelisted ips.
     #[serde(default)]
-    /// The last used data.
-    pub last_used_at: Option<String>,
+    pub data: Vec<WhitelistEntry>,
+}
+
+/// The evomi api token headers.
+fn auth_headers(credentials: &dyn CredentialSource) -> Result<HeaderMap> {
+    let password = credentials::require(credentials, API_TOKEN_KEY)?;
+    let mut headers = HeaderMap::new();
+
+    if let Ok(hv) = HeaderValue::from_str(&format!("Authorization={}", password)) {
+        headers.insert(COOKIE, hv);
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/json; charset=utf-8"),
+        );
+    }
+
+    Ok(headers)
+}
+
+/// The whitelist url of the product. Residential proxies keep the original product wide endpoint.
+fn whitelist_url(base_url: &str, product: EvomiProduct) -> String {
+    match product {
+        EvomiProduct::Residential => format!("{}/products/ip_whitelist", base_url),
+        product => format!("{}/products/{}/ip_whitelist", base_url, product.as_str()),
+    }
+}
+
+/// List the whitelisted ips of the product.
+pub async fn list_whitelist(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    product: EvomiProduct,
+) -> Result<Vec<WhitelistEntry>> {
+    let headers = auth_headers(credentials)?;
+    let response = client
+        .get(whitelist_url(base_url, product))
+        .headers(headers)
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        let entries: WhitelistEntries = response.json().await?;
+
+        Ok(entries
+            .data
+            .into_iter()
+            .map(|entry| WhitelistEntry { product, ..entry })
+            .collect())
+    } else {
+        Err(ProxierError::from_response(response).await)
+    }
 }
 
-// setup any of the proxies white listing if needed.
-pub async fn setup_proxy(client: &Client, target: &str, remove: bool) -> ProxyIP {
-    let mut p = ProxyIP::default();
-
-    if !target.is_empty() {
-        let proxy_url = "https://api.evomi.com/products/ip_whitelist";
-
-        match env::var("EVOMI_API_TOKEN") {
-            Ok(password) => {
-                let mut headers = HeaderMap::new();
-
-                match HeaderValue::from_str(&format!("Authorization={}", password).to_string()) {
-                    Ok(hv) => {
-                        headers.insert(COOKIE, hv);
-                        headers.insert(
-                            CONTENT_TYPE,
-                            HeaderValue::from_static("application/json; charset=utf-8"),
-                        );
-                    }
-                    _ => (),
+/// Whitelist the ip on the product.
+pub async fn create_whitelist_entry(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    product: EvomiProduct,
+    ip: &str,
+) -> Result<WhitelistEntry> {
+    super::ip::parse(ip)?;
+
+    let headers = auth_headers(credentials)?;
+    let response = client
+        .post(whitelist_url(base_url, product))
+        .headers(headers)
+        .json(&json!({ "ip": ip }))
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        // the ip is whitelisted even when the body does not hold the entry.
+        let entry =
+            serde_json::from_str::<WhitelistEntry>(&response.text().await?).unwrap_or_else(|_| {
+                WhitelistEntry {
+                    ip: ip.into(),
+                    ..Default::default()
                 }
+            });
+        Ok(WhitelistEntry { product, ..entry })
+    } else {
+        Err(ProxierError::from_response(response).await)
+    }
+}
+
+/// Remove the ip from the product whitelist.
+pub async fn delete_whitelist_entry(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    product: EvomiProduct,
+    ip: &str,
+) -> Result<()> {
+    let headers = auth_headers(credentials)?;
+    let response = client
+        .delete(whitelist_url(base_url, product))
+        .headers(headers)
+        .json(&json!({ "ip": ip }))
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(ProxierError::from_response(response).await)
+    }
+}
 
-                let action = if remove {
-                    client.delete(proxy_url).json(&json!({ "ip": target }))
-                } else {
-                    client.post(proxy_url).json(&json!({ "ip": target }))
-                };
-
-                match action.headers(headers).send().await {
-                    Ok(response) => {
-                        if response.status().is_success() {
-                            if !remove {
-                                let ip: ProxyIP = serde_json::from_str(
-                                    &response.text().await.unwrap_or_default(),
-                                )
-                                .unwrap_or_default();
-
-                                p.clone_from(&ip);
-                            }
-                            println!("Successfully updated the IP authorization for Evomi.");
-                        } else {
-                            if response.status() == 400 {
-                                if !remove {
-                                    let res_text = response.text().await;
-
-                                    let ip: ProxyIP =
-                                        serde_json::from_str(&res_text.unwrap_or_default())
-                                            .unwrap_or_default();
-
-                                    p.clone_from(&ip);
-                                }
-                                println!("IP already whitelisted for Evomi.");
-                            } else {
-                                if !remove && response.status() == 500 {
-                                    println!(
-                                        "IP Already whitelisted for evomi. Status: {:?} - Removed:{:?}",
-                                        response.status(),
-                                        remove,
-                                    );
-                                } else {
-                                    println!(
-                                        "Failed to update the IP authorization. Status: {:?} - Removed:{:?}",
-                                        response.status(),
-                                        remove,
-                                    );
-                                }
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        println!("{:?}", err)
-                    }
+impl EvomiConfiguration {
+    /// The api base url to use.
+    pub fn api_url(&self) -> &str {
+        self.base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+    }
+
+    /// The credential source to use.
+    pub fn credentials(&self) -> &dyn CredentialSource {
+        credentials::or_default(&self.credentials)
+    }
+
+    /// The products to whitelist. Defaults to residential.
+    pub fn products(&self) -> Vec<EvomiProduct> {
+        if self.products.is_empty() {
+            vec![EvomiProduct::default()]
+        } else {
+            self.products.clone()
+        }
+    }
+
+    /// Find the whitelisted ip on the product.
+    async fn find_entry(
+        &self,
+        client: &Client,
+        product: EvomiProduct,
+        ip: &str,
+    ) -> Result<Option<WhitelistEntry>> {
+        Ok(
+            list_whitelist(client, self.api_url(), self.credentials(), product)
+                .await?
+                .into_iter()
+                .find(|entry| entry.ip == ip),
+        )
+    }
+
+    /// Whitelist the ip on a single product.
+    async fn whitelist_product(
+        &self,
+        client: &Client,
+        product: EvomiProduct,
+        ip: &str,
+    ) -> Result<(WhitelistEntry, WhitelistStatus)> {
+        if let Some(entry) = self.find_entry(client, product, ip).await? {
+            return Ok((entry, WhitelistStatus::AlreadyPresent));
+        }
+
+        match create_whitelist_entry(client, self.api_url(), self.credentials(), product, ip).await
+        {
+            Ok(entry) => Ok((entry, WhitelistStatus::Created)),
+            // evomi fails a duplicate with an internal error, so an error response is only a duplicate
+            // when the listing confirms it.
+            Err(err @ ProxierError::Status { .. }) => {
+                match self.find_entry(client, product, ip).await? {
+                    Some(entry) => Ok((entry, WhitelistStatus::AlreadyPresent)),
+                    _ => Err(err),
                 }
             }
-            Err(e) => {
-                println!("Set the env {:?} to enable proxy whitelisting Evomi.", e)
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[async_trait]
+impl ProxyProvider for EvomiConfiguration {
+    fn name(&self) -> &str {
+        "evomi"
+    }
+
+    fn account(&self) -> &str {
+        self.account.as_deref().unwrap_or(DEFAULT_ACCOUNT)
+    }
+
+    fn supports_ipv6(&self) -> bool {
+        false
+    }
+
+    async fn whitelist(&mut self, client: &Client, ip: IpAddr) -> Result<WhitelistStatus> {
+        let ip = ip.to_string();
+        let ip = ip.as_str();
+
+        let mut status = WhitelistStatus::AlreadyPresent;
+
+        for product in self.products() {
+            let (entry, product_status) = self.whitelist_product(client, product, ip).await?;
+
+            if product_status == WhitelistStatus::Created {
+                status = WhitelistStatus::Created;
             }
-        };
+
+            self.whitelist_entries
+                .retain(|e| !(e.product == product && e.ip == entry.ip));
+            self.whitelist_entries.push(entry);
+        }
+
+        Ok(status)
     }
 
-    p
+    async fn delist(&mut self, client: &Client, ip: IpAddr) -> Result<DelistStatus> {
+        let mut status = DelistStatus::NotPresent;
+
+        while let Some(pos) = self
+            .whitelist_entries
+            .iter()
+            .position(|tracked| same_ip(&tracked.ip, ip))
+        {
+            let entry = self.whitelist_entries[pos].clone();
+
+            // only delete from the products that still list the ip.
+            if self
+                .find_entry(client, entry.product, &entry.ip)
+                .await?
+                .is_some()
+            {
+                delete_whitelist_entry(
+                    client,
+                    self.api_url(),
+                    self.credentials(),
+                    entry.product,
+                    &entry.ip,
+                )
+                .await?;
+                status = DelistStatus::Removed;
+            }
+
+            self.whitelist_entries.remove(pos);
+        }
+
+        Ok(status)
+    }
+
+    async fn list(&self, client: &Client) -> Result<Vec<ProxyEntry>> {
+        let mut entries = Vec::new();
+
+        for product in self.products() {
+            // the product stands in for the entry id.
+            entries.extend(
+                list_whitelist(client, self.api_url(), self.credentials(), product)
+                    .await?
+                    .into_iter()
+                    .map(|entry| ProxyEntry {
+                        ip: entry.ip,
+                        id: Some(product.as_str().into()),
+                        ..Default::default()
+                    }),
+            );
+        }
+
+        Ok(entries)
+    }
+
+    fn entries(&self) -> Vec<ProxyEntry> {
+        self.whitelist_entries
+            .iter()
+            .map(|entry| ProxyEntry {
+                ip: entry.ip.clone(),
+                id: Some(entry.product.as_str().into()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    async fn remove(&mut self, client: &Client, entry: &ProxyEntry) -> Result<DelistStatus> {
+        // the id names the product, without one the ip is removed from every product.
+        let products = match entry.id.as_deref() {
+            Some(name) => vec![EvomiProduct::from_name(name).ok_or_else(|| {
+                ProxierError::Unsupported(format!("unknown evomi product {:?}", name))
+            })?],
+            _ => self.products(),
+        };
+
+        let mut status = DelistStatus::NotPresent;
+
+        for product in products {
+            if removed(
+                delete_whitelist_entry(
+                    client,
+                    self.api_url(),
+                    self.credentials(),
+                    product,
+                    &entry.ip,
+                )
+                .await,
+            )? == DelistStatus::Removed
+            {
+                status = DelistStatus::Removed;
+            }
+
+            self.whitelist_entries
+                .retain(|tracked| !(tracked.product == product && tracked.ip == entry.ip));
+        }
+
+        Ok(status)
+    }
 }
@@ -0,0 +1,329 @@
+use super::election::{after, now};
+use super::ledger::update_locked;
+use super::redis::{Connection, Reply};
+use super::resolver::random_u64;
+use super::{ProxierError, Result};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The default time a hold lasts unless the holder acquires the ip again.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(15 * 60);
+/// The default timeout for the redis commands.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+/// The default prefix of the redis keys.
+pub const DEFAULT_PREFIX: &str = "proxier:holders:";
+
+/// A store shared by the replicas counting the holders of each whitelisted ip.
+/// The holders are kept as a set, so acquiring or releasing twice with the same holder is harmless.
+/// Each hold expires after its ttl, so a replica killed without releasing stops holding the ip.
+#[async_trait]
+pub trait HolderStore: std::fmt::Debug + Send + Sync {
+    /// Add the holder of the ip or extend its hold until the ttl passes. Returns the amount of holders after.
+    async fn acquire(&self, ip: &str, holder: &str, ttl: Duration) -> Result<usize>;
+    /// Remove the holder of the ip. Returns the amount of holders left.
+    async fn release(&self, ip: &str, holder: &str) -> Result<usize>;
+    /// The amount of holders of the ip whose hold has not expired.
+    async fn count(&self, ip: &str) -> Result<usize>;
+}
+
+/// The holder store of a proxier with the id this replica holds the ips as.
+#[derive(Clone, Debug)]
+pub struct Holders {
+    /// The shared store.
+    pub store: Arc<dyn HolderStore>,
+    /// The id of this replica. Keep it stable across restarts so `recover` releases the holds of a crashed run.
+    pub holder: String,
+    /// How long a hold lasts. Refresh the holds within the ttl with the ip watcher or `Proxier::refresh_holds`.
+    pub ttl: Duration,
+}
+
+impl Holders {
+    /// Hold the ips in the store as the holder.
+    pub fn new(store: impl HolderStore + 'static, holder: &str) -> Holders {
+        Holders {
+            store: Arc::new(store),
+            holder: holder.into(),
+            ttl: DEFAULT_TTL,
+        }
+    }
+
+    /// Hold the ips in the store with a random holder id for this process.
+    /// The holds of a process killed without delisting are only dropped once the ttl passes.
+    pub fn random(store: impl HolderStore + 'static) -> Holders {
+        Holders::new(store, &format!("{:016x}", random_u64()))
+    }
+
+    /// Add this replica as a holder of the ip or extend its hold.
+    pub async fn acquire(&self, ip: &str) -> Result<usize> {
+        self.store.acquire(ip, &self.holder, self.ttl).await
+    }
+
+    /// Remove this replica as a holder of the ip. Returns the holders left.
+    pub async fn release(&self, ip: &str) -> Result<usize> {
+        self.store.release(ip, &self.holder).await
+    }
+}
+
+/// The store error as a provider outcome, without wrapping the holder errors twice.
+pub(crate) fn failed(err: &ProxierError) -> ProxierError {
+    match err {
+        ProxierError::Holders(message) => ProxierError::Holders(message.clone()),
+        err => ProxierError::Holders(err.to_string()),
+    }
+}
+
+/// The holders of every ip with the unix time in milliseconds their hold expires.
+type HolderMap = BTreeMap<String, BTreeMap<String, i64>>;
+
+/// Drop the expired holds.
+fn expire(holders: &mut HolderMap) {
+    let now = now();
+
+    for set in holders.values_mut() {
+        set.retain(|_, expires_at| *expires_at > now);
+    }
+
+    holders.retain(|_, set| !set.is_empty());
+}
+
+/// Add the holder of the ip until the ttl passes, or remove it without one. Returns the amount left.
+fn update(holders: &mut HolderMap, ip: &str, holder: &str, ttl: Option<Duration>) -> usize {
+    expire(holders);
+    let set = holders.entry(ip.into()).or_default();
+
+    match ttl {
+        Some(ttl) => set.insert(holder.into(), after(ttl)),
+        _ => set.remove(holder),
+    };
+
+    let count = set.len();
+
+    if count == 0 {
+        holders.remove(ip);
+    }
+
+    count
+}
+
+/// The amount of holders of the ip whose hold has not expired.
+fn count(holders: &HolderMap, ip: &str) -> usize {
+    let now = now();
+
+    holders.get(ip).map_or(0, |set| {
+        set.values().filter(|expires_at| **expires_at > now).count()
+    })
+}
+
+/// An in process holder store, standing in for a shared store in tests and single host setups.
+/// Clones share the holders.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryHolderStore {
+    holders: Arc<Mutex<HolderMap>>,
+}
+
+impl MemoryHolderStore {
+    /// An empty store.
+    pub fn new() -> MemoryHolderStore {
+        MemoryHolderStore::default()
+    }
+
+    /// The holders of the ip whose hold has not expired.
+    pub fn holders(&self, ip: &str) -> Vec<String> {
+        let now = now();
+
+        self.lock()
+            .get(ip)
+            .map(|set| {
+                set.iter()
+                    .filter(|(_, expires_at)| **expires_at > now)
+                    .map(|(holder, _)| holder.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HolderMap> {
+        self.holders.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl HolderStore for MemoryHolderStore {
+    async fn acquire(&self, ip: &str, holder: &str, ttl: Duration) -> Result<usize> {
+        Ok(update(&mut self.lock(), ip, holder, Some(ttl)))
+    }
+
+    async fn release(&self, ip: &str, holder: &str) -> Result<usize> {
+        Ok(update(&mut self.lock(), ip, holder, None))
+    }
+
+    async fn count(&self, ip: &str) -> Result<usize> {
+        Ok(count(&self.lock(), ip))
+    }
+}
+
+/// A json file of the holders shared by the replicas on a host or a shared volume.
+/// Every change takes an exclusive lock on a `.lock` file next to it.
+#[derive(Clone, Debug)]
+pub struct FileHolderStore {
+    /// The path of the holders file.
+    path: PathBuf,
+}
+
+impl FileHolderStore {
+    /// A store at the path. The file is created on the first acquire.
+    pub fn new(path: impl Into<PathBuf>) -> FileHolderStore {
+        FileHolderStore { path: path.into() }
+    }
+
+    /// The path of the holders file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read and change the holders under the file lock.
+    async fn locked<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut HolderMap) -> T + Send + 'static,
+    ) -> Result<T> {
+        let path = self.path.clone();
+
+        tokio::task::spawn_blocking(move || update_locked(&path, f))
+            .await
+            .map_err(|err| ProxierError::Holders(err.to_string()))?
+    }
+}
+
+#[async_trait]
+impl HolderStore for FileHolderStore {
+    async fn acquire(&self, ip: &str, holder: &str, ttl: Duration) -> Result<usize> {
+        let (ip, holder) = (ip.to_string(), holder.to_string());
+        self.locked(move |holders| update(holders, &ip, &holder, Some(ttl)))
+            .await
+    }
+
+    async fn release(&self, ip: &str, holder: &str) -> Result<usize> {
+        let (ip, holder) = (ip.to_string(), holder.to_string());
+        self.locked(move |holders| update(holders, &ip, &holder, None))
+            .await
+    }
+
+    async fn count(&self, ip: &str) -> Result<usize> {
+        let ip = ip.to_string();
+        self.locked(move |holders| count(holders, &ip)).await
+    }
+}
+
+/// The holders kept as a redis sorted set per ip scored by the expiry of each hold, shared by the replicas across hosts.
+#[derive(Clone, Debug)]
+pub struct RedisHolderStore {
+    /// The redis server as `host:port`.
+    pub addr: String,
+    /// The password to authenticate with.
+    pub password: Option<String>,
+    /// The prefix of the keys.
+    pub prefix: String,
+    /// How long to wait for each command.
+    pub timeout: Duration,
+}
+
+impl RedisHolderStore {
+    /// A store on the redis server.
+    pub fn new(addr: &str) -> RedisHolderStore {
+        RedisHolderStore {
+            addr: addr.into(),
+            password: None,
+            prefix: DEFAULT_PREFIX.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// The key of the ip.
+    fn key(&self, ip: &str) -> String {
+        format!("{}{}", self.prefix, ip)
+    }
+
+    /// The holders of the ip whose hold has not expired.
+    pub async fn holders(&self, ip: &str) -> Result<Vec<String>> {
+        let redis = self.connection();
+        let now = format!("({}", now());
+
+        match redis
+            .call(&["ZRANGEBYSCORE", &self.key(ip), &now, "+inf"])
+            .await?
+        {
+            Reply::Array(Some(members)) => members
+                .into_iter()
+                .map(|member| match member {
+                    Reply::Bulk(Some(member)) => Ok(String::from_utf8_lossy(&member).into_owned()),
+                    reply => Err(redis.unexpected(Some(&reply))),
+                })
+                .collect(),
+            reply => Err(redis.unexpected(Some(&reply))),
+        }
+    }
+
+    /// A connection to the redis server.
+    fn connection(&self) -> Connection<'_> {
+        Connection {
+            addr: &self.addr,
+            password: self.password.as_deref(),
+            timeout: self.timeout,
+            error: ProxierError::Holders,
+        }
+    }
+
+    /// Drop the expired holds and change the set of the ip in a transaction, then read the size after.
+    async fn change(&self, command: &[&str]) -> Result<usize> {
+        let key = command[1];
+        let now = now().to_string();
+        let redis = self.connection();
+        let replies = redis
+            .send(&[
+                &["MULTI"],
+                &["ZREMRANGEBYSCORE", key, "-inf", &now],
+                command,
+                &["ZCARD", key],
+                &["EXEC"],
+            ])
+            .await?;
+
+        if !matches!(replies.first(), Some(Reply::Status(status)) if status == "OK") {
+            return Err(redis.unexpected(replies.first()));
+        }
+
+        match replies.into_iter().last() {
+            Some(Reply::Array(Some(results))) => match results.last() {
+                Some(Reply::Integer(count)) => Ok(*count as usize),
+                reply => Err(redis.unexpected(reply)),
+            },
+            reply => Err(redis.unexpected(reply.as_ref())),
+        }
+    }
+}
+
+#[async_trait]
+impl HolderStore for RedisHolderStore {
+    async fn acquire(&self, ip: &str, holder: &str, ttl: Duration) -> Result<usize> {
+        let expires_at = after(ttl).to_string();
+        self.change(&["ZADD", &self.key(ip), &expires_at, holder])
+            .await
+    }
+
+    async fn release(&self, ip: &str, holder: &str) -> Result<usize> {
+        self.change(&["ZREM", &self.key(ip), holder]).await
+    }
+
+    async fn count(&self, ip: &str) -> Result<usize> {
+        let redis = self.connection();
+        let now = format!("({}", now());
+
+        match redis.call(&["ZCOUNT", &self.key(ip), &now, "+inf"]).await? {
+            Reply::Integer(count) => Ok(count as usize),
+            reply => Err(redis.unexpected(Some(&reply))),
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use super::{ProxierError, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// The most ips a cidr range is expanded into for the providers that can not whitelist ranges natively.
+pub const MAX_EXPANDED_IPS: u128 = 256;
+
+/// The ipv4 address is in the carrier grade nat shared address space, 100.64.0.0/10.
+fn is_cgnat_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    a == 100 && (64..128).contains(&b)
+}
+
+/// The ipv4 address is reachable on the public internet.
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || is_cgnat_v4(ip)
+        || ip.octets()[0] == 0)
+}
+
+/// The ipv6 address is reachable on the public internet.
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public_v4(v4);
+    }
+
+    let first = ip.segments()[0];
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local addresses.
+        || (first & 0xfe00) == 0xfc00
+        // link local addresses.
+        || (first & 0xffc0) == 0xfe80)
+}
+
+/// The ip is reachable on the public internet, so it can be whitelisted on a provider.
+/// Private, loopback, unspecified, link local, carrier grade nat and multicast addresses are not.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+/// The ip is in the carrier grade nat shared address space, so its public ip is shared with other customers.
+pub fn is_cgnat(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_cgnat_v4(ip),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().is_some_and(is_cgnat_v4),
+    }
+}
+
+/// Parse the ip, rejecting anything that is not a public address.
+pub fn parse_public(ip: &str) -> Result<IpAddr> {
+    let ip = ip
+        .trim()
+        .parse()
+        .map_err(|_| ProxierError::InvalidIp(ip.into()))?;
+
+    validate_public(ip)
+}
+
+/// Reject the ip when it is not a public address.
+pub fn validate_public(ip: IpAddr) -> Result<IpAddr> {
+    if is_public(ip) {
+        Ok(ip)
+    } else {
+        Err(ProxierError::InvalidIp(ip.to_string()))
+    }
+}
+
+/// Parse the ip sent to a provider api. Any address is accepted, the typos and empty values are not.
+pub(crate) fn parse(ip: &str) -> Result<IpAddr> {
+    ip.parse().map_err(|_| ProxierError::InvalidIp(ip.into()))
+}
+
+/// A single ip or a cidr range of server ips.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// A range from the network address and prefix length. Host bits set in the address are rejected.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<IpNet> {
+        let net = IpNet { addr, prefix_len };
+
+        if prefix_len > net.max_prefix_len() || net.first_bits() != bits(addr) {
+            return Err(ProxierError::InvalidIp(format!("{}/{}", addr, prefix_len)));
+        }
+
+        Ok(net)
+    }
+
+    /// The network address.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// The prefix length.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// The prefix length of a single ip of the family.
+    pub fn max_prefix_len(&self) -> u8 {
+        match self.addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    /// The range holds a single ip.
+    pub fn is_single(&self) -> bool {
+        self.prefix_len == self.max_prefix_len()
+    }
+
+    /// The number of ips in the range, saturating for the full ipv6 space.
+    pub fn size(&self) -> u128 {
+        1u128
+            .checked_shl(u32::from(self.max_prefix_len() - self.prefix_len))
+            .unwrap_or(u128::MAX)
+    }
+
+    /// The last ip of the range.
+    pub fn last(&self) -> IpAddr {
+        from_bits(self.addr, self.first_bits() | !self.mask())
+    }
+
+    /// The ip is in the range.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.addr.is_ipv4() == ip.is_ipv4() && bits(ip) & self.mask() == self.first_bits()
+    }
+
+    /// Every ip of the range, including the network and broadcast addresses.
+    pub fn hosts(&self) -> impl Iterator<Item = IpAddr> {
+        let addr = self.addr;
+
+        (self.first_bits()..=bits(self.last())).map(move |bits| from_bits(addr, bits))
+    }
+
+    /// The mask of the prefix within the bits of the family.
+    fn mask(&self) -> u128 {
+        let host_bits = u32::from(self.max_prefix_len() - self.prefix_len);
+        let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+
+        match self.addr {
+            IpAddr::V4(_) => mask & u128::from(u32::MAX),
+            IpAddr::V6(_) => mask,
+        }
+    }
+
+    /// The network address bits.
+    fn first_bits(&self) -> u128 {
+        bits(self.addr) & self.mask()
+    }
+}
+
+/// The ip as an integer.
+fn bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(u32::from(ip)),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+/// The ip of the family from an integer.
+fn from_bits(family: IpAddr, bits: u128) -> IpAddr {
+    match family {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(bits as u32)),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(bits)),
+    }
+}
+
+impl From<IpAddr> for IpNet {
+    fn from(addr: IpAddr) -> Self {
+        let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+
+        IpNet { addr, prefix_len }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = ProxierError;
+
+    /// Parse a single ip or a range as `addr/prefix`.
+    fn from_str(value: &str) -> Result<IpNet> {
+        let invalid = || ProxierError::InvalidIp(value.into());
+
+        match value.trim().split_once('/') {
+            Some((addr, prefix_len)) => IpNet::new(
+                addr.parse().map_err(|_| invalid())?,
+                prefix_len.parse().map_err(|_| invalid())?,
+            ),
+            _ => Ok(IpNet::from(parse(value.trim())?)),
+        }
+    }
+}
+
+impl std::fmt::Display for IpNet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_single() {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix_len)
+        }
+    }
+}
+
+/// The ipv4 ranges that are not public: this network, private, carrier grade nat, loopback, link local, multicast and broadcast.
+const NON_PUBLIC_V4: [(Ipv4Addr, u8); 9] = [
+    (Ipv4Addr::new(0, 0, 0, 0), 8),
+    (Ipv4Addr::new(10, 0, 0, 0), 8),
+    (Ipv4Addr::new(100, 64, 0, 0), 10),
+    (Ipv4Addr::new(127, 0, 0, 0), 8),
+    (Ipv4Addr::new(169, 254, 0, 0), 16),
+    (Ipv4Addr::new(172, 16, 0, 0), 12),
+    (Ipv4Addr::new(192, 168, 0, 0), 16),
+    (Ipv4Addr::new(224, 0, 0, 0), 4),
+    (Ipv4Addr::BROADCAST, 32),
+];
+
+/// The ipv6 ranges that are not public: unspecified, loopback, unique local, link local and multicast.
+const NON_PUBLIC_V6: [(Ipv6Addr, u8); 5] = [
+    (Ipv6Addr::UNSPECIFIED, 128),
+    (Ipv6Addr::LOCALHOST, 128),
+    (Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7),
+    (Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10),
+    (Ipv6Addr::new(0xff00, 0, 0, 0, 0, 0, 0, 0), 8),
+];
+
+/// The ipv4 mapped ipv6 range, ::ffff:0:0/96.
+const MAPPED_V6: (Ipv6Addr, u8) = (Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0, 0), 96);
+
+/// Every ip of the range is reachable on the public internet.
+fn is_public_net(net: IpNet) -> bool {
+    let overlaps = |(addr, prefix_len): (IpAddr, u8)| {
+        let other = IpNet { addr, prefix_len };
+        net.contains(other.addr) || other.contains(net.addr)
+    };
+
+    match net.addr {
+        IpAddr::V4(_) => !NON_PUBLIC_V4
+            .iter()
+            .any(|&(addr, prefix_len)| overlaps((addr.into(), prefix_len))),
+        IpAddr::V6(addr) => {
+            let mapped = IpNet {
+                addr: MAPPED_V6.0.into(),
+                prefix_len: MAPPED_V6.1,
+            };
+
+            // a mapped range is public when the ipv4 range it maps is.
+            if net.prefix_len >= mapped.prefix_len && mapped.contains(net.addr) {
+                return addr.to_ipv4_mapped().is_some_and(|v4| {
+                    is_public_net(IpNet {
+                        addr: v4.into(),
+                        prefix_len: net.prefix_len - mapped.prefix_len,
+                    })
+                });
+            }
+
+            !overlaps((mapped.addr, mapped.prefix_len))
+                && !NON_PUBLIC_V6
+                    .iter()
+                    .any(|&(addr, prefix_len)| overlaps((addr.into(), prefix_len)))
+        }
+    }
+}
+
+/// Reject the range when any of its ips is not a public address.
+pub fn validate_public_net(net: IpNet) -> Result<IpNet> {
+    if is_public_net(net) {
+        Ok(net)
+    } else {
+        Err(ProxierError::InvalidIp(net.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(value: &str) -> IpNet {
+        value.parse().unwrap()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn new_rejects_host_bits_and_long_prefixes() {
+        assert!(IpNet::new(ip("203.0.113.0"), 24).is_ok());
+        assert!(IpNet::new(ip("203.0.113.1"), 24).is_err());
+        assert!(IpNet::new(ip("203.0.113.0"), 33).is_err());
+        assert!(IpNet::new(ip("2001:db8::"), 32).is_ok());
+        assert!(IpNet::new(ip("2001:db8::1"), 64).is_err());
+        assert!(IpNet::new(ip("2001:db8::"), 129).is_err());
+        assert!("203.0.113.1/24".parse::<IpNet>().is_err());
+        assert!("203.0.113.0/x".parse::<IpNet>().is_err());
+        assert!("".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        assert_eq!(net(" 203.0.113.7 ").to_string(), "203.0.113.7");
+        assert_eq!(net("203.0.113.7/32").to_string(), "203.0.113.7");
+        assert_eq!(net("203.0.113.0/24").to_string(), "203.0.113.0/24");
+        assert_eq!(net("2001:db8::/48").to_string(), "2001:db8::/48");
+        assert_eq!(net("2001:db8::1/128"), IpNet::from(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn size_last_and_hosts() {
+        let v4 = net("203.0.113.0/30");
+        assert_eq!(v4.size(), 4);
+        assert_eq!(v4.last(), ip("203.0.113.3"));
+        assert_eq!(
+            v4.hosts().collect::<Vec<_>>(),
+            ["203.0.113.0", "203.0.113.1", "203.0.113.2", "203.0.113.3"].map(ip)
+        );
+
+        let single = net("203.0.113.9");
+        assert!(single.is_single());
+        assert_eq!(single.size(), 1);
+        assert_eq!(single.last(), ip("203.0.113.9"));
+        assert_eq!(single.hosts().collect::<Vec<_>>(), [ip("203.0.113.9")]);
+
+        let v6 = net("2001:db8::/126");
+        assert_eq!(v6.size(), 4);
+        assert_eq!(v6.last(), ip("2001:db8::3"));
+        assert_eq!(net("2001:db8::1/128").size(), 1);
+    }
+
+    #[test]
+    fn zero_prefix_covers_the_family() {
+        let v4 = net("0.0.0.0/0");
+        assert_eq!(v4.size(), 1 << 32);
+        assert_eq!(v4.last(), ip("255.255.255.255"));
+        assert!(v4.contains(ip("203.0.113.7")));
+        assert!(!v4.contains(ip("2001:db8::1")));
+
+        let v6 = net("::/0");
+        // the full ipv6 space does not fit, the size saturates.
+        assert_eq!(v6.size(), u128::MAX);
+        assert_eq!(v6.last(), ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"));
+        assert!(v6.contains(ip("2001:db8::1")));
+        assert!(!v6.contains(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn contains_checks_the_prefix_and_family() {
+        let v4 = net("203.0.113.0/24");
+        assert!(v4.contains(ip("203.0.113.0")));
+        assert!(v4.contains(ip("203.0.113.255")));
+        assert!(!v4.contains(ip("203.0.114.0")));
+        assert!(!v4.contains(ip("::ffff:203.0.113.1")));
+
+        let v6 = net("2001:db8:1::/48");
+        assert!(v6.contains(ip("2001:db8:1:ffff::1")));
+        assert!(!v6.contains(ip("2001:db8:2::1")));
+    }
+
+    #[test]
+    fn public_addresses() {
+        for value in ["203.0.113.7", "8.8.8.8", "2001:4860:4860::8888"] {
+            assert!(is_public(ip(value)), "{}", value);
+        }
+
+        for value in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "10.0.0.1",
+            "127.0.0.1",
+            "169.254.1.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "100.64.0.1",
+            "100.127.255.255",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip(value)), "{}", value);
+        }
+
+        assert!(is_cgnat(ip("100.64.0.1")));
+        assert!(!is_cgnat(ip("100.128.0.1")));
+        assert!(parse_public("10.0.0.1").is_err());
+        assert_eq!(parse_public(" 203.0.113.7 ").unwrap(), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn validate_public_net_rejects_any_non_public_ip() {
+        assert!(validate_public_net(net("203.0.113.0/24")).is_ok());
+        assert!(validate_public_net(net("2001:db8::/64")).is_ok());
+        assert!(validate_public_net(net("10.0.0.0/8")).is_err());
+        // starts public but runs into the private 10.0.0.0/8 range.
+        assert!(validate_public_net(net("8.0.0.0/6")).is_err());
+        assert!(validate_public_net(net("0.0.0.0/0")).is_err());
+        assert!(validate_public_net(net("100.64.0.0/10")).is_err());
+        assert!(validate_public_net(net("::/0")).is_err());
+        // spans the unique local fc00::/7 range.
+        assert!(validate_public_net(net("f000::/4")).is_err());
+        assert!(validate_public_net(net("::ffff:203.0.113.0/120")).is_ok());
+        assert!(validate_public_net(net("::ffff:10.0.0.0/120")).is_err());
+        assert!(validate_public_net(net("::fffe:0:0/95")).is_err());
+    }
+
+    #[test]
+    fn single_ips_match_is_public() {
+        for value in [
+            "203.0.113.7",
+            "10.0.0.1",
+            "2001:db8::1",
+            "fe80::1",
+            "::ffff:8.8.8.8",
+        ] {
+            assert_eq!(
+                validate_public_net(net(value)).is_ok(),
+                is_public(ip(value)),
+                "{}",
+                value
+            );
+        }
+    }
+}
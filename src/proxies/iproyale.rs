@@ -1,8 +1,19 @@
+use super::credentials::{self, CredentialSource};
+use super::provider::{removed, same_ip};
+use super::{
+    DelistStatus, IPRoyaleConfiguration, ProxierError, ProxyEntry, ProxyProvider, Result,
+    WhitelistStatus, DEFAULT_ACCOUNT,
+};
+use async_trait::async_trait;
+use futures::{future, stream, Stream, TryStreamExt};
 use reqwest::Client;
 use serde_json::json;
-use std::env;
+use std::net::IpAddr;
 pub use string_concat::{string_concat, string_concat_impl};
 
+/// The iproyal api base url.
+pub const DEFAULT_BASE_URL: &str = "https://resi-api.iproyal.com";
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
 pub struct WhitelistEntry {
     /// the id hash.
@@ -17,106 +28,138 @@ pub struct WhitelistEntry {
     pub configuration: String,
 }
 
+/// The credential key for the iproyal api token.
+pub const API_TOKEN_KEY: &str = "IP_ROYALE_API_TOKEN";
+
+/// The pagination of the whitelist entries.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct PageMeta {
+    /// The current page.
+    #[serde(default)]
+    pub current_page: u32,
+    /// The last page.
+    #[serde(default)]
+    pub last_page: u32,
+    /// The entries per page.
+    #[serde(default)]
+    pub per_page: u32,
+    /// The total amount of entries.
+    #[serde(default)]
+    pub total: u32,
+}
+
+/// A page of whitelist entries.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct WhitelistEntries {
+    /// The entries of the page.
+    #[serde(default)]
+    pub data: Vec<WhitelistEntry>,
+    /// The pagination of the entries.
+    #[serde(default)]
+    pub meta: PageMeta,
+}
+
 /// Create a new whitelist entry
 pub async fn create_whitelist_entry(
     client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
     residential_user_hash: &str,
     ip: &str,
     port: u16,
     configuration: &str,
-) -> WhitelistEntry {
+) -> Result<WhitelistEntry> {
+    super::ip::parse(ip)?;
+
     let proxy_whitelist_url = string_concat!(
-        "https://resi-api.iproyal.com/v1/residential-users/",
+        base_url,
+        "/v1/residential-users/",
         residential_user_hash,
         "/whitelist-entries"
     );
 
-    match dotenv::var("IP_ROYALE_API_TOKEN") {
-        Ok(api_token) => {
-            let body = json!({
-                "ip": ip,
-                "port": port,
-                "configuration": configuration,
-            });
-
-            match client
-                .post(&proxy_whitelist_url)
-                .bearer_auth(api_token)
-                .json(&body)
-                .send()
-                .await
-            {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        response.json().await.unwrap_or_default()
-                    } else {
-                        if response.status() == 409 {
-                            // attempt to get the whitelist entry.
-                            println!("Failed to create whitelist entry: {}", response.status());
-                            Default::default()
-                        } else {
-                            // IF THIS FAILS DISABLE IP ROYALE FROM THE SERVER TEMP OR SHUTDOWN AND RESTART
-                            println!(
-                                "CRITICAL: Failed to create whitelist entry: {}",
-                                response.status()
-                            );
-                            Default::default()
-                        }
-                    }
-                }
-                Err(err) => {
-                    panic!("Request error: {:?}", err);
-                }
-            }
-        }
-        Err(e) => {
-            panic!("API Token missing: {:?}", e);
-        }
+    let api_token = credentials::require(credentials, API_TOKEN_KEY)?;
+    let body = json!({
+        "ip": ip,
+        "port": port,
+        "configuration": configuration,
+    });
+
+    let response = client
+        .post(&proxy_whitelist_url)
+        .bearer_auth(api_token)
+        .json(&body)
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        Ok(response.json().await?)
+    } else if response.status() == 409 {
+        Err(ProxierError::conflict(response).await)
+    } else {
+        Err(ProxierError::from_response(response).await)
     }
 }
 
 /// Get a new whitelist entry
 pub async fn get_whitelist_entry(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
     residential_user_hash: &str,
     whitelist_entry_hash: &str,
-) -> WhitelistEntry {
-    let client = Client::new();
+) -> Result<WhitelistEntry> {
     let url = string_concat!(
-        "https://resi-api.iproyal.com/v1/residential-users/",
+        base_url,
+        "/v1/residential-users/",
         residential_user_hash,
         "/whitelist-entries/",
         whitelist_entry_hash
     );
 
-    match env::var("IP_ROYALE_API_TOKEN") {
-        Ok(api_token) => match client.get(&url).bearer_auth(api_token).send().await {
-            Ok(response) => {
-                if response.status().is_success() {
-                    response.json().await.unwrap_or_default()
-                } else {
-                    println!("Failed to get whitelist entry: {}", response.status());
-                    Default::default()
-                }
-            }
-            Err(err) => {
-                panic!("Request error: {:?}", err);
-            }
-        },
-        Err(e) => {
-            panic!("API Token missing: {:?}", e);
-        }
+    let api_token = credentials::require(credentials, API_TOKEN_KEY)?;
+    let response = client.get(&url).bearer_auth(api_token).send().await?;
+
+    if response.status().is_success() {
+        Ok(response.json().await?)
+    } else {
+        Err(ProxierError::from_response(response).await)
     }
 }
 
 /// Get all the whitelist entries
 pub async fn get_whitelist_entries(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
     residential_user_hash: &str,
     page: Option<u32>,
     per_page: Option<u32>,
-) -> Vec<WhitelistEntry> {
-    let client = Client::new();
+) -> Result<Vec<WhitelistEntry>> {
+    Ok(get_whitelist_entries_page(
+        client,
+        base_url,
+        credentials,
+        residential_user_hash,
+        page,
+        per_page,
+    )
+    .await?
+    .data)
+}
+
+/// Get a page of the whitelist entries with the pagination.
+pub async fn get_whitelist_entries_page(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    residential_user_hash: &str,
+    page: Option<u32>,
+    per_page: Option<u32>,
+) -> Result<WhitelistEntries> {
     let url = string_concat!(
-        "https://resi-api.iproyal.com/v1/residential-users/",
+        base_url,
+        "/v1/residential-users/",
         residential_user_hash,
         "/whitelist-entries"
     );
@@ -129,108 +172,321 @@ pub async fn get_whitelist_entries(
         query_params.push(("per_page", pp.to_string()));
     }
 
-    match dotenv::var("IP_ROYALE_API_TOKEN") {
-        Ok(api_token) => {
-            match client
-                .get(&url)
-                .bearer_auth(api_token)
-                .query(&query_params)
-                .send()
-                .await
+    let api_token = credentials::require(credentials, API_TOKEN_KEY)?;
+    let response = client
+        .get(&url)
+        .bearer_auth(api_token)
+        .query(&query_params)
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        Ok(response.json().await?)
+    } else {
+        Err(ProxierError::from_response(response).await)
+    }
+}
+
+/// The amount of whitelist entries to request per page.
+const PER_PAGE: u32 = 100;
+
+/// Stream every whitelist entry across the pages.
+pub fn whitelist_entries<'a>(
+    client: &'a Client,
+    base_url: &'a str,
+    credentials: &'a dyn CredentialSource,
+    residential_user_hash: &'a str,
+) -> impl Stream<Item = Result<WhitelistEntry>> + Send + 'a {
+    stream::try_unfold(Some(1), move |page| async move {
+        let page = match page {
+            Some(page) => page,
+            _ => return Result::Ok(None),
+        };
+
+        let entries = get_whitelist_entries_page(
+            client,
+            base_url,
+            credentials,
+            residential_user_hash,
+            Some(page),
+            Some(PER_PAGE),
+        )
+        .await?;
+
+        let next = if entries.data.is_empty() || page >= entries.meta.last_page {
+            None
+        } else {
+            Some(page + 1)
+        };
+
+        Ok(Some((stream::iter(entries.data.into_iter().map(Ok)), next)))
+    })
+    .try_flatten()
+}
+
+/// Find the whitelist entry for the ip and port.
+pub async fn find_whitelist_entry(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    residential_user_hash: &str,
+    ip: &str,
+    port: u16,
+) -> Result<Option<WhitelistEntry>> {
+    let entries = whitelist_entries(client, base_url, credentials, residential_user_hash)
+        .try_filter(|entry| future::ready(entry.ip == ip && entry.port == port));
+
+    futures::pin_mut!(entries);
+
+    entries.try_next().await
+}
+
+/// Create a new whitelist entry or get the existing entry for the ip and port on a conflict.
+pub async fn create_or_find_whitelist_entry(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    residential_user_hash: &str,
+    ip: &str,
+    port: u16,
+    configuration: &str,
+) -> Result<(WhitelistEntry, WhitelistStatus)> {
+    match create_whitelist_entry(
+        client,
+        base_url,
+        credentials,
+        residential_user_hash,
+        ip,
+        port,
+        configuration,
+    )
+    .await
+    {
+        Ok(entry) => Ok((entry, WhitelistStatus::Created)),
+        Err(err) if err.is_conflict() => {
+            match find_whitelist_entry(
+                client,
+                base_url,
+                credentials,
+                residential_user_hash,
+                ip,
+                port,
+            )
+            .await?
             {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        response.json().await.unwrap_or_default()
-                    } else {
-                        println!("Failed to get whitelist entries: {}", response.status());
-                        Default::default()
-                    }
-                }
-                Err(err) => {
-                    panic!("Request error: {:?}", err);
-                }
+                Some(entry) => Ok((entry, WhitelistStatus::AlreadyPresent)),
+                _ => Err(err),
             }
         }
-        Err(e) => {
-            panic!("API Token missing: {:?}", e);
-        }
+        Err(err) => Err(err),
     }
 }
 
 /// Update the whitelist entry
 pub async fn update_whitelist_entry(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
     residential_user_hash: &str,
     whitelist_entry_hash: &str,
     configuration: &str,
-) -> WhitelistEntry {
-    let client = Client::new();
+) -> Result<WhitelistEntry> {
     let url = string_concat!(
-        "https://resi-api.iproyal.com/v1/residential-users/",
+        base_url,
+        "/v1/residential-users/",
         residential_user_hash,
         "/whitelist-entries/",
         whitelist_entry_hash
     );
 
-    match dotenv::var("IP_ROYALE_API_TOKEN") {
-        Ok(api_token) => {
-            let body = json!({
-                "configuration": configuration
-            });
-
-            match client
-                .put(&url)
-                .bearer_auth(api_token)
-                .json(&body)
-                .send()
-                .await
-            {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        response.json().await.unwrap_or_default()
-                    } else {
-                        println!("Failed to update whitelist entry: {}", response.status());
-                        Default::default()
-                    }
-                }
-                Err(err) => {
-                    panic!("Request error: {:?}", err);
-                }
-            }
-        }
-        Err(e) => {
-            panic!("API Token missing: {:?}", e);
-        }
+    let api_token = credentials::require(credentials, API_TOKEN_KEY)?;
+    let body = json!({
+        "configuration": configuration
+    });
+
+    let response = client
+        .put(&url)
+        .bearer_auth(api_token)
+        .json(&body)
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        Ok(response.json().await?)
+    } else {
+        Err(ProxierError::from_response(response).await)
     }
 }
 
 /// Delete the whitelist entry
 pub async fn delete_whitelist_entry(
     client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
     residential_user_hash: &str,
     whitelist_entry_hash: &str,
-) {
+) -> Result<()> {
     let url = string_concat!(
-        "https://resi-api.iproyal.com/v1/residential-users/",
+        base_url,
+        "/v1/residential-users/",
         residential_user_hash,
         "/whitelist-entries/",
         whitelist_entry_hash
     );
 
-    match env::var("IP_ROYALE_API_TOKEN") {
-        Ok(api_token) => match client.delete(&url).bearer_auth(api_token).send().await {
-            Ok(response) => {
-                if response.status().is_success() {
-                    println!("Whitelist entry deleted successfully.");
-                } else {
-                    println!("Failed to delete whitelist entry: {}", response.status());
-                }
-            }
-            Err(err) => {
-                println!("Request error: {:?}", err);
+    let api_token = credentials::require(credentials, API_TOKEN_KEY)?;
+    let response = client.delete(&url).bearer_auth(api_token).send().await?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(ProxierError::from_response(response).await)
+    }
+}
+
+impl IPRoyaleConfiguration {
+    /// The api base url to use.
+    pub fn api_url(&self) -> &str {
+        self.base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+    }
+
+    /// The credential source to use.
+    pub fn credentials(&self) -> &dyn CredentialSource {
+        credentials::or_default(&self.credentials)
+    }
+
+    /// Find the existing whitelist entry for the ip and port.
+    async fn find_entry(&self, client: &Client, ip: &str) -> Result<Option<WhitelistEntry>> {
+        find_whitelist_entry(
+            client,
+            self.api_url(),
+            self.credentials(),
+            &self.residential_user_hash,
+            ip,
+            self.port,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl ProxyProvider for IPRoyaleConfiguration {
+    fn name(&self) -> &str {
+        "iproyale"
+    }
+
+    fn account(&self) -> &str {
+        self.account.as_deref().unwrap_or(DEFAULT_ACCOUNT)
+    }
+
+    fn supports_ipv6(&self) -> bool {
+        false
+    }
+
+    async fn whitelist(&mut self, client: &Client, addr: IpAddr) -> Result<WhitelistStatus> {
+        let ip = addr.to_string();
+        let ip = ip.as_str();
+
+        // adopt the existing entry to keep the hash for delisting.
+        let (entry, status) = match self.find_entry(client, ip).await? {
+            Some(entry) => (entry, WhitelistStatus::AlreadyPresent),
+            _ => {
+                create_or_find_whitelist_entry(
+                    client,
+                    self.api_url(),
+                    self.credentials(),
+                    &self.residential_user_hash,
+                    ip,
+                    self.port,
+                    &self.configuration,
+                )
+                .await?
             }
-        },
-        Err(e) => {
-            panic!("API Token missing: {:?}", e);
+        };
+
+        self.whitelist_entries
+            .retain(|tracked| !same_ip(&tracked.ip, addr));
+        self.whitelist_entries.push(entry);
+
+        Ok(status)
+    }
+
+    async fn delist(&mut self, client: &Client, ip: IpAddr) -> Result<DelistStatus> {
+        let mut status = DelistStatus::NotPresent;
+
+        while let Some(pos) = self
+            .whitelist_entries
+            .iter()
+            .position(|tracked| same_ip(&tracked.ip, ip))
+        {
+            delete_whitelist_entry(
+                client,
+                self.api_url(),
+                self.credentials(),
+                &self.residential_user_hash,
+                &self.whitelist_entries[pos].hash,
+            )
+            .await?;
+            self.whitelist_entries.remove(pos);
+            status = DelistStatus::Removed;
         }
+
+        Ok(status)
+    }
+
+    async fn list(&self, client: &Client) -> Result<Vec<ProxyEntry>> {
+        whitelist_entries(
+            client,
+            self.api_url(),
+            self.credentials(),
+            &self.residential_user_hash,
+        )
+        .map_ok(|entry| ProxyEntry {
+            ip: entry.ip,
+            id: Some(entry.hash),
+            ..Default::default()
+        })
+        .try_collect()
+        .await
+    }
+
+    fn entries(&self) -> Vec<ProxyEntry> {
+        self.whitelist_entries
+            .iter()
+            .map(|entry| ProxyEntry {
+                ip: entry.ip.clone(),
+                id: Some(entry.hash.clone()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    async fn remove(&mut self, client: &Client, entry: &ProxyEntry) -> Result<DelistStatus> {
+        let hash = match entry.id.clone() {
+            Some(hash) => hash,
+            _ => match self.find_entry(client, &entry.ip).await? {
+                Some(found) => found.hash,
+                _ => return Ok(DelistStatus::NotPresent),
+            },
+        };
+
+        let status = removed(
+            delete_whitelist_entry(
+                client,
+                self.api_url(),
+                self.credentials(),
+                &self.residential_user_hash,
+                &hash,
+            )
+            .await,
+        )?;
+
+        self.whitelist_entries
+            .retain(|tracked| tracked.hash != hash);
+
+        Ok(status)
     }
 }
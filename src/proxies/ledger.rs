@@ -0,0 +1,257 @@
+use super::{ProxyEntry, Result};
+use fs4::FileExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// A whitelist entry recorded in the ledger.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LedgerEntry {
+    /// The name of the provider.
+    pub provider: String,
+    /// The account on the provider.
+    pub account: String,
+    /// The whitelisted ip address.
+    pub ip: String,
+    /// The provider id of the entry if the service uses one.
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+impl LedgerEntry {
+    /// The entry on the provider.
+    pub fn proxy_entry(&self) -> ProxyEntry {
+        ProxyEntry {
+            ip: self.ip.clone(),
+            id: self.id.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// The ledger file contents.
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+struct LedgerFile {
+    #[serde(default)]
+    entries: Vec<LedgerEntry>,
+}
+
+/// Read the json file, change it and write it back while holding an exclusive lock on a `.lock` file next to it.
+/// A missing or empty file reads as the default and the file is only written when the contents changed.
+pub(crate) fn update_locked<M, T>(path: &Path, f: impl FnOnce(&mut M) -> T) -> Result<T>
+where
+    M: Serialize + DeserializeOwned + Default + Clone + PartialEq,
+{
+    let mut lock_path = path.to_path_buf().into_os_string();
+    lock_path.push(".lock");
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)?;
+
+    // released when the lock file is closed.
+    FileExt::lock(&lock)?;
+
+    let mut contents: M = match fs::read(path) {
+        Ok(contents) if contents.is_empty() => M::default(),
+        Ok(contents) => serde_json::from_slice(&contents)?,
+        Err(err) if err.kind() == ErrorKind::NotFound => M::default(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let before = contents.clone();
+    let out = f(&mut contents);
+
+    if contents != before {
+        write_atomic(path, &serde_json::to_vec_pretty(&contents)?)?;
+    }
+
+    Ok(out)
+}
+
+/// Write the file through a temporary file renamed over it, so a crash leaves either the old or the new contents.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+
+    // a name of its own per write, so concurrent writers never write to or rename each other's file.
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(format!(
+        ".{}.{:016x}.tmp",
+        std::process::id(),
+        super::resolver::random_u64()
+    ));
+    let tmp = PathBuf::from(tmp);
+
+    let written = File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+
+    if let Err(err) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(err.into());
+    }
+
+    // persist the rename itself where the platform allows syncing directories.
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    Ok(())
+}
+
+/// A json file recording the whitelisted entries so they can be delisted after a crash.
+#[derive(Clone, Debug)]
+pub struct Ledger {
+    /// The path of the ledger file.
+    path: PathBuf,
+}
+
+impl Ledger {
+    /// A ledger stored at the path. The file is created on the first write.
+    pub fn new(path: impl Into<PathBuf>) -> Ledger {
+        Ledger { path: path.into() }
+    }
+
+    /// The path of the ledger file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The recorded entries. A missing file has no entries.
+    pub fn entries(&self) -> Result<Vec<LedgerEntry>> {
+        match fs::read(&self.path) {
+            Ok(contents) if contents.is_empty() => Ok(Vec::new()),
+            Ok(contents) => Ok(serde_json::from_slice::<LedgerFile>(&contents)?.entries),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replace the recorded entries. The file is written to a temporary file and renamed over the ledger,
+    /// so a crash leaves either the old or the new entries behind.
+    pub fn save(&self, entries: &[LedgerEntry]) -> Result<()> {
+        let contents = serde_json::to_vec_pretty(&LedgerFile {
+            entries: entries.to_vec(),
+        })?;
+
+        write_atomic(&self.path, &contents)
+    }
+
+    /// Read, change and save the recorded entries while holding the lock of the ledger,
+    /// so the changes of concurrent processes sharing the file are not lost.
+    pub fn update(&self, f: impl FnOnce(&mut Vec<LedgerEntry>)) -> Result<()> {
+        update_locked(&self.path, |file: &mut LedgerFile| f(&mut file.entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_locked_serializes_writers() {
+        let dir = std::env::temp_dir().join(format!("proxier-ledger-{}", std::process::id()));
+        let path = dir.join("counter.json");
+        let _ = fs::remove_dir_all(&dir);
+
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        update_locked(&path, |count: &mut u64| *count += 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(update_locked(&path, |count: &mut u64| *count).unwrap(), 100);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn update_keeps_the_entries_of_concurrent_writers() {
+        let dir =
+            std::env::temp_dir().join(format!("proxier-ledger-update-{}", std::process::id()));
+        let ledger = Ledger::new(dir.join("ledger.json"));
+        let _ = fs::remove_dir_all(&dir);
+
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let ledger = ledger.clone();
+                std::thread::spawn(move || {
+                    for i in 0..25 {
+                        ledger
+                            .update(|entries| {
+                                entries.push(LedgerEntry {
+                                    provider: "webshare".into(),
+                                    account: writer.to_string(),
+                                    ip: format!("203.0.113.{}", i),
+                                    id: None,
+                                })
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(ledger.entries().unwrap().len(), 100);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn write_atomic_writers_do_not_share_the_temporary_file() {
+        let dir =
+            std::env::temp_dir().join(format!("proxier-ledger-atomic-{}", std::process::id()));
+        let path = dir.join("ledger.json");
+        let _ = fs::remove_dir_all(&dir);
+
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        write_atomic(&path, writer.to_string().repeat(4096).as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // the last rename wins whole, and no temporary file is left behind.
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.len(), 4096);
+        assert!(contents
+            .chars()
+            .all(|c| c == contents.chars().next().unwrap()));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
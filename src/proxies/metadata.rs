@@ -0,0 +1,193 @@
+use super::resolver::IpResolver;
+use super::{ProxierError, Result};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// The link local metadata service base url of aws and azure.
+pub const DEFAULT_BASE_URL: &str = "http://169.254.169.254";
+/// The gcp metadata service base url.
+pub const GCP_BASE_URL: &str = "http://metadata.google.internal";
+/// The default timeout for the metadata services, which do not answer outside of the cloud.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The lifetime of the aws session token in seconds.
+const AWS_TOKEN_TTL: &str = "21600";
+
+/// Send the metadata request and read the ip from the text body.
+async fn fetch_ip(provider: &str, request: RequestBuilder) -> Result<IpAddr> {
+    let response = request.send().await?;
+
+    if !response.status().is_success() {
+        return Err(ProxierError::from_response(response).await);
+    }
+
+    let body = response.text().await?;
+
+    body.trim().parse().map_err(|_| {
+        ProxierError::Resolve(format!(
+            "the {} metadata has no public ip, got {:?}",
+            provider,
+            body.trim()
+        ))
+    })
+}
+
+/// Resolve the ip with the aws instance metadata service using an imdsv2 session token.
+#[derive(Clone, Debug)]
+pub struct AwsMetadataResolver {
+    /// The metadata base url. Defaults to the link local address.
+    pub base_url: Option<String>,
+    /// How long to wait for each request.
+    pub timeout: Duration,
+}
+
+impl Default for AwsMetadataResolver {
+    fn default() -> Self {
+        AwsMetadataResolver {
+            base_url: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl AwsMetadataResolver {
+    /// The metadata base url to use.
+    pub fn api_url(&self) -> &str {
+        self.base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+    }
+}
+
+#[async_trait]
+impl IpResolver for AwsMetadataResolver {
+    fn name(&self) -> &str {
+        "aws metadata"
+    }
+
+    async fn resolve(&self, client: &Client) -> Result<IpAddr> {
+        let response = client
+            .put(format!("{}/latest/api/token", self.api_url()))
+            .header("X-aws-ec2-metadata-token-ttl-seconds", AWS_TOKEN_TTL)
+            .timeout(self.timeout)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ProxierError::from_response(response).await);
+        }
+
+        let token = response.text().await?;
+
+        fetch_ip(
+            "aws",
+            client
+                .get(format!("{}/latest/meta-data/public-ipv4", self.api_url()))
+                .header("X-aws-ec2-metadata-token", token.trim())
+                .timeout(self.timeout),
+        )
+        .await
+    }
+}
+
+/// Resolve the ip with the gcp metadata server from the external ip of the first network interface.
+#[derive(Clone, Debug)]
+pub struct GcpMetadataResolver {
+    /// The metadata base url. Defaults to `metadata.google.internal`.
+    pub base_url: Option<String>,
+    /// How long to wait for the request.
+    pub timeout: Duration,
+}
+
+impl Default for GcpMetadataResolver {
+    fn default() -> Self {
+        GcpMetadataResolver {
+            base_url: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl GcpMetadataResolver {
+    /// The metadata base url to use.
+    pub fn api_url(&self) -> &str {
+        self.base_url
+            .as_deref()
+            .unwrap_or(GCP_BASE_URL)
+            .trim_end_matches('/')
+    }
+}
+
+#[async_trait]
+impl IpResolver for GcpMetadataResolver {
+    fn name(&self) -> &str {
+        "gcp metadata"
+    }
+
+    async fn resolve(&self, client: &Client) -> Result<IpAddr> {
+        fetch_ip(
+            "gcp",
+            client
+                .get(format!(
+                    "{}/computeMetadata/v1/instance/network-interfaces/0/access-configs/0/external-ip",
+                    self.api_url()
+                ))
+                .header("Metadata-Flavor", "Google")
+                .timeout(self.timeout),
+        )
+        .await
+    }
+}
+
+/// Resolve the ip with the azure instance metadata service from the public ip of the first network interface.
+#[derive(Clone, Debug)]
+pub struct AzureMetadataResolver {
+    /// The metadata base url. Defaults to the link local address.
+    pub base_url: Option<String>,
+    /// How long to wait for the request.
+    pub timeout: Duration,
+}
+
+impl Default for AzureMetadataResolver {
+    fn default() -> Self {
+        AzureMetadataResolver {
+            base_url: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl AzureMetadataResolver {
+    /// The metadata base url to use.
+    pub fn api_url(&self) -> &str {
+        self.base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+    }
+}
+
+#[async_trait]
+impl IpResolver for AzureMetadataResolver {
+    fn name(&self) -> &str {
+        "azure metadata"
+    }
+
+    async fn resolve(&self, client: &Client) -> Result<IpAddr> {
+        fetch_ip(
+            "azure",
+            client
+                .get(format!(
+                    "{}/metadata/instance/network/interface/0/ipv4/ipAddress/0/publicIpAddress",
+                    self.api_url()
+                ))
+                .query(&[("api-version", "2021-02-01"), ("format", "text")])
+                .header("Metadata", "true")
+                .timeout(self.timeout),
+        )
+        .await
+    }
+}
@@ -1,14 +1,73 @@
+/// declarative configuration files
+pub mod config;
+/// credential sources
+pub mod credentials;
 /// datainpulse
 pub mod datainpulse;
+/// local egress ip discovery
+pub mod egress;
+/// leader election across replicas
+pub mod election;
+/// the proxier errors
+pub mod error;
 /// use evomi
 pub mod evomi;
+/// shared nat holder counting
+pub mod holders;
+/// server ips and cidr ranges
+pub mod ip;
 /// iproyal
 pub mod iproyale;
+/// crash safe whitelist ledger
+pub mod ledger;
+/// cloud instance metadata resolvers
+pub mod metadata;
+/// proxy provider trait
+pub mod provider;
+/// orphaned entry pruning
+pub mod prune;
+/// minimal redis client
+mod redis;
+/// whitelist reports
+pub mod report;
+/// public ip resolvers
+pub mod resolver;
+/// stun binding requests
+pub mod stun;
+/// public ip change watcher
+pub mod watch;
 /// webshare proxy
 pub mod webshare;
 
+pub use config::{ConfigFormat, ProxierConfig, ResolverConfig};
+pub use credentials::{
+    CredentialSource, DotenvCredentials, EnvCredentials, FileCredentials, MappedCredentials,
+    StaticCredentials,
+};
+pub use egress::{EgressAddr, EgressKind, EgressReport, LocalAddr};
+use election::Role;
+pub use election::{Election, FileLeaseStore, LeaseStore, MemoryLeaseStore, RedisLeaseStore};
+pub use error::{ProxierError, Result};
+use futures::future::join_all;
+pub use holders::{FileHolderStore, HolderStore, Holders, MemoryHolderStore, RedisHolderStore};
+use ip::IpNet;
 use iproyale::WhitelistEntry;
+pub use ledger::{Ledger, LedgerEntry};
+pub use metadata::{AwsMetadataResolver, AzureMetadataResolver, GcpMetadataResolver};
+pub use provider::{ProxyEntry, ProxyProvider, DEFAULT_ACCOUNT};
+pub use prune::{PruneOptions, PruneOutcome, PruneReport, PrunedEntry};
+pub use report::{DelistStatus, ProviderOutcome, ProviderReport, WhitelistReport, WhitelistStatus};
 use reqwest::Client;
+pub use resolver::{
+    DnsRecord, DnsResolver, HttpEchoResolver, IpResolver, IpResolvers, StunResolver,
+    WebshareResolver,
+};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+pub use stun::StunBinding;
+pub use watch::{IpChange, IpEvent, IpWatcher};
 use webshare::ProxyIP;
 
 /// The ip royale configuration for the proxy.
@@ -20,50 +79,233 @@ pub struct IPRoyaleConfiguration {
     pub port: u16,
     /// The configuration type
     pub configuration: String,
-    /// The proxy ip results when whitelisting
-    pub whitelist_entry: Option<WhitelistEntry>,
+    /// The proxy ip results for each whitelisted ip
+    pub whitelist_entries: Vec<WhitelistEntry>,
+    /// The api base url. Defaults to the iproyal api.
+    pub base_url: Option<String>,
+    /// The credential source. Defaults to the env and `.env` file.
+    pub credentials: Option<Arc<dyn CredentialSource>>,
+    /// The account name when using multiple accounts of the provider.
+    pub account: Option<String>,
 }
 
 /// The ip royale configuration for the proxy.
 #[derive(Default, Clone, Debug)]
 pub struct WebShareConfiguration {
-    /// The proxy ip results when whitelisting
-    pub whitelist_entry: Option<ProxyIP>,
+    /// The proxy ip results for each whitelisted ip
+    pub whitelist_entries: Vec<ProxyIP>,
+    /// The api base url. Defaults to the webshare api.
+    pub base_url: Option<String>,
+    /// The credential source. Defaults to the env and `.env` file.
+    pub credentials: Option<Arc<dyn CredentialSource>>,
+    /// The account name when using multiple accounts of the provider.
+    pub account: Option<String>,
 }
 
 /// The datainpulse configuration for the proxy.
 #[derive(Default, Clone, Debug)]
-pub struct DatainpulseConfiguration(bool);
+pub struct DatainpulseConfiguration {
+    /// The whitelisted ip results for each whitelisted ip
+    pub whitelist_entries: Vec<datainpulse::WhitelistIp>,
+    /// The api base url. Defaults to the datainpulse api.
+    pub base_url: Option<String>,
+    /// The credential source. Defaults to the env and `.env` file.
+    pub credentials: Option<Arc<dyn CredentialSource>>,
+    /// The account name when using multiple accounts of the provider.
+    pub account: Option<String>,
+}
 
 /// The evomi configuration for the proxy.
 #[derive(Default, Clone, Debug)]
-pub struct EvomiConfiguration(bool);
+pub struct EvomiConfiguration {
+    /// The products to whitelist the server ip on. Defaults to residential.
+    pub products: Vec<evomi::EvomiProduct>,
+    /// The whitelisted ip results per product when whitelisting
+    pub whitelist_entries: Vec<evomi::WhitelistEntry>,
+    /// The api base url. Defaults to the evomi api.
+    pub base_url: Option<String>,
+    /// The credential source. Defaults to the env and `.env` file.
+    pub credentials: Option<Arc<dyn CredentialSource>>,
+    /// The account name when using multiple accounts of the provider.
+    pub account: Option<String>,
+}
 
 /// The proxy service you want to use.
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Debug)]
 pub struct Proxier {
-    /// Iproyale service.
-    pub iproyale: Option<IPRoyaleConfiguration>,
-    /// Webshare service.
-    pub webshare: Option<WebShareConfiguration>,
-    /// Datainpulse service residential proxies.
-    pub datainpulse: Option<DatainpulseConfiguration>,
-    /// Evomi service proxies.
-    pub evomi: Option<EvomiConfiguration>,
+    /// The registered proxy providers.
+    pub providers: Vec<Box<dyn ProxyProvider>>,
     /// The shared client.
     pub client: Client,
-    /// The server ip NAT to whitelist.
-    pub server_ip: String,
+    /// The server ip NAT to whitelist. Resolved when not set.
+    pub server_ip: Option<IpAddr>,
+    /// More server ips and cidr ranges whitelisted along with the server ip, for example other nat gateways.
+    pub server_ips: Vec<IpNet>,
+    /// The overall deadline for whitelisting and delisting across the providers, and for waiting on the leader of an election.
+    pub deadline: Option<Duration>,
+    /// The ip echo service used to find the server ip. Defaults to ipify.
+    pub ip_echo_url: Option<String>,
+    /// The ledger recording the whitelisted entries on disk.
+    pub ledger: Option<Ledger>,
+    /// The resolvers to find the server ip with. Defaults to webshare and then the ip echo service.
+    pub resolvers: Option<IpResolvers>,
+    /// The holders of the server ips shared with other replicas behind the same nat. An ip is only delisted by the last holder.
+    pub holders: Option<Holders>,
+    /// The election of the replica that whitelists and prunes for the replicas behind the same nat.
+    pub election: Option<Election>,
+    /// The previous server ips of the moves that did not finish, retried by the next `refresh_server_ip`.
+    retry: Option<BTreeSet<IpAddr>>,
 }
 
 impl Proxier {
-    /// A new proxier setup.
-    pub fn new(server_ip: &str) -> Proxier {
+    /// A new proxier setup. Pass `None` to resolve the server ip.
+    pub fn new(server_ip: impl Into<Option<IpAddr>>) -> Proxier {
         Proxier {
             server_ip: server_ip.into(),
             ..Default::default()
         }
     }
+
+    /// Load the proxier from a toml, yaml or json configuration file. Resolve the server ip after if it is not set.
+    pub fn from_config_file(path: impl AsRef<std::path::Path>) -> Result<Proxier> {
+        Ok(ProxierConfig::from_file(path)?.build())
+    }
+
+    /// Resolve the server ip if it is not set, using the first registered webshare account first.
+    pub async fn resolve_server_ip(&mut self) -> Result<IpAddr> {
+        if let Some(server_ip) = self.server_ip {
+            return Ok(server_ip);
+        }
+
+        let server_ip = self.lookup_ip(self.provider("webshare")).await?;
+        self.server_ip = Some(server_ip);
+        Ok(server_ip)
+    }
+
+    /// Resolve the server ip if it is not set using the webshare account first.
+    async fn resolve_server_ip_with(&mut self, webshare: &WebShareConfiguration) -> Result<IpAddr> {
+        match self.server_ip {
+            Some(server_ip) => Ok(server_ip),
+            _ => {
+                let server_ip = self.lookup_ip(Some(webshare)).await?;
+                self.server_ip = Some(server_ip);
+                Ok(server_ip)
+            }
+        }
+    }
+
+    /// Look up the current egress ip with the resolvers or using the webshare account first.
+    /// Without an account the default webshare configuration is tried. Anything but a public address is rejected.
+    async fn lookup_ip(&self, webshare: Option<&dyn ProxyProvider>) -> Result<IpAddr> {
+        if let Some(resolvers) = self.resolvers.as_ref() {
+            return ip::validate_public(resolvers.resolve(&self.client).await?);
+        }
+
+        // try to get the ip via webshare or other services.
+        let ip_echo_url = self
+            .ip_echo_url
+            .as_deref()
+            .unwrap_or(webshare::DEFAULT_IP_ECHO_URL);
+
+        let default = WebShareConfiguration::default();
+        let webshare = webshare.unwrap_or(&default);
+
+        let server_ip = match webshare.lookup_ip(&self.client).await {
+            Some(Ok(server_ip)) => server_ip,
+            _ => webshare::get_local_ip(&self.client, ip_echo_url).await?,
+        };
+
+        ip::parse_public(&server_ip)
+    }
+
+    /// The server ips and ranges to whitelist. Missing or non public addresses are rejected before calling the providers.
+    fn public_server_ips(&self) -> Result<Vec<IpNet>> {
+        let mut server_ips: Vec<IpNet> = Vec::new();
+
+        for net in self
+            .server_ip
+            .map(IpNet::from)
+            .into_iter()
+            .chain(self.server_ips.iter().copied())
+        {
+            if !server_ips.contains(&net) {
+                server_ips.push(ip::validate_public_net(net)?);
+            }
+        }
+
+        if server_ips.is_empty() {
+            return Err(ProxierError::InvalidIp(String::new()));
+        }
+
+        Ok(server_ips)
+    }
+
+    /// Whitelist another server ip or cidr range along with the server ip.
+    pub fn add_server_ip(&mut self, ip: impl Into<IpNet>) -> &mut Self {
+        self.server_ips.push(ip.into());
+        self
+    }
+
+    /// Set the overall deadline for whitelisting and delisting. Providers that do not finish in time are reported as timed out.
+    pub fn with_deadline(&mut self, deadline: Duration) -> &mut Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Find the server ip with the resolvers instead of webshare and the ip echo service.
+    pub fn with_resolvers(&mut self, resolvers: IpResolvers) -> &mut Self {
+        self.resolvers = Some(resolvers);
+        self
+    }
+
+    /// Record the whitelisted entries in a ledger file to delist them with `recover` after a crash.
+    pub fn with_ledger(&mut self, path: impl Into<std::path::PathBuf>) -> &mut Self {
+        self.ledger = Some(Ledger::new(path));
+        self
+    }
+
+    /// Count the replicas holding each server ip in a shared store, so delisting leaves the ips other replicas still hold.
+    pub fn with_holders(&mut self, holders: Holders) -> &mut Self {
+        self.holders = Some(holders);
+        self
+    }
+
+    /// Elect a single replica to whitelist and prune, while the others wait for and follow its outcome.
+    pub fn with_election(&mut self, election: Election) -> &mut Self {
+        self.election = Some(election);
+        self
+    }
+
+    /// Register a proxy provider.
+    pub fn register<P: ProxyProvider + 'static>(&mut self, provider: P) -> &mut Self {
+        self.providers.push(Box::new(provider));
+        self
+    }
+
+    /// Get the first registered provider by name.
+    pub fn provider(&self, name: &str) -> Option<&dyn ProxyProvider> {
+        self.providers
+            .iter()
+            .find(|p| p.name() == name)
+            .map(|p| p.as_ref())
+    }
+
+    /// Get a registered provider by name and account.
+    pub fn provider_account(&self, name: &str, account: &str) -> Option<&dyn ProxyProvider> {
+        self.providers
+            .iter()
+            .find(|p| p.name() == name && p.account() == account)
+            .map(|p| p.as_ref())
+    }
+
+    /// All of the registered accounts for a provider.
+    pub fn accounts<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a dyn ProxyProvider> {
+        self.providers
+            .iter()
+            .filter(move |p| p.name() == name)
+            .map(|p| p.as_ref())
+    }
+
     /// Setup all of the proxies needed.
     pub async fn setup_proxies(
         &mut self,
@@ -71,107 +313,567 @@ impl Proxier {
         webshare: Option<WebShareConfiguration>,
         datainpulse: Option<DatainpulseConfiguration>,
         evomi: Option<EvomiConfiguration>,
-    ) {
-        if self.server_ip.is_empty() {
-            // try to get the ip via webshare or other services.
-            self.server_ip = webshare::get_ip(&self.client).await;
+    ) -> Result<()> {
+        self.resolve_server_ip_with(webshare.as_ref().unwrap_or(&Default::default()))
+            .await?;
+
+        if let Some(webshare) = webshare {
+            self.register(webshare);
+        }
+        if let Some(datainpulse) = datainpulse {
+            self.register(datainpulse);
         }
-        self.iproyale = iproyale;
-        self.webshare = webshare;
-        self.datainpulse = datainpulse;
-        self.evomi = evomi;
+        if let Some(iproyale) = iproyale {
+            self.register(iproyale);
+        }
+        if let Some(evomi) = evomi {
+            self.register(evomi);
+        }
+        Ok(())
+    }
+
+    /// Whitelist the server ips and ranges on all of the providers concurrently.
+    /// The ips are held before whitelisting when the holders are set.
+    /// With the election set, only the leader calls the providers and the followers report its outcome.
+    pub async fn whitelist(&mut self) -> WhitelistReport {
+        let held = self.ledger_entries();
+        let holders = self.acquire().await;
+        let role = self.elect("whitelist").await;
+
+        let mut report = match &role {
+            Role::Follower(published) => WhitelistReport {
+                providers: published.providers(),
+                ..Default::default()
+            },
+            Role::TimedOut(_) => WhitelistReport {
+                providers: self.timed_out(),
+                ..Default::default()
+            },
+            _ => {
+                let (intents, intended) =
+                    match self.intend(&self.public_server_ips().unwrap_or_default()) {
+                        Ok(intents) => (intents, None),
+                        Err(err) => (Vec::new(), Some(err)),
+                    };
+                let mut report = self.drive(false, &[]).await;
+                report.ledger = intended.or(self.record(&held, &intents).err());
+                report
+            }
+        };
+
+        report.holders = holders.err();
+        report.election = self
+            .lead(role, election::published(&report.providers))
+            .await;
+        report
     }
 
-    /// Whitelist the server ips all at once.
-    pub async fn whitelist(&mut self) {
-        self.whitelist_webshare().await;
-        self.whitelist_datainpulse().await;
-        self.whitelist_iproyale().await;
-        self.whitelist_evomi().await;
+    /// Delist all the proxy entries for the server ips and ranges on all of the providers concurrently.
+    /// With the holders set, the ips other replicas still hold are retained, and nothing is delisted when the store fails.
+    /// With the election set, the published whitelist outcome is dropped. With the holders set too, the last holder
+    /// lists and removes the entries another replica created for the ips, since only the leader tracked them.
+    pub async fn delist(&mut self) -> WhitelistReport {
+        let retained = match self.release().await {
+            Ok(retained) => retained,
+            Err(err) => {
+                let mut report = WhitelistReport::default();
+
+                for provider in &self.providers {
+                    report.push(
+                        provider.name(),
+                        provider.account(),
+                        ProviderOutcome::Failed(holders::failed(&err)),
+                    );
+                }
+
+                report.holders = Some(err);
+                return report;
+            }
+        };
+
+        let held = self.ledger_entries();
+        let mut report = self.drive(true, &retained).await;
+        report.ledger = self.record(&held, &[]).err();
+        // the replicas starting after elect a leader to whitelist again.
+        report.election = self.forget("whitelist").await;
+        report
     }
 
-    /// Whitelist datainpulse.
-    pub async fn whitelist_datainpulse(&mut self) {
-        if let Some(datainpulse) = self.datainpulse.as_mut() {
-            datainpulse::create_whitelist_entry(&self.client, &self.server_ip).await;
-            datainpulse.0 = true;
+    /// Hold the server ips in the shared store.
+    async fn acquire(&self) -> Result<()> {
+        let holders = match self.holders.as_ref() {
+            Some(holders) => holders,
+            _ => return Ok(()),
+        };
+
+        for net in self.public_server_ips().unwrap_or_default() {
+            holders.acquire(&net.to_string()).await?;
         }
+
+        Ok(())
     }
 
-    /// Whitelist evomi. May update at anytime.
-    pub async fn whitelist_evomi(&mut self) {
-        if let Some(evomi) = self.evomi.as_mut() {
-            evomi::setup_proxy(&self.client, &self.server_ip, false).await;
-            evomi.0 = true;
-        }
+    /// Extend the holds of the server ips, so they do not expire while this replica runs.
+    /// The ip watcher refreshes the holds on every check.
+    pub async fn refresh_holds(&self) -> Result<()> {
+        self.acquire().await
     }
 
-    /// Whitelist webshare.
-    pub async fn whitelist_webshare(&mut self) {
-        if let Some(webshare) = self.webshare.as_mut() {
-            let proxy_results = webshare::setup_proxy(&self.client, &self.server_ip, false).await;
-            let _ = webshare.whitelist_entry.insert(proxy_results);
+    /// Release the server ips in the shared store. Returns the ips other replicas still hold with the amount of holders.
+    async fn release(&self) -> Result<Vec<(IpNet, usize)>> {
+        let holders = match self.holders.as_ref() {
+            Some(holders) => holders,
+            _ => return Ok(Vec::new()),
+        };
+
+        let mut retained = Vec::new();
+
+        for net in self.public_server_ips().unwrap_or_default() {
+            match holders.release(&net.to_string()).await? {
+                0 => (),
+                count => retained.push((net, count)),
+            }
         }
+
+        Ok(retained)
     }
 
-    /// Whitelist webshare.
-    pub async fn whitelist_iproyale(&mut self) {
-        if let Some(iproyale) = self.iproyale.as_mut() {
-            let proxy_results = iproyale::create_whitelist_entry(
-                &self.client,
-                &iproyale.residential_user_hash,
-                &self.server_ip,
-                iproyale.port,
-                &iproyale.configuration,
-            )
-            .await;
-            let _ = iproyale.whitelist_entry.insert(proxy_results);
+    /// Delist the entries left in the ledger by a previous run. Entries that can not be removed stay in the ledger.
+    /// With the holders set, the holds of the previous run are released and the entries other replicas hold are left.
+    pub async fn recover(&mut self) -> Result<WhitelistReport> {
+        let ledger = match self.ledger.as_ref() {
+            Some(ledger) => ledger.clone(),
+            _ => return Ok(WhitelistReport::default()),
+        };
+
+        let held = self.ledger_entries();
+        let retained = self.release().await?;
+        let mut report = WhitelistReport::default();
+        let mut remaining = Vec::new();
+
+        for entry in ledger.entries()? {
+            // entries held by this run are not leftovers.
+            if held.contains(&entry) {
+                remaining.push(entry);
+                continue;
+            }
+
+            if let Some(holders) = self.holders.as_ref() {
+                let count = match retained.iter().find(|(net, _)| {
+                    entry.ip == net.to_string() || entry.ip.parse().is_ok_and(|ip| net.contains(ip))
+                }) {
+                    Some((_, count)) => *count,
+                    _ => holders.release(&entry.ip).await?,
+                };
+
+                // the entry is no longer ours to remove.
+                if count > 0 {
+                    report.push(
+                        &entry.provider,
+                        &entry.account,
+                        ProviderOutcome::Retained(count),
+                    );
+                    continue;
+                }
+            }
+
+            let provider = self
+                .providers
+                .iter_mut()
+                .find(|p| p.name() == entry.provider && p.account() == entry.account);
+
+            let outcome = match provider {
+                Some(provider) => {
+                    ProviderOutcome::from(provider.remove(&self.client, &entry.proxy_entry()).await)
+                }
+                _ => ProviderOutcome::Failed(ProxierError::Unsupported(format!(
+                    "no registered provider for {} account {}",
+                    entry.provider, entry.account
+                ))),
+            };
+
+            // entries the provider can not remove would only fail again.
+            if !matches!(
+                outcome,
+                ProviderOutcome::Removed
+                    | ProviderOutcome::NotPresent
+                    | ProviderOutcome::Unsupported(_)
+            ) {
+                remaining.push(entry.clone());
+            }
+
+            report.push(&entry.provider, &entry.account, outcome);
         }
+
+        ledger.save(&remaining)?;
+
+        Ok(report)
     }
 
-    /// Delist all the proxy entries.
-    pub async fn delist(&mut self) {
-        self.delist_webshare().await;
-        self.delist_datainpulse().await;
-        self.delist_iproyale().await;
-        self.delist_evomi().await;
+    /// Remove the whitelist entries on every provider that are not for the server ips or the allowlist.
+    /// With the election set, only the leader prunes and the followers report its outcome. Dry runs are not elected.
+    pub async fn prune(&mut self, options: &PruneOptions) -> Result<PruneReport> {
+        // without a server ip every entry would be pruned.
+        self.public_server_ips()?;
+
+        let role = if options.dry_run {
+            Role::Alone
+        } else {
+            self.elect("prune").await
+        };
+
+        let mut report = match &role {
+            Role::Follower(published) => PruneReport {
+                providers: published.providers(),
+                ..Default::default()
+            },
+            Role::TimedOut(_) => PruneReport {
+                providers: self.timed_out(),
+                ..Default::default()
+            },
+            _ => self.prune_entries(options).await?,
+        };
+
+        report.election = self.lead(role, self.pruned(&report)).await;
+        Ok(report)
     }
 
-    /// Delist a dataipnulse entry for whitelisting.
-    pub async fn delist_datainpulse(&mut self) {
-        if let Some(_) = self.datainpulse.take() {
-            datainpulse::delete_whitelist_entry(&self.client, &self.server_ip).await;
+    /// Remove the entries not kept by the options on every provider.
+    async fn prune_entries(&mut self, options: &PruneOptions) -> Result<PruneReport> {
+        let server_ips = self.public_server_ips()?;
+        let server_ips = &server_ips[..];
+        let held = self.ledger_entries();
+        let client = &self.client;
+        let holders = self.holders.as_ref();
+        let deadline = self.deadline;
+        let now = chrono::Utc::now();
+
+        let calls = self.providers.iter_mut().map(|provider| async move {
+            let mut pruned = Vec::new();
+
+            let call = async {
+                let entries = provider.list(client).await?;
+
+                for entry in entries {
+                    if options.keeps(server_ips, &entry, now) {
+                        continue;
+                    }
+
+                    // other replicas may still use the ip, a failing store keeps the entry too.
+                    if let Some(holders) = holders {
+                        if !matches!(holders.store.count(&entry.ip).await, Ok(0)) {
+                            continue;
+                        }
+                    }
+
+                    let outcome = if options.dry_run {
+                        PruneOutcome::WouldRemove
+                    } else {
+                        match provider.remove(client, &entry).await {
+                            Ok(DelistStatus::Removed) => PruneOutcome::Removed,
+                            Ok(DelistStatus::NotPresent) => PruneOutcome::NotPresent,
+                            Err(err) => PruneOutcome::Failed(err),
+                        }
+                    };
+
+                    pruned.push(PrunedEntry {
+                        provider: provider.name().to_string(),
+                        account: provider.account().to_string(),
+                        entry,
+                        outcome,
+                    });
+                }
+
+                Result::Ok(())
+            };
+
+            let listed = match deadline {
+                Some(deadline) => tokio::time::timeout(deadline, call).await.ok(),
+                _ => Some(call.await),
+            };
+
+            let outcome = match listed {
+                Some(Ok(())) => None,
+                Some(Err(err)) => Some(ProviderOutcome::from(Err::<DelistStatus, _>(err))),
+                _ => Some(ProviderOutcome::TimedOut),
+            };
+
+            let report = outcome.map(|outcome| ProviderReport {
+                provider: provider.name().to_string(),
+                account: provider.account().to_string(),
+                ip: None,
+                outcome,
+            });
+
+            (report, pruned)
+        });
+
+        let mut report = PruneReport::default();
+
+        for (provider, pruned) in join_all(calls).await {
+            report.entries.extend(pruned);
+            report.providers.extend(provider);
+        }
+
+        if !options.dry_run {
+            let released: Vec<LedgerEntry> = report
+                .removed()
+                .map(|pruned| LedgerEntry {
+                    provider: pruned.provider.clone(),
+                    account: pruned.account.clone(),
+                    ip: pruned.entry.ip.clone(),
+                    id: pruned.entry.id.clone(),
+                })
+                .collect();
+
+            report.ledger = self.record(&held, &released).err();
         }
+
+        Ok(report)
     }
 
-    /// Delist a evomi entry for whitelisting.
-    pub async fn delist_evomi(&mut self) {
-        if let Some(evomi) = self.evomi.as_mut() {
-            evomi::setup_proxy(&self.client, &self.server_ip, true).await;
-            evomi.0 = true;
+    /// The entries held by the registered providers.
+    fn ledger_entries(&self) -> Vec<LedgerEntry> {
+        if self.ledger.is_none() {
+            return Vec::new();
         }
+
+        self.providers
+            .iter()
+            .flat_map(|provider| {
+                provider.entries().into_iter().map(|entry| LedgerEntry {
+                    provider: provider.name().to_string(),
+                    account: provider.account().to_string(),
+                    ip: entry.ip,
+                    id: entry.id,
+                })
+            })
+            .collect()
     }
 
-    /// Delist a webshare entry for whitelisting.
-    pub async fn delist_webshare(&mut self) {
-        if let Some(webshare) = self.webshare.as_mut() {
-            if let Some(whitelist_entry) = webshare.whitelist_entry.as_mut().take() {
-                webshare::setup_proxy(&self.client, &whitelist_entry.id.to_string(), true).await;
+    /// Record the entries about to be whitelisted on the ips before calling the providers,
+    /// so a crash in between leaves them to `recover`, which removes them by ip.
+    /// Returns the entries that were not recorded yet, dropped once the providers report their own entries.
+    fn intend(&self, ips: &[IpNet]) -> Result<Vec<LedgerEntry>> {
+        let ledger = match self.ledger.as_ref() {
+            Some(ledger) => ledger,
+            _ => return Ok(Vec::new()),
+        };
+
+        let intents: Vec<LedgerEntry> = self
+            .providers
+            .iter()
+            .flat_map(|provider| {
+                targets(provider.as_ref(), ips)
+                    .into_iter()
+                    .filter(|net| net.is_single() || provider.supports_cidr())
+                    .filter(|net| net.addr().is_ipv4() || provider.supports_ipv6())
+                    .map(|net| LedgerEntry {
+                        provider: provider.name().to_string(),
+                        account: provider.account().to_string(),
+                        ip: net.to_string(),
+                        id: None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let mut added = Vec::new();
+
+        ledger.update(|entries| {
+            for intent in intents {
+                if !entries.contains(&intent) {
+                    entries.push(intent.clone());
+                    added.push(intent);
+                }
             }
-        }
+        })?;
+
+        Ok(added)
     }
 
-    /// Delist a iproyale entry for whitelisting.
-    pub async fn delist_iproyale(&mut self) {
-        if let Some(iproyale) = self.iproyale.as_mut() {
-            if let Some(whitelist_entry) = iproyale.whitelist_entry.as_mut().take() {
-                iproyale::delete_whitelist_entry(
-                    &self.client,
-                    &iproyale.residential_user_hash,
-                    &whitelist_entry.hash,
-                )
-                .await
+    /// Update the ledger with the entries the providers gained or released since the held snapshot.
+    fn record(&self, held: &[LedgerEntry], released: &[LedgerEntry]) -> Result<()> {
+        let ledger = match self.ledger.as_ref() {
+            Some(ledger) => ledger,
+            _ => return Ok(()),
+        };
+
+        let current = self.ledger_entries();
+
+        ledger.update(|entries| {
+            entries.retain(|entry| {
+                (!held.contains(entry) || current.contains(entry)) && !released.contains(entry)
+            });
+
+            for entry in current {
+                if !entries.contains(&entry) {
+                    entries.push(entry);
+                }
             }
+        })
+    }
+
+    /// Run the whitelist or delist calls for every server ip on every provider within the deadline.
+    /// The retained ips are reported without calling the providers.
+    async fn drive(&mut self, remove: bool, retained: &[(IpNet, usize)]) -> WhitelistReport {
+        let mut report = WhitelistReport::default();
+
+        let server_ips = match self.public_server_ips() {
+            Ok(server_ips) => server_ips,
+            Err(err) => {
+                let invalid = match err {
+                    ProxierError::InvalidIp(ip) => ip,
+                    _ => String::new(),
+                };
+
+                for provider in &self.providers {
+                    report.push(
+                        provider.name(),
+                        provider.account(),
+                        ProviderOutcome::Failed(ProxierError::InvalidIp(invalid.clone())),
+                    );
+                }
+                return report;
+            }
+        };
+
+        let server_ips: Vec<IpNet> = server_ips
+            .into_iter()
+            .filter(|net| !retained.iter().any(|(held, _)| held == net))
+            .collect();
+
+        let client = &self.client;
+        let server_ips = &server_ips[..];
+        let deadline = self.deadline;
+        // the followers of an election never tracked the entries of the leader, so the last holder lists them.
+        // only the ips no other replica holds are left here, without the holders nothing says this replica is the last.
+        let listed = remove && self.election.is_some() && self.holders.is_some();
+
+        let calls = self.providers.iter_mut().map(|provider| async move {
+            let targets = targets(provider.as_ref(), server_ips);
+            let mut outcomes = Vec::with_capacity(targets.len());
+
+            let call = async {
+                for net in &targets {
+                    let outcome = match call(provider, client, *net, remove).await {
+                        ProviderOutcome::NotPresent if listed => {
+                            remove_listed(provider, client, *net).await
+                        }
+                        outcome => outcome,
+                    };
+                    outcomes.push(outcome);
+                }
+            };
+
+            match deadline {
+                Some(deadline) => {
+                    let _ = tokio::time::timeout(deadline, call).await;
+                }
+                _ => call.await,
+            };
+
+            // the ips left when the deadline passed are timed out.
+            let outcomes = outcomes
+                .into_iter()
+                .chain(std::iter::repeat_with(|| ProviderOutcome::TimedOut));
+
+            let retained = retained
+                .iter()
+                .map(|(net, count)| (net, ProviderOutcome::Retained(*count)));
+
+            targets
+                .iter()
+                .zip(outcomes)
+                .chain(retained)
+                .map(|(net, outcome)| ProviderReport {
+                    provider: provider.name().to_string(),
+                    account: provider.account().to_string(),
+                    ip: Some(net.to_string()),
+                    outcome,
+                })
+                .collect::<Vec<_>>()
+        });
+
+        report
+            .providers
+            .extend(join_all(calls).await.into_iter().flatten());
+
+        report
+    }
+}
+
+/// The ips and ranges to call the provider with, expanding the ranges it can not whitelist natively.
+/// Ranges larger than `ip::MAX_EXPANDED_IPS` are kept to be reported as unsupported.
+fn targets(provider: &dyn ProxyProvider, server_ips: &[IpNet]) -> Vec<IpNet> {
+    let mut targets = Vec::new();
+    let mut push = |net| {
+        if !targets.contains(&net) {
+            targets.push(net);
+        }
+    };
+
+    for net in server_ips {
+        if net.is_single() || provider.supports_cidr() || net.size() > ip::MAX_EXPANDED_IPS {
+            push(*net);
+        } else {
+            net.hosts().map(IpNet::from).for_each(&mut push);
+        }
+    }
+
+    targets
+}
+
+/// Remove the entries the provider lists for the ip or range, such as the ones another replica created.
+async fn remove_listed(
+    provider: &mut Box<dyn ProxyProvider>,
+    client: &Client,
+    net: IpNet,
+) -> ProviderOutcome {
+    let entries = match provider.list(client).await {
+        Ok(entries) => entries,
+        Err(err) => return ProviderOutcome::from(Err::<DelistStatus, _>(err)),
+    };
+
+    let mut outcome = ProviderOutcome::NotPresent;
+
+    for entry in entries.iter().filter(|entry| {
+        entry.ip == net.to_string() || (net.is_single() && provider::same_ip(&entry.ip, net.addr()))
+    }) {
+        match provider.remove(client, entry).await {
+            Ok(DelistStatus::Removed) => outcome = ProviderOutcome::Removed,
+            Ok(DelistStatus::NotPresent) => (),
+            Err(err) => return ProviderOutcome::from(Err::<DelistStatus, _>(err)),
         }
     }
+
+    outcome
+}
+
+/// Whitelist or delist a single ip or range on the provider.
+async fn call(
+    provider: &mut Box<dyn ProxyProvider>,
+    client: &Client,
+    net: IpNet,
+    remove: bool,
+) -> ProviderOutcome {
+    if !remove && net.addr().is_ipv6() && !provider.supports_ipv6() {
+        return ProviderOutcome::Unsupported(format!(
+            "{} does not support ipv6 addresses",
+            provider.name()
+        ));
+    }
+
+    match (net.is_single(), remove) {
+        (true, false) => ProviderOutcome::from(provider.whitelist(client, net.addr()).await),
+        (true, true) => ProviderOutcome::from(provider.delist(client, net.addr()).await),
+        _ if !provider.supports_cidr() => ProviderOutcome::Unsupported(format!(
+            "{} can not take the range {} of more than {} ips",
+            provider.name(),
+            net,
+            ip::MAX_EXPANDED_IPS
+        )),
+        (false, false) => ProviderOutcome::from(provider.whitelist_net(client, net).await),
+        (false, true) => ProviderOutcome::from(provider.delist_net(client, net).await),
+    }
 }
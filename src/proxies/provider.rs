@@ -0,0 +1,90 @@
+use super::ip::IpNet;
+use super::{DelistStatus, ProxierError, Result, WhitelistStatus};
+use async_trait::async_trait;
+use reqwest::Client;
+use std::net::IpAddr;
+
+/// The account name for providers without a named account.
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+/// A whitelist entry reported by a proxy provider.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct ProxyEntry {
+    /// The whitelisted ip address or cidr range.
+    pub ip: String,
+    /// The provider id of the entry if the service uses one.
+    pub id: Option<String>,
+    /// The rfc 3339 time the entry was last used if the service reports it.
+    pub last_used_at: Option<String>,
+}
+
+/// The entry is for the ip.
+pub(crate) fn same_ip(entry: &str, ip: IpAddr) -> bool {
+    entry.parse() == Ok(ip)
+}
+
+/// The delist status of a removal, where a missing entry counts as not present.
+pub(crate) fn removed<T>(result: Result<T>) -> Result<DelistStatus> {
+    match result {
+        Ok(_) => Ok(DelistStatus::Removed),
+        Err(err) if err.is_not_found() => Ok(DelistStatus::NotPresent),
+        Err(err) => Err(err),
+    }
+}
+
+/// A proxy service that can whitelist and delist the server ip.
+#[async_trait]
+pub trait ProxyProvider: std::fmt::Debug + Send + Sync {
+    /// The name of the provider.
+    fn name(&self) -> &str;
+    /// The account name on the provider, used to tell apart multiple accounts of the same provider.
+    fn account(&self) -> &str {
+        DEFAULT_ACCOUNT
+    }
+    /// Whitelist the ip on the provider. An existing entry for the ip is adopted instead of created again.
+    async fn whitelist(&mut self, client: &Client, ip: IpAddr) -> Result<WhitelistStatus>;
+    /// Delist the entries for the ip on the provider.
+    async fn delist(&mut self, client: &Client, ip: IpAddr) -> Result<DelistStatus>;
+    /// List the whitelist entries for the provider.
+    async fn list(&self, client: &Client) -> Result<Vec<ProxyEntry>>;
+    /// The provider can whitelist ipv6 addresses. Ipv6 server ips are reported as unsupported otherwise.
+    fn supports_ipv6(&self) -> bool {
+        true
+    }
+    /// The provider can whitelist cidr ranges as a single entry. Ranges are expanded into their ips otherwise.
+    fn supports_cidr(&self) -> bool {
+        false
+    }
+    /// Whitelist the cidr range as a single entry. Only called when `supports_cidr` is true.
+    async fn whitelist_net(&mut self, _client: &Client, net: IpNet) -> Result<WhitelistStatus> {
+        Err(ProxierError::Unsupported(format!(
+            "{} can not whitelist the range {}",
+            self.name(),
+            net
+        )))
+    }
+    /// Delist the entry for the cidr range. Only called when `supports_cidr` is true.
+    async fn delist_net(&mut self, _client: &Client, net: IpNet) -> Result<DelistStatus> {
+        Err(ProxierError::Unsupported(format!(
+            "{} can not delist the range {}",
+            self.name(),
+            net
+        )))
+    }
+    /// Look up the ip of the server with the provider api. `None` when the provider does not report it.
+    async fn lookup_ip(&self, _client: &Client) -> Option<Result<String>> {
+        None
+    }
+    /// The entries held from the last whitelist, recorded in the ledger.
+    fn entries(&self) -> Vec<ProxyEntry> {
+        Vec::new()
+    }
+    /// Remove an entry by its ip and id, for example one left over from a previous run.
+    async fn remove(&mut self, _client: &Client, entry: &ProxyEntry) -> Result<DelistStatus> {
+        Err(ProxierError::Unsupported(format!(
+            "{} can not remove the entry for {}",
+            self.name(),
+            entry.ip
+        )))
+    }
+}
@@ -0,0 +1,128 @@
+use super::ip::IpNet;
+use super::provider::same_ip;
+use super::{ProviderReport, ProxierError, ProxyEntry};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// The options for pruning the whitelist entries that are no longer needed.
+#[derive(Clone, Debug, Default)]
+pub struct PruneOptions {
+    /// The ips that are never pruned besides the server ips.
+    pub allowlist: Vec<String>,
+    /// Keep the entries used within the duration. Only webshare reports when an entry was last used,
+    /// the entries of the other providers are kept since their usage is unknown.
+    pub max_idle: Option<Duration>,
+    /// Only report the entries that would be removed.
+    pub dry_run: bool,
+}
+
+impl PruneOptions {
+    /// The default options, pruning every entry besides the server ips.
+    pub fn new() -> PruneOptions {
+        PruneOptions::default()
+    }
+
+    /// Protect the ip from pruning.
+    pub fn allow(mut self, ip: &str) -> PruneOptions {
+        self.allowlist.push(ip.into());
+        self
+    }
+
+    /// The entry is kept for the server ips and ranges at the time.
+    pub fn keeps(&self, server_ips: &[IpNet], entry: &ProxyEntry, now: DateTime<Utc>) -> bool {
+        if server_ips
+            .iter()
+            .any(|net| match entry.ip.parse::<IpNet>() {
+                Ok(ip) if ip.is_single() => net.contains(ip.addr()),
+                Ok(ip) => ip == *net,
+                _ => false,
+            })
+            || self
+                .allowlist
+                .iter()
+                .any(|ip| *ip == entry.ip || ip.parse().is_ok_and(|ip| same_ip(&entry.ip, ip)))
+        {
+            return true;
+        }
+
+        match (self.max_idle, entry.last_used_at.as_deref()) {
+            (None, _) => false,
+            // without a reported usage the entry may be in use.
+            (Some(_), None) => true,
+            // an unreadable time or one in the future keeps the entry to be safe.
+            (Some(max_idle), Some(last_used_at)) => {
+                match DateTime::parse_from_rfc3339(last_used_at) {
+                    Ok(last_used_at) => now
+                        .signed_duration_since(last_used_at)
+                        .to_std()
+                        .map_or(true, |idle| idle < max_idle),
+                    _ => true,
+                }
+            }
+        }
+    }
+}
+
+/// The outcome of pruning a single entry.
+#[derive(Debug)]
+pub enum PruneOutcome {
+    /// The entry would be removed outside of a dry run.
+    WouldRemove,
+    /// The entry was removed.
+    Removed,
+    /// The entry was already gone.
+    NotPresent,
+    /// The removal failed.
+    Failed(ProxierError),
+}
+
+/// An entry selected for pruning.
+#[derive(Debug)]
+pub struct PrunedEntry {
+    /// The name of the provider.
+    pub provider: String,
+    /// The account on the provider.
+    pub account: String,
+    /// The whitelist entry.
+    pub entry: ProxyEntry,
+    /// The outcome of the removal.
+    pub outcome: PruneOutcome,
+}
+
+/// The outcomes of pruning across the providers.
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    /// The entries selected for pruning.
+    pub entries: Vec<PrunedEntry>,
+    /// The providers that could not be listed: skipped for missing credentials, failed or timed out.
+    /// On a follower replica, the providers the leader pruned.
+    pub providers: Vec<ProviderReport>,
+    /// The error updating the ledger when it could not be written.
+    pub ledger: Option<ProxierError>,
+    /// The error electing or publishing as the leader. The replica prunes alone when the election fails,
+    /// and reports the providers as timed out when the deadline passes waiting for the leader.
+    pub election: Option<ProxierError>,
+}
+
+impl PruneReport {
+    /// Every selected entry was removed or would be removed and all of the providers were listed or skipped.
+    pub fn is_success(&self) -> bool {
+        self.ledger.is_none()
+            && self.election.is_none()
+            && !self
+                .entries
+                .iter()
+                .any(|pruned| matches!(pruned.outcome, PruneOutcome::Failed(_)))
+            && !self
+                .providers
+                .iter()
+                .any(|report| report.outcome.is_failed())
+    }
+
+    /// The entries that were removed.
+    pub fn removed(&self) -> impl Iterator<Item = &PrunedEntry> {
+        self.entries
+            .iter()
+            .filter(|pruned| matches!(pruned.outcome, PruneOutcome::Removed))
+    }
+}
@@ -0,0 +1,272 @@
+use super::{ProxierError, Result};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// A redis reply.
+#[derive(Debug)]
+pub(crate) enum Reply {
+    Status(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+/// A minimal redis client opening a connection per pipeline.
+pub(crate) struct Connection<'a> {
+    /// The redis server as `host:port`.
+    pub addr: &'a str,
+    /// The password to authenticate with.
+    pub password: Option<&'a str>,
+    /// How long to wait for each pipeline.
+    pub timeout: Duration,
+    /// Build the error of the store using the connection.
+    pub error: fn(String) -> ProxierError,
+}
+
+impl Connection<'_> {
+    /// Send the commands in a pipeline and read a reply for each.
+    pub async fn send(&self, commands: &[&[&str]]) -> Result<Vec<Reply>> {
+        let call = async {
+            let mut stream = TcpStream::connect(self.addr).await?;
+            let auth = self.password.map(|password| ["AUTH", password]);
+            let commands: Vec<&[&str]> = auth
+                .as_ref()
+                .map(|auth| &auth[..])
+                .into_iter()
+                .chain(commands.iter().copied())
+                .collect();
+
+            let mut request = Vec::new();
+
+            for command in &commands {
+                request.extend_from_slice(format!("*{}\r\n", command.len()).as_bytes());
+                for arg in command.iter() {
+                    request.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+                }
+            }
+
+            stream.write_all(&request).await?;
+
+            let mut reader = BufReader::new(stream);
+            let mut replies = Vec::with_capacity(commands.len());
+
+            for _ in &commands {
+                replies.push(read_reply(&mut reader, self.error).await?);
+            }
+
+            if auth.is_some() {
+                replies.remove(0);
+            }
+
+            Ok(replies)
+        };
+
+        tokio::time::timeout(self.timeout, call)
+            .await
+            .map_err(|_| (self.error)(format!("the redis server {} did not respond", self.addr)))?
+    }
+
+    /// Send a single command and read its reply.
+    pub async fn call(&self, command: &[&str]) -> Result<Reply> {
+        let reply = self.send(&[command]).await?.pop();
+
+        reply.ok_or_else(|| self.unexpected(None))
+    }
+
+    /// The error for an unexpected reply.
+    pub fn unexpected(&self, reply: Option<&Reply>) -> ProxierError {
+        (self.error)(format!("unexpected redis reply {:?}", reply))
+    }
+}
+
+/// Read a reply, turning error replies into errors.
+async fn read_reply<R: tokio::io::AsyncBufRead + Unpin + Send>(
+    reader: &mut R,
+    error: fn(String) -> ProxierError,
+) -> Result<Reply> {
+    let mut line = String::new();
+
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(error("the redis server closed the connection".into()));
+    }
+
+    let line = line.trim_end_matches("\r\n");
+    let (kind, value) = line.split_at(line.len().min(1));
+    let number = || {
+        value
+            .parse::<i64>()
+            .map_err(|_| error(format!("invalid redis reply {:?}", line)))
+    };
+
+    match kind {
+        "+" => Ok(Reply::Status(value.into())),
+        "-" => Err(error(value.into())),
+        ":" => Ok(Reply::Integer(number()?)),
+        "$" => match number()? {
+            len if len < 0 => Ok(Reply::Bulk(None)),
+            len => {
+                let mut data = vec![0; len as usize + 2];
+                reader.read_exact(&mut data).await?;
+                data.truncate(len as usize);
+                Ok(Reply::Bulk(Some(data)))
+            }
+        },
+        "*" => match number()? {
+            len if len < 0 => Ok(Reply::Array(None)),
+            len => {
+                let mut items = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    items.push(Box::pin(read_reply(reader, error)).await?);
+                }
+                Ok(Reply::Array(Some(items)))
+            }
+        },
+        _ => Err(error(format!("invalid redis reply {:?}", line))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// Read a reply from the raw bytes.
+    async fn parse(mut raw: &[u8]) -> Result<Reply> {
+        read_reply(&mut raw, ProxierError::Holders).await
+    }
+
+    #[tokio::test]
+    async fn reads_the_simple_replies() {
+        assert!(matches!(parse(b"+OK\r\n").await, Ok(Reply::Status(status)) if status == "OK"));
+        assert!(matches!(parse(b":42\r\n").await, Ok(Reply::Integer(42))));
+        assert!(matches!(parse(b":-1\r\n").await, Ok(Reply::Integer(-1))));
+        assert!(matches!(parse(b"$-1\r\n").await, Ok(Reply::Bulk(None))));
+        assert!(matches!(parse(b"*-1\r\n").await, Ok(Reply::Array(None))));
+    }
+
+    #[tokio::test]
+    async fn reads_bulk_strings_by_length() {
+        // the length covers the line breaks inside the value.
+        match parse(b"$8\r\npod\r\na-1\r\n").await {
+            Ok(Reply::Bulk(Some(value))) => assert_eq!(value, b"pod\r\na-1"),
+            reply => panic!("{:?}", reply),
+        }
+
+        assert!(
+            matches!(parse(b"$0\r\n\r\n").await, Ok(Reply::Bulk(Some(value))) if value.is_empty())
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_nested_arrays() {
+        let reply = parse(b"*3\r\n+QUEUED\r\n*2\r\n:1\r\n$5\r\npod-a\r\n:2\r\n").await;
+
+        match reply {
+            Ok(Reply::Array(Some(items))) => {
+                assert_eq!(items.len(), 3);
+                assert!(matches!(&items[0], Reply::Status(status) if status == "QUEUED"));
+                assert!(matches!(&items[2], Reply::Integer(2)));
+
+                match &items[1] {
+                    Reply::Array(Some(nested)) => {
+                        assert!(matches!(nested[0], Reply::Integer(1)));
+                        assert!(
+                            matches!(&nested[1], Reply::Bulk(Some(value)) if value == b"pod-a")
+                        );
+                    }
+                    reply => panic!("{:?}", reply),
+                }
+            }
+            reply => panic!("{:?}", reply),
+        }
+    }
+
+    #[tokio::test]
+    async fn turns_error_replies_into_store_errors() {
+        let err = parse(b"-WRONGPASS invalid password\r\n").await.unwrap_err();
+        assert!(
+            matches!(&err, ProxierError::Holders(message) if message == "WRONGPASS invalid password"),
+            "{:?}",
+            err
+        );
+
+        assert!(matches!(
+            parse(b"!5\r\n").await,
+            Err(ProxierError::Holders(_))
+        ));
+        assert!(matches!(
+            parse(b":many\r\n").await,
+            Err(ProxierError::Holders(_))
+        ));
+        assert!(
+            matches!(parse(b"").await, Err(ProxierError::Holders(message)) if message.contains("closed"))
+        );
+        assert!(parse(b"$5\r\npod").await.is_err());
+        assert!(parse(b"*2\r\n:1\r\n").await.is_err());
+    }
+
+    /// A server answering a single connection with the canned replies and returning the request.
+    async fn canned(replies: &'static [u8]) -> (String, JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 1024];
+            let len = stream.read(&mut request).await.unwrap();
+            stream.write_all(replies).await.unwrap();
+            request.truncate(len);
+            request
+        });
+
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn pipelines_the_commands_after_auth() {
+        let (addr, request) = canned(b"+OK\r\n:1\r\n$-1\r\n").await;
+        let redis = Connection {
+            addr: &addr,
+            password: Some("secret"),
+            timeout: Duration::from_secs(3),
+            error: ProxierError::Election,
+        };
+
+        let replies = redis
+            .send(&[&["SADD", "key", "pod-a"], &["GET", "state"]])
+            .await
+            .unwrap();
+
+        // the auth reply is dropped.
+        assert_eq!(replies.len(), 2);
+        assert!(matches!(replies[0], Reply::Integer(1)));
+        assert!(matches!(replies[1], Reply::Bulk(None)));
+        assert_eq!(
+            request.await.unwrap(),
+            b"*2\r\n$4\r\nAUTH\r\n$6\r\nsecret\r\n*3\r\n$4\r\nSADD\r\n$3\r\nkey\r\n$5\r\npod-a\r\n*2\r\n$3\r\nGET\r\n$5\r\nstate\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn times_out_without_a_reply() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let redis = Connection {
+            addr: &addr,
+            password: None,
+            timeout: Duration::from_millis(100),
+            error: ProxierError::Election,
+        };
+
+        let err = redis.call(&["GET", "state"]).await.unwrap_err();
+
+        assert!(
+            matches!(&err, ProxierError::Election(message) if message.contains("did not respond")),
+            "{:?}",
+            err
+        );
+        drop(listener);
+    }
+}
@@ -0,0 +1,180 @@
+use super::ProxierError;
+
+/// The status of a successful whitelist call on a provider.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhitelistStatus {
+    /// The ip was newly whitelisted.
+    Created,
+    /// The ip was already whitelisted.
+    AlreadyPresent,
+}
+
+/// The status of a successful delist call on a provider.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DelistStatus {
+    /// The whitelist entry was removed.
+    Removed,
+    /// There was no whitelist entry to remove.
+    NotPresent,
+}
+
+/// The outcome of a whitelist or delist call on a provider.
+#[derive(Debug)]
+pub enum ProviderOutcome {
+    /// The ip was newly whitelisted.
+    Created,
+    /// The ip was already whitelisted.
+    AlreadyPresent,
+    /// The whitelist entry was removed.
+    Removed,
+    /// There was no whitelist entry to remove.
+    NotPresent,
+    /// The provider was skipped since the credentials are missing. Holds the missing key.
+    Skipped(String),
+    /// The entry was kept since other replicas still hold the ip. Holds the amount of holders left.
+    Retained(usize),
+    /// The call was left to the elected leader replica, which succeeded. Holds the leader.
+    Followed(String),
+    /// The provider does not support the call, such as whitelisting an ipv6 address. Holds the reason.
+    Unsupported(String),
+    /// The call failed.
+    Failed(ProxierError),
+    /// The call did not finish before the deadline.
+    TimedOut,
+}
+
+impl ProviderOutcome {
+    /// The outcome is a failure or did not finish in time.
+    pub fn is_failed(&self) -> bool {
+        matches!(self, ProviderOutcome::Failed(_) | ProviderOutcome::TimedOut)
+    }
+
+    /// The call did not finish before the deadline.
+    pub fn is_timed_out(&self) -> bool {
+        matches!(self, ProviderOutcome::TimedOut)
+    }
+}
+
+impl From<WhitelistStatus> for ProviderOutcome {
+    fn from(status: WhitelistStatus) -> Self {
+        match status {
+            WhitelistStatus::Created => ProviderOutcome::Created,
+            WhitelistStatus::AlreadyPresent => ProviderOutcome::AlreadyPresent,
+        }
+    }
+}
+
+impl From<DelistStatus> for ProviderOutcome {
+    fn from(status: DelistStatus) -> Self {
+        match status {
+            DelistStatus::Removed => ProviderOutcome::Removed,
+            DelistStatus::NotPresent => ProviderOutcome::NotPresent,
+        }
+    }
+}
+
+impl<T: Into<ProviderOutcome>> From<Result<T, ProxierError>> for ProviderOutcome {
+    fn from(result: Result<T, ProxierError>) -> Self {
+        match result {
+            Ok(status) => status.into(),
+            Err(ProxierError::MissingCredentials(key)) => ProviderOutcome::Skipped(key),
+            Err(ProxierError::Unsupported(reason)) => ProviderOutcome::Unsupported(reason),
+            Err(err) => ProviderOutcome::Failed(err),
+        }
+    }
+}
+
+/// The outcome of a call for a single provider.
+#[derive(Debug)]
+pub struct ProviderReport {
+    /// The name of the provider.
+    pub provider: String,
+    /// The account on the provider.
+    pub account: String,
+    /// The server ip or cidr range of the call, when it was for one.
+    pub ip: Option<String>,
+    /// The outcome of the call.
+    pub outcome: ProviderOutcome,
+}
+
+/// The outcomes of a whitelist or delist call across the providers.
+#[derive(Debug, Default)]
+pub struct WhitelistReport {
+    /// The report for each provider in registration order.
+    pub providers: Vec<ProviderReport>,
+    /// The error updating the ledger when it could not be written.
+    pub ledger: Option<ProxierError>,
+    /// The error holding the ips in the shared holder store.
+    pub holders: Option<ProxierError>,
+    /// The error electing or publishing as the leader. The replica acts alone when the election fails,
+    /// and reports the providers as timed out when the deadline passes waiting for the leader.
+    pub election: Option<ProxierError>,
+}
+
+impl WhitelistReport {
+    /// Add the outcome for a provider account.
+    pub fn push(&mut self, provider: &str, account: &str, outcome: impl Into<ProviderOutcome>) {
+        self.providers.push(ProviderReport {
+            provider: provider.into(),
+            account: account.into(),
+            ip: None,
+            outcome: outcome.into(),
+        });
+    }
+
+    /// Get the outcome for the first account of a provider.
+    pub fn get(&self, provider: &str) -> Option<&ProviderOutcome> {
+        self.providers
+            .iter()
+            .find(|report| report.provider == provider)
+            .map(|report| &report.outcome)
+    }
+
+    /// Get the outcome for a provider account.
+    pub fn get_account(&self, provider: &str, account: &str) -> Option<&ProviderOutcome> {
+        self.providers
+            .iter()
+            .find(|report| report.provider == provider && report.account == account)
+            .map(|report| &report.outcome)
+    }
+
+    /// Get the outcome for a server ip or cidr range on the first account of a provider.
+    pub fn get_ip(&self, provider: &str, ip: &str) -> Option<&ProviderOutcome> {
+        self.providers
+            .iter()
+            .find(|report| report.provider == provider && report.ip.as_deref() == Some(ip))
+            .map(|report| &report.outcome)
+    }
+
+    /// All of the providers succeeded, were skipped, unsupported, retained or followed and the ledger, holders and election were updated.
+    pub fn is_success(&self) -> bool {
+        self.ledger.is_none()
+            && self.holders.is_none()
+            && self.election.is_none()
+            && !self
+                .providers
+                .iter()
+                .any(|report| report.outcome.is_failed())
+    }
+
+    /// The providers that did not finish before the deadline.
+    pub fn timed_out(&self) -> impl Iterator<Item = &ProviderReport> {
+        self.providers
+            .iter()
+            .filter(|report| report.outcome.is_timed_out())
+    }
+
+    /// The providers that do not support the call.
+    pub fn unsupported(&self) -> impl Iterator<Item = &ProviderReport> {
+        self.providers
+            .iter()
+            .filter(|report| matches!(report.outcome, ProviderOutcome::Unsupported(_)))
+    }
+
+    /// The providers that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &ProviderReport> {
+        self.providers
+            .iter()
+            .filter(|report| report.outcome.is_failed())
+    }
+}
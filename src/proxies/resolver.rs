@@ -0,0 +1,763 @@
+use super::{
+    stun, webshare, AwsMetadataResolver, AzureMetadataResolver, GcpMetadataResolver, ProxierError,
+    Result, WebShareConfiguration,
+};
+use async_trait::async_trait;
+use futures::future::{join_all, BoxFuture};
+use reqwest::Client;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// The default timeout for the udp resolvers.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The default time a resolver gets to answer before the next one is asked or the vote goes on without it.
+pub const DEFAULT_RESOLVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A random number for message ids, seeded per process.
+pub(crate) fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+/// A way to find the public ip of the server.
+#[async_trait]
+pub trait IpResolver: std::fmt::Debug + Send + Sync {
+    /// The name of the resolver for errors.
+    fn name(&self) -> &str;
+    /// Resolve the public ip.
+    async fn resolve(&self, client: &Client) -> Result<IpAddr>;
+    /// Resolve the public ip of the traffic from the local address. The client is bound to the address,
+    /// resolvers that open their own sockets bind them to it.
+    async fn resolve_from(&self, client: &Client, _local: IpAddr) -> Result<IpAddr> {
+        self.resolve(client).await
+    }
+}
+
+/// Parse the ip returned by a resolver.
+fn parse_ip(resolver: &str, ip: &str) -> Result<IpAddr> {
+    ip.trim()
+        .parse()
+        .map_err(|_| ProxierError::Resolve(format!("{} returned {:?}", resolver, ip.trim())))
+}
+
+/// Resolve the ip with an http echo service returning the ip as text.
+#[derive(Clone, Debug)]
+pub struct HttpEchoResolver {
+    /// The url of the echo service.
+    pub url: String,
+}
+
+impl HttpEchoResolver {
+    /// Resolve with the echo service at the url.
+    pub fn new(url: &str) -> HttpEchoResolver {
+        HttpEchoResolver { url: url.into() }
+    }
+}
+
+impl Default for HttpEchoResolver {
+    fn default() -> Self {
+        HttpEchoResolver::new(webshare::DEFAULT_IP_ECHO_URL)
+    }
+}
+
+#[async_trait]
+impl IpResolver for HttpEchoResolver {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn resolve(&self, client: &Client) -> Result<IpAddr> {
+        parse_ip(&self.url, &webshare::get_local_ip(client, &self.url).await?)
+    }
+}
+
+/// Resolve the ip with the webshare whatsmyip api. Needs the webshare credentials.
+#[derive(Clone, Debug, Default)]
+pub struct WebshareResolver {
+    /// The webshare account to ask.
+    pub configuration: WebShareConfiguration,
+}
+
+#[async_trait]
+impl IpResolver for WebshareResolver {
+    fn name(&self) -> &str {
+        "webshare"
+    }
+
+    async fn resolve(&self, client: &Client) -> Result<IpAddr> {
+        let ip = webshare::get_webshare_ip(
+            client,
+            self.configuration.api_url(),
+            self.configuration.credentials(),
+        )
+        .await?;
+
+        parse_ip("webshare", &ip)
+    }
+}
+
+/// The dns record holding the ip.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsRecord {
+    /// An ipv4 address record.
+    #[default]
+    A,
+    /// An ipv6 address record.
+    Aaaa,
+    /// A text record holding the ip.
+    Txt,
+}
+
+impl DnsRecord {
+    /// The record type code.
+    fn code(&self) -> u16 {
+        match self {
+            DnsRecord::A => 1,
+            DnsRecord::Aaaa => 28,
+            DnsRecord::Txt => 16,
+        }
+    }
+}
+
+/// Resolve the ip with a dns server that answers with the address of the asker, like opendns or google.
+#[derive(Clone, Debug)]
+pub struct DnsResolver {
+    /// The dns server to ask.
+    pub server: SocketAddr,
+    /// The name to look up.
+    pub name: String,
+    /// The record holding the ip.
+    pub record: DnsRecord,
+    /// How long to wait for the answer.
+    pub timeout: Duration,
+}
+
+impl DnsResolver {
+    /// Look up the name on the dns server.
+    pub fn new(server: SocketAddr, name: &str, record: DnsRecord) -> DnsResolver {
+        DnsResolver {
+            server,
+            name: name.into(),
+            record,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// The `myip.opendns.com` a record on the opendns resolver.
+    pub fn opendns() -> DnsResolver {
+        DnsResolver::new(
+            (Ipv4Addr::new(208, 67, 222, 222), 53).into(),
+            "myip.opendns.com",
+            DnsRecord::A,
+        )
+    }
+
+    /// The `o-o.myaddr.l.google.com` txt record on the google name server.
+    pub fn google() -> DnsResolver {
+        DnsResolver::new(
+            (Ipv4Addr::new(216, 239, 32, 10), 53).into(),
+            "o-o.myaddr.l.google.com",
+            DnsRecord::Txt,
+        )
+    }
+}
+
+/// Build the dns query message.
+fn dns_query(id: u16, name: &str, record: DnsRecord) -> Vec<u8> {
+    let mut query = Vec::with_capacity(512);
+
+    query.extend_from_slice(&id.to_be_bytes());
+    // a standard query with recursion desired and a single question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+
+    query.push(0);
+    query.extend_from_slice(&record.code().to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+
+    query
+}
+
+/// The position after the name at the position.
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)?;
+
+        if len & 0xC0 == 0xC0 {
+            return Some(pos + 2);
+        }
+
+        pos += 1;
+
+        if len == 0 {
+            return Some(pos);
+        }
+
+        pos += usize::from(len);
+    }
+}
+
+/// Read the ips from the answers of the dns response.
+fn dns_answers(message: &[u8], id: u16, record: DnsRecord) -> Result<Vec<IpAddr>> {
+    let invalid = || ProxierError::Resolve("invalid dns response".into());
+    let u16_at = |pos: usize| {
+        message
+            .get(pos..pos + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+
+    if message.len() < 12 || u16_at(0) != Some(id) {
+        return Err(invalid());
+    }
+
+    let rcode = message[3] & 0x0F;
+
+    if rcode != 0 {
+        return Err(ProxierError::Resolve(format!(
+            "the dns server answered with rcode {}",
+            rcode
+        )));
+    }
+
+    let questions = u16_at(4).ok_or_else(invalid)?;
+    let answers = u16_at(6).ok_or_else(invalid)?;
+    let mut pos = 12;
+
+    for _ in 0..questions {
+        pos = skip_name(message, pos).ok_or_else(invalid)? + 4;
+    }
+
+    let mut ips = Vec::new();
+
+    for _ in 0..answers {
+        pos = skip_name(message, pos).ok_or_else(invalid)?;
+
+        let kind = u16_at(pos).ok_or_else(invalid)?;
+        let len = usize::from(u16_at(pos + 8).ok_or_else(invalid)?);
+        let data = message.get(pos + 10..pos + 10 + len).ok_or_else(invalid)?;
+
+        pos += 10 + len;
+
+        if kind != record.code() {
+            continue;
+        }
+
+        match record {
+            DnsRecord::A => {
+                if let Ok(octets) = <[u8; 4]>::try_from(data) {
+                    ips.push(Ipv4Addr::from(octets).into());
+                }
+            }
+            DnsRecord::Aaaa => {
+                if let Ok(octets) = <[u8; 16]>::try_from(data) {
+                    ips.push(Ipv6Addr::from(octets).into());
+                }
+            }
+            DnsRecord::Txt => {
+                // a txt record is a list of length prefixed strings.
+                let mut i = 0;
+                while let Some(&len) = data.get(i) {
+                    let text = data
+                        .get(i + 1..i + 1 + usize::from(len))
+                        .unwrap_or_default();
+                    if let Ok(ip) = String::from_utf8_lossy(text).trim().parse() {
+                        ips.push(ip);
+                    }
+                    i += 1 + usize::from(len);
+                }
+            }
+        }
+    }
+
+    Ok(ips)
+}
+
+#[async_trait]
+impl IpResolver for DnsResolver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn resolve(&self, _client: &Client) -> Result<IpAddr> {
+        let local = if self.server.is_ipv4() {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        } else {
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        };
+
+        self.query(local).await
+    }
+
+    async fn resolve_from(&self, _client: &Client, local: IpAddr) -> Result<IpAddr> {
+        if local.is_ipv4() != self.server.is_ipv4() {
+            return Err(ProxierError::Resolve(format!(
+                "the dns server {} can not be reached from {}",
+                self.server, local
+            )));
+        }
+
+        self.query(local).await
+    }
+}
+
+impl DnsResolver {
+    /// Send the query from the local address and read the first ip of the answer.
+    async fn query(&self, local: IpAddr) -> Result<IpAddr> {
+        let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+        let id = random_u64() as u16;
+
+        socket
+            .send_to(&dns_query(id, &self.name, self.record), self.server)
+            .await?;
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut buf = [0; 1232];
+
+        loop {
+            let (len, from) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf))
+                .await
+                .map_err(|_| {
+                    ProxierError::Resolve(format!("the dns server {} did not respond", self.server))
+                })??;
+
+            // ignore stray datagrams and answers to other queries.
+            if from != self.server || buf[..len].get(..2) != Some(&id.to_be_bytes()[..]) {
+                continue;
+            }
+
+            return dns_answers(&buf[..len], id, self.record)?
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    ProxierError::Resolve(format!("no ip in the dns answer for {}", self.name))
+                });
+        }
+    }
+}
+
+/// Resolve the ip with a stun binding request.
+#[derive(Clone, Debug)]
+pub struct StunResolver {
+    /// The stun server as `host:port`.
+    pub server: String,
+    /// How long to wait for the answer.
+    pub timeout: Duration,
+}
+
+impl StunResolver {
+    /// Ask the stun server.
+    pub fn new(server: &str) -> StunResolver {
+        StunResolver {
+            server: server.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl Default for StunResolver {
+    fn default() -> Self {
+        StunResolver::new(stun::DEFAULT_STUN_SERVER)
+    }
+}
+
+#[async_trait]
+impl IpResolver for StunResolver {
+    fn name(&self) -> &str {
+        &self.server
+    }
+
+    async fn resolve(&self, _client: &Client) -> Result<IpAddr> {
+        Ok(stun::mapped_address(&self.server, self.timeout).await?.ip())
+    }
+
+    async fn resolve_from(&self, _client: &Client, local: IpAddr) -> Result<IpAddr> {
+        Ok(stun::binding_from(&self.server, local, self.timeout)
+            .await?
+            .ip())
+    }
+}
+
+/// Ask the resolver from the local address or the default route, failing once the timeout passes.
+async fn ask(
+    resolver: &dyn IpResolver,
+    client: &Client,
+    local: Option<IpAddr>,
+    timeout: Duration,
+) -> Result<IpAddr> {
+    let answer: BoxFuture<'_, Result<IpAddr>> = match local {
+        Some(local) => resolver.resolve_from(client, local),
+        _ => resolver.resolve(client),
+    };
+
+    tokio::time::timeout(timeout, answer)
+        .await
+        .unwrap_or_else(|_| {
+            Err(ProxierError::Resolve(format!(
+                "timed out after {:?}",
+                timeout
+            )))
+        })
+}
+
+/// The resolvers to find the server ip with, tried in order.
+#[derive(Debug)]
+pub struct IpResolvers {
+    /// The resolvers in the order to try them.
+    pub resolvers: Vec<Box<dyn IpResolver>>,
+    /// The amount of resolvers that need to agree on the ip. Zero or one takes the first answer.
+    pub quorum: usize,
+    /// How long each resolver gets to answer.
+    pub timeout: Duration,
+}
+
+impl Default for IpResolvers {
+    fn default() -> Self {
+        IpResolvers {
+            resolvers: Vec::new(),
+            quorum: 0,
+            timeout: DEFAULT_RESOLVER_TIMEOUT,
+        }
+    }
+}
+
+impl IpResolvers {
+    /// An empty set of resolvers.
+    pub fn new() -> IpResolvers {
+        IpResolvers::default()
+    }
+
+    /// The aws, gcp and azure metadata services and then the ip echo service, for cloud instances.
+    pub fn cloud() -> IpResolvers {
+        IpResolvers::new()
+            .with(AwsMetadataResolver::default())
+            .with(GcpMetadataResolver::default())
+            .with(AzureMetadataResolver::default())
+            .with(HttpEchoResolver::default())
+    }
+
+    /// Add a resolver after the existing ones.
+    pub fn with<R: IpResolver + 'static>(mut self, resolver: R) -> IpResolvers {
+        self.resolvers.push(Box::new(resolver));
+        self
+    }
+
+    /// Require the amount of resolvers to agree on the ip.
+    pub fn with_quorum(mut self, quorum: usize) -> IpResolvers {
+        self.quorum = quorum;
+        self
+    }
+
+    /// Give each resolver the time to answer, a resolver that does not is counted as failed.
+    pub fn with_timeout(mut self, timeout: Duration) -> IpResolvers {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Resolve the ip. Without a quorum the first resolver to answer in order wins,
+    /// with one all of the resolvers are asked at once and the ip most of them agree on is taken.
+    pub async fn resolve(&self, client: &Client) -> Result<IpAddr> {
+        self.run(client, None).await
+    }
+
+    /// Resolve the ip of the traffic from the local address, with the client bound to it.
+    pub async fn resolve_from(&self, client: &Client, local: IpAddr) -> Result<IpAddr> {
+        self.run(client, Some(local)).await
+    }
+
+    /// Ask the resolvers from the local address or the default route.
+    async fn run(&self, client: &Client, local: Option<IpAddr>) -> Result<IpAddr> {
+        if self.resolvers.is_empty() {
+            return Err(ProxierError::Resolve("no ip resolvers are set".into()));
+        }
+
+        if self.quorum <= 1 {
+            let mut errors = Vec::new();
+
+            for resolver in &self.resolvers {
+                match ask(resolver.as_ref(), client, local, self.timeout).await {
+                    Ok(ip) => return Ok(ip),
+                    Err(err) => errors.push(format!("{}: {}", resolver.name(), err)),
+                }
+            }
+
+            return Err(ProxierError::Resolve(errors.join(", ")));
+        }
+
+        let answers = join_all(
+            self.resolvers
+                .iter()
+                .map(|resolver| ask(resolver.as_ref(), client, local, self.timeout)),
+        )
+        .await;
+
+        let mut votes: Vec<(IpAddr, usize)> = Vec::new();
+        let mut errors = Vec::new();
+
+        for (resolver, answer) in self.resolvers.iter().zip(answers) {
+            match answer {
+                Ok(ip) => match votes.iter_mut().find(|(voted, _)| *voted == ip) {
+                    Some((_, count)) => *count += 1,
+                    _ => votes.push((ip, 1)),
+                },
+                Err(err) => errors.push(format!("{}: {}", resolver.name(), err)),
+            }
+        }
+
+        // the first ip in resolver order wins a tie.
+        let best = votes
+            .iter()
+            .fold(None::<&(IpAddr, usize)>, |best, vote| match best {
+                Some(best) if best.1 >= vote.1 => Some(best),
+                _ => Some(vote),
+            });
+
+        match best {
+            Some((ip, count)) if *count >= self.quorum => Ok(*ip),
+            _ => {
+                let votes: Vec<String> = votes
+                    .iter()
+                    .map(|(ip, count)| format!("{} x{}", ip, count))
+                    .collect();
+
+                Err(ProxierError::Resolve(format!(
+                    "no quorum of {} resolvers, answers [{}], errors [{}]",
+                    self.quorum,
+                    votes.join(", "),
+                    errors.join(", ")
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A resolver answering with the ip, failing without one, after the delay.
+    #[derive(Debug)]
+    struct Fixed {
+        name: &'static str,
+        ip: Option<&'static str>,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl IpResolver for Fixed {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn resolve(&self, _client: &Client) -> Result<IpAddr> {
+            tokio::time::sleep(self.delay).await;
+            match self.ip {
+                Some(ip) => parse_ip(self.name, ip),
+                _ => Err(ProxierError::Resolve("unreachable".into())),
+            }
+        }
+    }
+
+    fn fixed(name: &'static str, ip: Option<&'static str>) -> Fixed {
+        Fixed {
+            name,
+            ip,
+            delay: Duration::ZERO,
+        }
+    }
+
+    fn hanging(name: &'static str) -> Fixed {
+        Fixed {
+            name,
+            ip: Some("198.51.100.1"),
+            delay: Duration::from_secs(3600),
+        }
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn quorum_takes_the_ip_enough_resolvers_agree_on() {
+        let resolvers = IpResolvers::new()
+            .with(fixed("first", Some("198.51.100.1")))
+            .with(fixed("second", Some("203.0.113.10")))
+            .with(fixed("third", None))
+            .with(fixed("fourth", Some("203.0.113.10")))
+            .with_quorum(2);
+
+        let resolved = resolvers.resolve(&Client::new()).await.unwrap();
+
+        assert_eq!(resolved, ip("203.0.113.10"));
+    }
+
+    #[tokio::test]
+    async fn quorum_without_enough_agreement_fails() {
+        let resolvers = IpResolvers::new()
+            .with(fixed("first", Some("198.51.100.1")))
+            .with(fixed("second", Some("203.0.113.10")))
+            .with(fixed("third", None))
+            .with_quorum(2);
+
+        let err = resolvers.resolve(&Client::new()).await.unwrap_err();
+
+        match err {
+            ProxierError::Resolve(message) => {
+                assert!(
+                    message.starts_with("no quorum of 2 resolvers"),
+                    "{}",
+                    message
+                );
+                assert!(message.contains("198.51.100.1 x1"), "{}", message);
+                assert!(message.contains("203.0.113.10 x1"), "{}", message);
+                assert!(message.contains("third: "), "{}", message);
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn hanging_resolver_times_out() {
+        let resolvers = IpResolvers::new()
+            .with(hanging("slow"))
+            .with(fixed("fast", Some("203.0.113.10")))
+            .with_timeout(Duration::from_millis(50));
+
+        let resolved = resolvers.resolve(&Client::new()).await.unwrap();
+        assert_eq!(resolved, ip("203.0.113.10"));
+
+        // the vote goes on without the resolver that does not answer.
+        let resolvers = IpResolvers::new()
+            .with(hanging("slow"))
+            .with(fixed("first", Some("203.0.113.10")))
+            .with(fixed("second", Some("203.0.113.10")))
+            .with_quorum(2)
+            .with_timeout(Duration::from_millis(50));
+
+        let resolved = resolvers.resolve(&Client::new()).await.unwrap();
+        assert_eq!(resolved, ip("203.0.113.10"));
+
+        let resolvers = IpResolvers::new()
+            .with(hanging("slow"))
+            .with_timeout(Duration::from_millis(50));
+
+        match resolvers.resolve(&Client::new()).await {
+            Err(ProxierError::Resolve(message)) => {
+                assert!(message.starts_with("slow: "), "{}", message);
+                assert!(message.contains("timed out"), "{}", message);
+            }
+            other => panic!("unexpected answer {:?}", other),
+        }
+    }
+
+    /// The response to the query with the answers appended and counted.
+    fn response(query: &[u8], answers: &[&[u8]]) -> Vec<u8> {
+        let mut message = query.to_vec();
+        // a response with recursion available and no error.
+        message[2] = 0x81;
+        message[3] = 0x80;
+        message[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+        for answer in answers {
+            message.extend_from_slice(answer);
+        }
+        message
+    }
+
+    /// An answer record for the name at the position with the data.
+    fn record(name: &[u8], kind: u16, data: &[u8]) -> Vec<u8> {
+        let mut record = name.to_vec();
+        record.extend_from_slice(&kind.to_be_bytes());
+        // the internet class and a ttl of 60 seconds.
+        record.extend_from_slice(&[0, 1, 0, 0, 0, 60]);
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+
+    /// A pointer to the name of the question, right after the header.
+    const QUESTION_NAME: [u8; 2] = [0xC0, 12];
+
+    #[test]
+    fn skip_name_follows_labels_and_pointers() {
+        let query = dns_query(7, "myip.opendns.com", DnsRecord::A);
+
+        // the name ends after its labels and the root, before the type and class.
+        assert_eq!(skip_name(&query, 12), Some(query.len() - 4));
+        // a pointer always takes two bytes, also after labels.
+        let mut message = query.clone();
+        message.extend_from_slice(&[4, b'm', b'y', b'i', b'p', 0xC0, 17]);
+        assert_eq!(skip_name(&message, query.len()), Some(message.len()));
+        assert_eq!(skip_name(&QUESTION_NAME, 0), Some(2));
+        // a name running past the message is invalid.
+        assert_eq!(skip_name(&[4, b'm', b'y'], 0), None);
+    }
+
+    #[test]
+    fn dns_answers_read_compressed_a_records() {
+        let query = dns_query(7, "myip.opendns.com", DnsRecord::A);
+        let message = response(
+            &query,
+            &[
+                &record(&QUESTION_NAME, 5, &QUESTION_NAME),
+                &record(&QUESTION_NAME, 1, &[203, 0, 113, 10]),
+            ],
+        );
+
+        let ips = dns_answers(&message, 7, DnsRecord::A).unwrap();
+
+        assert_eq!(ips, [ip("203.0.113.10")]);
+    }
+
+    #[test]
+    fn dns_answers_read_aaaa_and_txt_records() {
+        let query = dns_query(9, "myip.opendns.com", DnsRecord::Aaaa);
+        let v6 = ip("2001:db8::1");
+        let octets = match v6 {
+            IpAddr::V6(v6) => v6.octets(),
+            _ => unreachable!(),
+        };
+        let message = response(&query, &[&record(&QUESTION_NAME, 28, &octets)]);
+
+        assert_eq!(dns_answers(&message, 9, DnsRecord::Aaaa).unwrap(), [v6]);
+
+        let query = dns_query(11, "o-o.myaddr.l.google.com", DnsRecord::Txt);
+        let mut text = vec![12];
+        text.extend_from_slice(b"203.0.113.10");
+        let message = response(&query, &[&record(&QUESTION_NAME, 16, &text)]);
+
+        assert_eq!(
+            dns_answers(&message, 11, DnsRecord::Txt).unwrap(),
+            [ip("203.0.113.10")]
+        );
+    }
+
+    #[test]
+    fn dns_answers_reject_invalid_responses() {
+        let query = dns_query(7, "myip.opendns.com", DnsRecord::A);
+        let message = response(&query, &[&record(&QUESTION_NAME, 1, &[203, 0, 113, 10])]);
+
+        // another id, an error rcode and a truncated answer.
+        assert!(dns_answers(&message, 8, DnsRecord::A).is_err());
+
+        let mut refused = message.clone();
+        refused[3] |= 5;
+        match dns_answers(&refused, 7, DnsRecord::A) {
+            Err(ProxierError::Resolve(message)) => assert!(message.contains("rcode 5")),
+            other => panic!("unexpected answer {:?}", other),
+        }
+
+        assert!(dns_answers(&message[..message.len() - 2], 7, DnsRecord::A).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+use super::resolver::random_u64;
+use super::{ProxierError, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// The public stun server of google.
+pub const DEFAULT_STUN_SERVER: &str = "stun.l.google.com:19302";
+
+/// The initial retransmission timeout from rfc 5389.
+const INITIAL_RTO: Duration = Duration::from_millis(500);
+
+pub(crate) const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const BINDING_ERROR: u16 = 0x0111;
+pub(crate) const MAGIC_COOKIE: u32 = 0x2112_A442;
+const MAPPED_ADDRESS: u16 = 0x0001;
+const ERROR_CODE: u16 = 0x0009;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+pub(crate) const HEADER_LEN: usize = 20;
+
+/// The result of a stun binding request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StunBinding {
+    /// The stun server that answered.
+    pub server: SocketAddr,
+    /// The local address of the socket that sent the request.
+    pub local: SocketAddr,
+    /// The address and port the server saw the request come from.
+    pub mapped: SocketAddr,
+}
+
+impl StunBinding {
+    /// The public ip of the server.
+    pub fn ip(&self) -> IpAddr {
+        self.mapped.ip()
+    }
+
+    /// The request went through a nat, since the mapped ip differs from the local ip.
+    pub fn is_nat(&self) -> bool {
+        self.mapped.ip() != self.local.ip()
+    }
+
+    /// The nat kept the local port for the mapping.
+    pub fn preserves_port(&self) -> bool {
+        self.mapped.port() == self.local.port()
+    }
+}
+
+/// A new random transaction id.
+fn transaction_id() -> [u8; 12] {
+    let mut id = [0; 12];
+    id[..8].copy_from_slice(&random_u64().to_be_bytes());
+    id[8..].copy_from_slice(&random_u64().to_be_bytes()[..4]);
+    id
+}
+
+/// The binding request message.
+fn binding_request(transaction_id: &[u8; 12]) -> [u8; HEADER_LEN] {
+    let mut message = [0; HEADER_LEN];
+    message[..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+    message[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    message[8..].copy_from_slice(transaction_id);
+    message
+}
+
+/// The xor key of the magic cookie and transaction id.
+fn xor_key(transaction_id: &[u8; 12]) -> [u8; 16] {
+    let mut key = [0; 16];
+    key[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    key[4..].copy_from_slice(transaction_id);
+    key
+}
+
+/// The binding success response with the xor mapped address.
+#[cfg(feature = "testing")]
+pub(crate) fn binding_response(transaction_id: &[u8], mapped: SocketAddr) -> Vec<u8> {
+    let mut id = [0; 12];
+    id.copy_from_slice(&transaction_id[..12]);
+    let key = xor_key(&id);
+
+    let (family, ip): (u8, Vec<u8>) = match mapped.ip() {
+        IpAddr::V4(ip) => (0x01, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (0x02, ip.octets().to_vec()),
+    };
+
+    let mut value = vec![0, family];
+    value.extend_from_slice(&(mapped.port() ^ u16::from_be_bytes([key[0], key[1]])).to_be_bytes());
+    value.extend(ip.iter().zip(key).map(|(octet, key)| octet ^ key));
+
+    let mut message = Vec::with_capacity(HEADER_LEN + 4 + value.len());
+    message.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+    message.extend_from_slice(&(4 + value.len() as u16).to_be_bytes());
+    message.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    message.extend_from_slice(&id);
+    message.extend_from_slice(&XOR_MAPPED_ADDRESS.to_be_bytes());
+    message.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    message.extend_from_slice(&value);
+    message
+}
+
+/// Read the mapped address from a binding response. Messages for other transactions are ignored.
+fn parse_binding_response(message: &[u8], transaction_id: &[u8; 12]) -> Result<Option<SocketAddr>> {
+    if message.len() < HEADER_LEN
+        || message[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || message[8..HEADER_LEN] != transaction_id[..]
+    {
+        return Ok(None);
+    }
+
+    let kind = u16::from_be_bytes([message[0], message[1]]);
+
+    if kind != BINDING_SUCCESS && kind != BINDING_ERROR {
+        return Err(ProxierError::Resolve(format!(
+            "unexpected stun response type {:#06x}",
+            kind
+        )));
+    }
+
+    let len = usize::from(u16::from_be_bytes([message[2], message[3]]));
+    let attributes = message
+        .get(HEADER_LEN..HEADER_LEN + len)
+        .ok_or_else(|| ProxierError::Resolve("truncated stun response".into()))?;
+
+    let mut mapped = None;
+    let mut pos = 0;
+
+    while pos + 4 <= attributes.len() {
+        let kind = u16::from_be_bytes([attributes[pos], attributes[pos + 1]]);
+        let len = usize::from(u16::from_be_bytes([
+            attributes[pos + 2],
+            attributes[pos + 3],
+        ]));
+        let value = attributes
+            .get(pos + 4..pos + 4 + len)
+            .ok_or_else(|| ProxierError::Resolve("truncated stun attribute".into()))?;
+
+        match kind {
+            ERROR_CODE if value.len() >= 4 => {
+                let code = u16::from(value[2] & 0x07) * 100 + u16::from(value[3]);
+                return Err(ProxierError::Resolve(format!(
+                    "the stun server answered with error {}: {}",
+                    code,
+                    String::from_utf8_lossy(&value[4..])
+                )));
+            }
+            XOR_MAPPED_ADDRESS => return Ok(Some(address(value, Some(transaction_id))?)),
+            MAPPED_ADDRESS => mapped = Some(address(value, None)?),
+            _ => (),
+        }
+
+        // the attributes are padded to four bytes.
+        pos += 4 + len.div_ceil(4) * 4;
+    }
+
+    if kind == BINDING_ERROR {
+        return Err(ProxierError::Resolve(
+            "the stun server answered with an error".into(),
+        ));
+    }
+
+    mapped
+        .map(Some)
+        .ok_or_else(|| ProxierError::Resolve("the stun response has no mapped address".into()))
+}
+
+/// Read an address attribute, xored with the magic cookie and transaction id when given one.
+fn address(value: &[u8], xor: Option<&[u8; 12]>) -> Result<SocketAddr> {
+    let invalid = || ProxierError::Resolve("invalid stun address attribute".into());
+
+    if value.len() < 4 {
+        return Err(invalid());
+    }
+
+    let key = xor.map(xor_key).unwrap_or_default();
+    let port = u16::from_be_bytes([value[2] ^ key[0], value[3] ^ key[1]]);
+
+    let ip = match (value[1], &value[4..]) {
+        (0x01, octets) if octets.len() == 4 => {
+            let mut ip = [0; 4];
+            for (i, octet) in octets.iter().enumerate() {
+                ip[i] = octet ^ key[i];
+            }
+            IpAddr::from(Ipv4Addr::from(ip))
+        }
+        (0x02, octets) if octets.len() == 16 => {
+            let mut ip = [0; 16];
+            for (i, octet) in octets.iter().enumerate() {
+                ip[i] = octet ^ key[i];
+            }
+            IpAddr::from(Ipv6Addr::from(ip))
+        }
+        _ => return Err(invalid()),
+    };
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Send a binding request to the stun server as `host:port`.
+/// The request is retransmitted with a doubling timeout from 500ms, as in rfc 5389, until the timeout runs out.
+pub async fn binding(server: &str, timeout: Duration) -> Result<StunBinding> {
+    request(server, None, timeout).await
+}
+
+/// Send a binding request to the stun server from the local address, to find the mapping of a single interface.
+pub async fn binding_from(server: &str, local: IpAddr, timeout: Duration) -> Result<StunBinding> {
+    request(server, Some(local), timeout).await
+}
+
+/// Send the binding request from the local address or the one of the route to the server.
+async fn request(server: &str, local: Option<IpAddr>, timeout: Duration) -> Result<StunBinding> {
+    // the server address has to be of the same family as the local address.
+    let server = tokio::net::lookup_host(server)
+        .await?
+        .find(|addr| local.is_none_or(|local| local.is_ipv4() == addr.is_ipv4()))
+        .ok_or_else(|| {
+            ProxierError::Resolve(format!("no address for the stun server {}", server))
+        })?;
+
+    let local: SocketAddr = match local {
+        Some(local) => (local, 0).into(),
+        _ if server.is_ipv4() => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        _ => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    let socket = UdpSocket::bind(local).await?;
+
+    // connecting picks the local address of the route and filters datagrams from other peers.
+    socket.connect(server).await?;
+
+    let local = socket.local_addr()?;
+    let transaction_id = transaction_id();
+    let request = binding_request(&transaction_id);
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut rto = INITIAL_RTO;
+    let mut buf = [0; 576];
+
+    loop {
+        socket.send(&request).await?;
+
+        let retransmit = std::cmp::min(tokio::time::Instant::now() + rto, deadline);
+
+        // wait for the answer until the next retransmission.
+        while let Ok(received) = tokio::time::timeout_at(retransmit, socket.recv(&mut buf)).await {
+            let len = received?;
+
+            if let Some(mapped) = parse_binding_response(&buf[..len], &transaction_id)? {
+                return Ok(StunBinding {
+                    server,
+                    local,
+                    mapped,
+                });
+            }
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            return Err(ProxierError::Resolve(format!(
+                "the stun server {} did not respond",
+                server
+            )));
+        }
+
+        rto *= 2;
+    }
+}
+
+/// Send a binding request to the stun server and read the mapped address of the socket.
+pub async fn mapped_address(server: &str, timeout: Duration) -> Result<SocketAddr> {
+    Ok(binding(server, timeout).await?.mapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The transaction id of the rfc 5769 sample responses.
+    const TRANSACTION_ID: [u8; 12] = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    /// The ipv4 sample response of rfc 5769 with software, integrity and fingerprint attributes.
+    const IPV4_RESPONSE: [u8; 80] = [
+        0x01, 0x01, 0x00, 0x3c, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6,
+        0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74, 0x20, 0x76,
+        0x65, 0x63, 0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1,
+        0x12, 0xa6, 0x43, 0x00, 0x08, 0x00, 0x14, 0x2b, 0x91, 0xf5, 0x99, 0xfd, 0x9e, 0x90, 0xc3,
+        0x8c, 0x74, 0x89, 0xf9, 0x2a, 0xf9, 0xba, 0x53, 0xf0, 0x6b, 0xe7, 0xd7, 0x80, 0x28, 0x00,
+        0x04, 0xc0, 0x7d, 0x4c, 0x96,
+    ];
+
+    /// The ipv6 sample response of rfc 5769.
+    const IPV6_RESPONSE: [u8; 92] = [
+        0x01, 0x01, 0x00, 0x48, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6,
+        0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74, 0x20, 0x76,
+        0x65, 0x63, 0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00, 0x14, 0x00, 0x02, 0xa1, 0x47, 0x01,
+        0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1, 0x79, 0xbc, 0x25, 0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+        0x00, 0x08, 0x00, 0x14, 0xa3, 0x82, 0x95, 0x4e, 0x4b, 0xe6, 0x7b, 0xf1, 0x17, 0x84, 0xc9,
+        0x7c, 0x82, 0x92, 0xc2, 0x75, 0xbf, 0xe3, 0xed, 0x41, 0x80, 0x28, 0x00, 0x04, 0xc8, 0xfb,
+        0x0b, 0x4c,
+    ];
+
+    /// A message of the type with the attributes for the sample transaction.
+    fn message(kind: u16, attributes: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&kind.to_be_bytes());
+        message.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+        message.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        message.extend_from_slice(&TRANSACTION_ID);
+        message.extend_from_slice(attributes);
+        message
+    }
+
+    #[test]
+    fn parses_the_xor_mapped_ipv4_address() {
+        let mapped = parse_binding_response(&IPV4_RESPONSE, &TRANSACTION_ID).unwrap();
+
+        assert_eq!(mapped, Some("192.0.2.1:32853".parse().unwrap()));
+    }
+
+    #[test]
+    fn parses_the_xor_mapped_ipv6_address() {
+        let mapped = parse_binding_response(&IPV6_RESPONSE, &TRANSACTION_ID).unwrap();
+
+        assert_eq!(
+            mapped,
+            Some(
+                "[2001:db8:1234:5678:11:2233:4455:6677]:32853"
+                    .parse()
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn ignores_other_transactions() {
+        let mut other = TRANSACTION_ID;
+        other[0] ^= 0xff;
+
+        assert_eq!(
+            parse_binding_response(&IPV4_RESPONSE, &other).unwrap(),
+            None
+        );
+        assert_eq!(
+            parse_binding_response(&IPV4_RESPONSE[..HEADER_LEN - 1], &TRANSACTION_ID).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_mapped_address() {
+        let attributes = [
+            0x00, 0x01, 0x00, 0x08, 0x00, 0x01, 0x1f, 0x90, 198, 51, 100, 7,
+        ];
+        let response = message(BINDING_SUCCESS, &attributes);
+
+        assert_eq!(
+            parse_binding_response(&response, &TRANSACTION_ID).unwrap(),
+            Some("198.51.100.7:8080".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn reports_error_code_responses() {
+        // class 4 and number 1 with a padded reason.
+        let mut attributes = vec![0x00, 0x09, 0x00, 0x10, 0x00, 0x00, 0x04, 0x01];
+        attributes.extend_from_slice(b"Unauthorized");
+        let response = message(BINDING_ERROR, &attributes);
+
+        let err = parse_binding_response(&response, &TRANSACTION_ID)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("error 401: Unauthorized"), "{}", err);
+
+        let err = parse_binding_response(&message(BINDING_ERROR, &[]), &TRANSACTION_ID)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("answered with an error"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_malformed_responses() {
+        assert!(parse_binding_response(&message(BINDING_SUCCESS, &[]), &TRANSACTION_ID).is_err());
+        assert!(parse_binding_response(&message(BINDING_REQUEST, &[]), &TRANSACTION_ID).is_err());
+        assert!(parse_binding_response(&IPV4_RESPONSE[..40], &TRANSACTION_ID).is_err());
+
+        let attributes = [0x00, 0x20, 0x00, 0x08, 0x00, 0x03, 0xa1, 0x47, 0, 0, 0, 0];
+        assert!(
+            parse_binding_response(&message(BINDING_SUCCESS, &attributes), &TRANSACTION_ID)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn binding_requests_carry_the_transaction() {
+        let request = binding_request(&TRANSACTION_ID);
+
+        assert_eq!(request[..4], [0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(request[4..8], MAGIC_COOKIE.to_be_bytes());
+        assert_eq!(request[8..], TRANSACTION_ID);
+    }
+}
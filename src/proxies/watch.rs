@@ -0,0 +1,378 @@
+use super::election::{self, Role};
+use super::{
+    holders, remove_listed, DelistStatus, IpNet, ProviderOutcome, ProviderReport, Proxier,
+    ProxierError, ProxyEntry, ProxyProvider, Result, WhitelistReport,
+};
+use futures::future::join_all;
+use reqwest::Client;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// A change of the server ip with the outcome of moving the whitelist over.
+#[derive(Debug)]
+pub struct IpChange {
+    /// The server ips delisted by the move: the ip before the change and the ips of earlier moves that did not finish.
+    /// Empty when the ip was not resolved yet.
+    pub previous: Vec<IpAddr>,
+    /// The server ip after the change.
+    pub current: IpAddr,
+    /// The outcome of whitelisting the current ip.
+    pub whitelist: WhitelistReport,
+    /// The outcome of delisting each previous ip.
+    pub delist: WhitelistReport,
+}
+
+/// An event from the ip watcher.
+#[derive(Debug)]
+pub enum IpEvent {
+    /// The server ip changed and the whitelist was moved to the new ip, or a failed move was retried.
+    Changed(Box<IpChange>),
+    /// The server ip could not be resolved or its holds refreshed. The whitelist is left as is.
+    Failed(ProxierError),
+}
+
+/// A background task re-resolving the server ip on an interval. Dropping the watcher stops the task.
+#[derive(Debug)]
+pub struct IpWatcher {
+    events: mpsc::UnboundedReceiver<IpEvent>,
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<Proxier>,
+}
+
+impl IpWatcher {
+    /// The next event. Returns `None` once the watcher stopped.
+    pub async fn next(&mut self) -> Option<IpEvent> {
+        self.events.recv().await
+    }
+
+    /// Stop watching and get the proxier back, for example to delist on shutdown.
+    pub async fn stop(self) -> Proxier {
+        let _ = self.stop.send(());
+
+        match self.handle.await {
+            Ok(proxier) => proxier,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+}
+
+/// Remove the held entries for the ip on the provider.
+async fn remove_held(
+    provider: &mut Box<dyn ProxyProvider>,
+    client: &Client,
+    held: &[ProxyEntry],
+    ip: IpAddr,
+) -> ProviderOutcome {
+    let mut outcome = ProviderOutcome::NotPresent;
+
+    for entry in held.iter().filter(|entry| entry.ip.parse() == Ok(ip)) {
+        match provider.remove(client, entry).await {
+            Ok(DelistStatus::Removed) => outcome = ProviderOutcome::Removed,
+            Ok(DelistStatus::NotPresent) => (),
+            Err(err) => return ProviderOutcome::from(Err::<DelistStatus, _>(err)),
+        }
+    }
+
+    outcome
+}
+
+/// Delist a previous ip on the provider, with the held entries when the provider reports them.
+/// With `listed`, the entries another replica created for the ip are listed and removed when none are held.
+async fn delist_previous(
+    provider: &mut Box<dyn ProxyProvider>,
+    client: &Client,
+    held: &[ProxyEntry],
+    ip: IpAddr,
+    listed: bool,
+) -> ProviderOutcome {
+    let outcome = if held.is_empty() {
+        ProviderOutcome::from(provider.delist(client, ip).await)
+    } else {
+        remove_held(provider, client, held, ip).await
+    };
+
+    match outcome {
+        ProviderOutcome::NotPresent if listed => {
+            remove_listed(provider, client, IpNet::from(ip)).await
+        }
+        outcome => outcome,
+    }
+}
+
+/// The outcome of a previous ip that is not delisted: other replicas hold it or the store failed.
+fn kept(released: &Result<usize>) -> Option<ProviderOutcome> {
+    match released {
+        Ok(0) => None,
+        Ok(count) => Some(ProviderOutcome::Retained(*count)),
+        Err(err) => Some(ProviderOutcome::Failed(holders::failed(err))),
+    }
+}
+
+/// Part of the move failed on a provider or a store, so it has to be retried.
+fn unfinished(report: &WhitelistReport) -> bool {
+    report.ledger.is_some()
+        || report.holders.is_some()
+        || report
+            .providers
+            .iter()
+            .any(|report| report.outcome.is_failed())
+}
+
+impl Proxier {
+    /// Resolve the server ip again and move the whitelist over when it changed.
+    /// The new ip is whitelisted before the previous ip is delisted, so the proxies keep working in between.
+    /// Providers that do not report their entries are delisted first.
+    /// Providers without ipv6 support only delist the previous ip when the new ip is ipv6.
+    /// With the holders set, the new ip is held and the previous ip is only delisted when no other replica holds it.
+    /// With the election set, only the leader moves the whitelist and the followers report its outcome.
+    /// The leader lists and removes the entries of the previous ips, or with the holders set the last replica to release them.
+    /// A move that failed on any provider or store is retried on the next refresh, along with the previous ips of
+    /// every move that did not finish, so an ip changing again before the retry is not left whitelisted.
+    /// Only `server_ip` moves, the extra `server_ips` are fixed and stay whitelisted.
+    pub async fn refresh_server_ip(&mut self) -> Result<Option<IpChange>> {
+        let current = self.lookup_ip(self.provider("webshare")).await?;
+
+        let unfinished_ips = self.retry.take();
+        if unfinished_ips.is_none() && Some(current) == self.server_ip {
+            return Ok(None);
+        }
+
+        // an ip the server moved back to is whitelisted again rather than delisted.
+        let mut previous = unfinished_ips.unwrap_or_default();
+        previous.extend(self.server_ip);
+        previous.remove(&current);
+
+        self.server_ip = Some(current);
+        let role = self.elect("move").await;
+
+        let (mut whitelist, delist) = match &role {
+            // nothing was called, the holds are left for the retry.
+            Role::TimedOut(_) => (
+                WhitelistReport {
+                    providers: self.timed_out(),
+                    ..Default::default()
+                },
+                WhitelistReport {
+                    providers: self.timed_out(),
+                    ..Default::default()
+                },
+            ),
+            Role::Follower(published) => {
+                let holders = self.hold(current).await;
+                let (_, delist) = self
+                    .move_ip(current, &previous, Some(published.leader()))
+                    .await;
+                let whitelist = WhitelistReport {
+                    providers: published.providers(),
+                    holders,
+                    ..Default::default()
+                };
+                (whitelist, delist)
+            }
+            _ => {
+                let held = self.ledger_entries();
+                let (intents, intended) = match self.intend(&[IpNet::from(current)]) {
+                    Ok(intents) => (intents, None),
+                    Err(err) => (Vec::new(), Some(err)),
+                };
+
+                let holders = self.hold(current).await;
+                let (whitelisted, delist) = self.move_ip(current, &previous, None).await;
+                let whitelist = WhitelistReport {
+                    providers: whitelisted,
+                    ledger: intended.or(self.record(&held, &intents).err()),
+                    holders,
+                    ..Default::default()
+                };
+                (whitelist, delist)
+            }
+        };
+
+        let retry = unfinished(&whitelist) || unfinished(&delist);
+
+        // a leader that did not finish publishes nothing, so the replicas elect again on their retry.
+        whitelist.election = match role {
+            Role::Leader(key) if retry => self.abandon(&key).await,
+            role => {
+                self.lead(role, election::published(&whitelist.providers))
+                    .await
+            }
+        };
+
+        // the calls are idempotent, so the whole move is retried.
+        if retry {
+            self.retry = Some(previous.clone());
+        }
+
+        Ok(Some(IpChange {
+            previous: previous.into_iter().collect(),
+            current,
+            whitelist,
+            delist,
+        }))
+    }
+
+    /// Hold the current ip in the shared store. Returns the error of the store.
+    async fn hold(&self, current: IpAddr) -> Option<ProxierError> {
+        let holders = self.holders.as_ref()?;
+
+        holders.acquire(&current.to_string()).await.err()
+    }
+
+    /// Whitelist the current ip and delist the previous ips on every provider within the deadline.
+    /// A follower of the leader only delists the previous ips it released last, and only with the holders set.
+    /// Returns the whitelist outcomes, empty for a follower, and the delist report.
+    async fn move_ip(
+        &mut self,
+        current: IpAddr,
+        previous: &BTreeSet<IpAddr>,
+        leader: Option<&str>,
+    ) -> (Vec<ProviderReport>, WhitelistReport) {
+        let mut released = Vec::with_capacity(previous.len());
+
+        for ip in previous {
+            released.push(match self.holders.as_ref() {
+                Some(holders) => (*ip, holders.release(&ip.to_string()).await),
+                _ => (*ip, Ok(0)),
+            });
+        }
+
+        let mut delist = WhitelistReport {
+            holders: released
+                .iter()
+                .find_map(|(_, released)| released.as_ref().err())
+                .map(holders::failed),
+            ..Default::default()
+        };
+
+        // without the holders the leader delisted the previous ips for every replica.
+        let followed = leader.filter(|_| self.holders.is_none());
+        // the replica that created the entries may be another one, so the leader or the last holder lists them.
+        // the previous ips are no longer the egress of any replica behind the nat, unlike the ips on delist.
+        let listed = self.election.is_some();
+        let released = &released[..];
+        let client = &self.client;
+        let deadline = self.deadline;
+
+        let calls = self.providers.iter_mut().map(|provider| async move {
+            let call = async {
+                let held = provider.entries();
+                let mut delisted: Vec<Option<ProviderOutcome>> = released
+                    .iter()
+                    .map(|(_, released)| match (kept(released), followed) {
+                        (Some(outcome), _) => Some(outcome),
+                        (_, Some(leader)) => Some(ProviderOutcome::Followed(leader.to_string())),
+                        _ => None,
+                    })
+                    .collect();
+
+                if held.is_empty() {
+                    for (outcome, (ip, _)) in delisted.iter_mut().zip(released) {
+                        if outcome.is_none() {
+                            *outcome =
+                                Some(delist_previous(provider, client, &held, *ip, listed).await);
+                        }
+                    }
+                }
+
+                let whitelisted = match leader {
+                    Some(_) => None,
+                    _ if current.is_ipv6() && !provider.supports_ipv6() => {
+                        Some(ProviderOutcome::Unsupported(format!(
+                            "{} does not support ipv6 addresses",
+                            provider.name()
+                        )))
+                    }
+                    _ => Some(ProviderOutcome::from(
+                        provider.whitelist(client, current).await,
+                    )),
+                };
+
+                let mut outcomes = Vec::with_capacity(delisted.len());
+                for (outcome, (ip, _)) in delisted.into_iter().zip(released) {
+                    outcomes.push(match outcome {
+                        Some(outcome) => outcome,
+                        _ => delist_previous(provider, client, &held, *ip, listed).await,
+                    });
+                }
+
+                (whitelisted, outcomes)
+            };
+
+            let (whitelisted, delisted) = match deadline {
+                Some(deadline) => tokio::time::timeout(deadline, call)
+                    .await
+                    .unwrap_or_else(|_| {
+                        let timed_out = std::iter::repeat_with(|| ProviderOutcome::TimedOut);
+                        (
+                            leader.is_none().then_some(ProviderOutcome::TimedOut),
+                            timed_out.take(released.len()).collect(),
+                        )
+                    }),
+                _ => call.await,
+            };
+
+            let report = |ip: IpAddr, outcome| ProviderReport {
+                provider: provider.name().to_string(),
+                account: provider.account().to_string(),
+                ip: Some(ip.to_string()),
+                outcome,
+            };
+
+            let delisted: Vec<ProviderReport> = released
+                .iter()
+                .zip(delisted)
+                .map(|((ip, _), outcome)| report(*ip, outcome))
+                .collect();
+
+            (
+                whitelisted.map(|outcome| report(current, outcome)),
+                delisted,
+            )
+        });
+
+        let mut whitelisted = Vec::new();
+
+        for (whitelist, delisted) in join_all(calls).await {
+            whitelisted.extend(whitelist);
+            delist.providers.extend(delisted);
+        }
+
+        (whitelisted, delist)
+    }
+
+    /// Watch the server ip in the background, checking on every interval.
+    /// On a change the whitelist is moved over with `refresh_server_ip` and an event is sent.
+    /// Otherwise the holds are refreshed, so keep the interval below the ttl of the holders.
+    pub fn watch(mut self, interval: Duration) -> IpWatcher {
+        let (events, receiver) = mpsc::unbounded_channel();
+        let (stop, mut stopped) = oneshot::channel();
+
+        let handle = tokio::spawn(async move {
+            // a stop or a dropped watcher ends the loop.
+            while tokio::time::timeout(interval, &mut stopped).await.is_err() {
+                let event = match self.refresh_server_ip().await {
+                    Ok(Some(change)) => IpEvent::Changed(Box::new(change)),
+                    Ok(None) => match self.refresh_holds().await {
+                        Ok(()) => continue,
+                        Err(err) => IpEvent::Failed(err),
+                    },
+                    Err(err) => IpEvent::Failed(err),
+                };
+
+                let _ = events.send(event);
+            }
+
+            self
+        });
+
+        IpWatcher {
+            events: receiver,
+            stop,
+            handle,
+        }
+    }
+}
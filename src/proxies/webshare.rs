@@ -1,10 +1,25 @@
+use super::credentials::{self, CredentialSource};
+use super::provider::{removed, same_ip};
+use super::{
+    DelistStatus, ProxierError, ProxyEntry, ProxyProvider, Result, WebShareConfiguration,
+    WhitelistStatus, DEFAULT_ACCOUNT,
+};
+use async_trait::async_trait;
 use reqwest::{
     header::{HeaderMap, HeaderValue, AUTHORIZATION},
-    Client,
+    Client, StatusCode,
 };
 use serde_json::json;
+use std::net::IpAddr;
 pub use string_concat::{string_concat, string_concat_impl};
 
+/// The webshare api base url.
+pub const DEFAULT_BASE_URL: &str = "https://proxy.webshare.io";
+/// The credential key for the webshare api token.
+pub const API_TOKEN_KEY: &str = "PROXY_SHARE_PASSWORD";
+/// The ip echo service base url.
+pub const DEFAULT_IP_ECHO_URL: &str = "https://api.ipify.org";
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
 struct ProxtIP {
     /// The local ip address of the server.
@@ -25,156 +40,314 @@ pub struct ProxyIP {
     pub last_used_at: Option<String>,
 }
 
-// setup any of the proxies white listing if needed.
-pub async fn setup_proxy(client: &Client, target: &str, remove: bool) -> ProxyIP {
-    let mut p = ProxyIP::default();
+/// A page of ip authorizations.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct IpAuthorizations {
+    /// The total amount of ip authorizations.
+    #[serde(default)]
+    pub count: usize,
+    /// The url of the next page.
+    #[serde(default)]
+    pub next: Option<String>,
+    /// The url of the previous page.
+    #[serde(default)]
+    pub previous: Option<String>,
+    /// The ip authorizations of the page.
+    #[serde(default)]
+    pub results: Vec<ProxyIP>,
+}
 
-    if !target.is_empty() {
-        let proxy_share_url = "https://proxy.webshare.io/api/v2/proxy/ipauthorization/";
+/// The webshare api token header.
+fn auth_headers(credentials: &dyn CredentialSource) -> Result<HeaderMap> {
+    let password = credentials::require(credentials, API_TOKEN_KEY)?;
+    let mut headers = HeaderMap::new();
 
-        match dotenv::var("PROXY_SHARE_PASSWORD") {
-            Ok(password) => {
-                let mut headers = HeaderMap::new();
+    if let Ok(hv) = HeaderValue::from_str(&string_concat!("Token ".to_string(), password)) {
+        headers.insert(AUTHORIZATION, hv);
+    }
 
-                match HeaderValue::from_str(
-                    &string_concat!("Token ".to_string(), password).to_string(),
-                ) {
-                    Ok(hv) => {
-                        headers.insert(AUTHORIZATION, hv);
-                    }
-                    _ => (),
-                }
+    Ok(headers)
+}
 
-                let action = if remove {
-                    client.delete(string_concat!(proxy_share_url, target, "/"))
-                } else {
-                    client
-                        .post(proxy_share_url)
-                        .json(&json!({ "ip_address": target }))
-                };
-
-                match action.headers(headers).send().await {
-                    Ok(response) => {
-                        if response.status().is_success() {
-                            if !remove {
-                                let ip: ProxyIP = serde_json::from_str(
-                                    &response.text().await.unwrap_or_default(),
-                                )
-                                .unwrap_or_default();
-
-                                p.clone_from(&ip);
-                            }
-                            println!("Successfully updated the IP authorization.");
-                        } else {
-                            if response.status() == 400 {
-                                if !remove {
-                                    // we need to get the existing IP from the system to get the ID.
-                                    // we first need to list all of the IPS from the proxy until we find our ID. After finding our ID we can remove the value.
-
-                                    let res_text = response.text().await;
-
-                                    let ip: ProxyIP =
-                                        serde_json::from_str(&res_text.unwrap_or_default())
-                                            .unwrap_or_default();
-
-                                    p.clone_from(&ip);
-                                }
-                                println!("IP already whitelisted.");
-                            } else {
-                                println!(
-                                    "Failed to update the IP authorization. Status: {:?} - Removed:{:?}",
-                                    response.status(),
-                                    remove,
-                                );
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        println!("{:?}", err)
-                    }
-                }
-            }
-            Err(e) => {
-                println!("Set the env {:?} to enable proxy whitelisting.", e)
-            }
-        };
+// setup any of the proxies white listing if needed.
+pub async fn setup_proxy(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    target: &str,
+    remove: bool,
+) -> Result<ProxyIP> {
+    if remove {
+        if target.is_empty() {
+            return Err(ProxierError::InvalidIp(target.into()));
+        }
+    } else {
+        super::ip::parse(target)?;
     }
 
-    p
+    let proxy_share_url = string_concat!(base_url, "/api/v2/proxy/ipauthorization/");
+    let headers = auth_headers(credentials)?;
+
+    let action = if remove {
+        client.delete(string_concat!(proxy_share_url, target, "/"))
+    } else {
+        client
+            .post(&proxy_share_url)
+            .json(&json!({ "ip_address": target }))
+    };
+
+    let response = action.headers(headers).send().await?;
+
+    if response.status().is_success() {
+        if remove {
+            Ok(ProxyIP::default())
+        } else {
+            Ok(response.json().await?)
+        }
+    } else {
+        Err(ProxierError::from_response(response).await)
+    }
 }
 
-// setup any of the proxies white listing if needed.
-pub async fn get_local_ip(client: &Client) -> String {
-    // set the whitelist to the proxy service.
-    let metadata_url = "https://api.ipify.org";
-    let mut ip_address = String::new();
-
-    match client.get(metadata_url).send().await {
-        Ok(response) => {
-            if response.status().is_success() {
-                ip_address = response.text().await.unwrap_or_default();
-                println!("The IP address of the local server is {}", ip_address);
-            } else {
-                eprintln!(
-                    "Failed to retrieve IP address. Status: {}",
-                    response.status()
-                );
-            }
+/// The amount of ip authorizations to request per page.
+const PAGE_SIZE: &str = "100";
+
+/// List all of the ip authorizations of the account across the pages.
+pub async fn list_ip_authorizations(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+) -> Result<Vec<ProxyIP>> {
+    let headers = auth_headers(credentials)?;
+    let mut ips = Vec::new();
+    let mut request = client
+        .get(string_concat!(base_url, "/api/v2/proxy/ipauthorization/"))
+        .query(&[("page_size", PAGE_SIZE)]);
+
+    loop {
+        let response = request.headers(headers.clone()).send().await?;
+
+        if !response.status().is_success() {
+            return Err(ProxierError::from_response(response).await);
+        }
+
+        let page: IpAuthorizations = response.json().await?;
+
+        ips.extend(page.results);
+
+        match page.next {
+            // the next url already holds the page query.
+            Some(next) if !next.is_empty() => request = client.get(next),
+            _ => break,
         }
-        Err(e) => {
-            println!("{:?}", e)
+    }
+
+    Ok(ips)
+}
+
+/// Find the ip authorization for the ip, in any notation of the address.
+pub async fn find_by_ip(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    ip: &str,
+) -> Result<Option<ProxyIP>> {
+    let addr = ip.parse::<IpAddr>().ok();
+
+    Ok(list_ip_authorizations(client, base_url, credentials)
+        .await?
+        .into_iter()
+        .find(|entry| match addr {
+            Some(addr) => same_ip(&entry.ip_address, addr),
+            _ => entry.ip_address == ip,
+        }))
+}
+
+/// Delete the ip authorization for the ip. Returns the removed entry if one was found.
+pub async fn delete_by_ip(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    ip: &str,
+) -> Result<Option<ProxyIP>> {
+    match find_by_ip(client, base_url, credentials, ip).await? {
+        Some(entry) => {
+            setup_proxy(client, base_url, credentials, &entry.id.to_string(), true).await?;
+            Ok(Some(entry))
         }
+        _ => Ok(None),
+    }
+}
+
+// setup any of the proxies white listing if needed.
+pub async fn get_local_ip(client: &Client, metadata_url: &str) -> Result<String> {
+    let response = client.get(metadata_url).send().await?;
+
+    if response.status().is_success() {
+        Ok(response.text().await?.trim().to_string())
+    } else {
+        Err(ProxierError::from_response(response).await)
     }
+}
+
+/// Get the server ip from webshare.
+pub async fn get_webshare_ip(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+) -> Result<String> {
+    let proxy_share_url = string_concat!(base_url, "/api/v2/proxy/ipauthorization/whatsmyip/");
+    let headers = auth_headers(credentials)?;
+    let response = client.get(&proxy_share_url).headers(headers).send().await?;
 
-    ip_address
+    if response.status().is_success() {
+        let ip: ProxtIP = response.json().await?;
+        Ok(ip.ip_address)
+    } else {
+        Err(ProxierError::from_response(response).await)
+    }
 }
 
 // Get the main local IP address
-pub async fn get_ip(client: &Client) -> String {
-    let proxy_share_url = "https://proxy.webshare.io/api/v2/proxy/ipauthorization/whatsmyip/";
-    let mut ip_address = String::new();
-
-    // SETUP IP whitelisting. TODO: move this to another file.
-    match dotenv::var("PROXY_SHARE_PASSWORD") {
-        Ok(password) => {
-            let mut headers = HeaderMap::new();
-
-            match HeaderValue::from_str(&string_concat!("Token ".to_string(), password).to_string())
-            {
-                Ok(hv) => {
-                    headers.insert(AUTHORIZATION, hv);
-                }
-                _ => (),
-            }
+pub async fn get_ip(
+    client: &Client,
+    base_url: &str,
+    credentials: &dyn CredentialSource,
+    metadata_url: &str,
+) -> Result<String> {
+    match get_webshare_ip(client, base_url, credentials).await {
+        Ok(ip_address) => Ok(ip_address),
+        _ => get_local_ip(client, metadata_url).await,
+    }
+}
 
-            match client.get(proxy_share_url).headers(headers).send().await {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        let text = response.text().await.unwrap_or_default();
-                        let ip: ProxtIP = serde_json::from_str(&text).unwrap_or_default();
+impl WebShareConfiguration {
+    /// The api base url to use.
+    pub fn api_url(&self) -> &str {
+        self.base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+    }
 
-                        println!("The IP address of the server is {}", ip.ip_address);
+    /// The credential source to use.
+    pub fn credentials(&self) -> &dyn CredentialSource {
+        credentials::or_default(&self.credentials)
+    }
+
+    /// Find the existing ip authorization for the ip.
+    async fn find_entry(&self, client: &Client, ip: &str) -> Result<Option<ProxyIP>> {
+        find_by_ip(client, self.api_url(), self.credentials(), ip).await
+    }
+}
+
+#[async_trait]
+impl ProxyProvider for WebShareConfiguration {
+    fn name(&self) -> &str {
+        "webshare"
+    }
+
+    fn account(&self) -> &str {
+        self.account.as_deref().unwrap_or(DEFAULT_ACCOUNT)
+    }
 
-                        ip_address = ip.ip_address;
-                    } else {
-                        eprintln!(
-                            "Failed to retrieve IP address. Status: {}",
-                            response.status()
-                        );
+    async fn whitelist(&mut self, client: &Client, addr: IpAddr) -> Result<WhitelistStatus> {
+        let ip = addr.to_string();
+        let ip = ip.as_str();
 
-                        ip_address = get_local_ip(&client).await;
+        // adopt the existing entry to keep the id for delisting.
+        let (entry, status) = match self.find_entry(client, ip).await? {
+            Some(entry) => (Some(entry), WhitelistStatus::AlreadyPresent),
+            _ => match setup_proxy(client, self.api_url(), self.credentials(), ip, false).await {
+                Ok(proxy_results) => (Some(proxy_results), WhitelistStatus::Created),
+                // webshare answers a duplicate with a 400 like any invalid request, so only an entry
+                // listed for the ip since the lookup makes it a duplicate.
+                Err(err @ ProxierError::Status { status, .. })
+                    if status == StatusCode::BAD_REQUEST =>
+                {
+                    match self.find_entry(client, ip).await? {
+                        Some(entry) => (Some(entry), WhitelistStatus::AlreadyPresent),
+                        _ => return Err(err),
                     }
                 }
-                Err(e) => {
-                    println!("{:?}", e);
-                    ip_address = get_local_ip(&client).await;
-                }
-            }
-        }
-        Err(e) => {
-            println!("Set the env {:?} to enable proxy whitelisting.", e)
+                Err(err) => return Err(err),
+            },
+        };
+
+        self.whitelist_entries
+            .retain(|tracked| !same_ip(&tracked.ip_address, addr));
+        self.whitelist_entries.extend(entry);
+
+        Ok(status)
+    }
+
+    async fn delist(&mut self, client: &Client, ip: IpAddr) -> Result<DelistStatus> {
+        let mut status = DelistStatus::NotPresent;
+
+        while let Some(pos) = self
+            .whitelist_entries
+            .iter()
+            .position(|tracked| same_ip(&tracked.ip_address, ip))
+        {
+            setup_proxy(
+                client,
+                self.api_url(),
+                self.credentials(),
+                &self.whitelist_entries[pos].id.to_string(),
+                true,
+            )
+            .await?;
+            self.whitelist_entries.remove(pos);
+            status = DelistStatus::Removed;
         }
-    };
 
-    ip_address
+        Ok(status)
+    }
+
+    async fn list(&self, client: &Client) -> Result<Vec<ProxyEntry>> {
+        Ok(
+            list_ip_authorizations(client, self.api_url(), self.credentials())
+                .await?
+                .into_iter()
+                .map(|entry| ProxyEntry {
+                    ip: entry.ip_address,
+                    id: Some(entry.id.to_string()),
+                    last_used_at: entry.last_used_at,
+                })
+                .collect(),
+        )
+    }
+
+    async fn lookup_ip(&self, client: &Client) -> Option<Result<String>> {
+        Some(get_webshare_ip(client, self.api_url(), self.credentials()).await)
+    }
+
+    fn entries(&self) -> Vec<ProxyEntry> {
+        self.whitelist_entries
+            .iter()
+            .map(|entry| ProxyEntry {
+                ip: entry.ip_address.clone(),
+                id: Some(entry.id.to_string()),
+                last_used_at: entry.last_used_at.clone(),
+            })
+            .collect()
+    }
+
+    async fn remove(&mut self, client: &Client, entry: &ProxyEntry) -> Result<DelistStatus> {
+        let id = match entry.id.clone() {
+            Some(id) => id,
+            _ => match self.find_entry(client, &entry.ip).await? {
+                Some(found) => found.id.to_string(),
+                _ => return Ok(DelistStatus::NotPresent),
+            },
+        };
+
+        let status =
+            removed(setup_proxy(client, self.api_url(), self.credentials(), &id, true).await)?;
+
+        self.whitelist_entries
+            .retain(|tracked| tracked.id.to_string() != id);
+
+        Ok(status)
+    }
 }
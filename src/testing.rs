@@ -0,0 +1,1329 @@
+use crate::proxies::{
+    datainpulse, evomi, iproyale, stun, webshare, AwsMetadataResolver, AzureMetadataResolver,
+    CredentialSource, DatainpulseConfiguration, EvomiConfiguration, GcpMetadataResolver,
+    IPRoyaleConfiguration, StaticCredentials, WebShareConfiguration,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+
+/// The provider apis emulated by the mock server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MockProvider {
+    /// The webshare ipauthorization api.
+    Webshare,
+    /// The iproyal whitelist-entries api.
+    IPRoyale,
+    /// The datainpulse whitelist_ip api.
+    Datainpulse,
+    /// The evomi ip_whitelist api.
+    Evomi,
+}
+
+/// A whitelist entry stored by the mock server.
+#[derive(Clone, Debug, Default)]
+pub struct MockEntry {
+    /// The id of the entry.
+    pub id: u64,
+    /// The whitelisted ip.
+    pub ip: String,
+    /// The port of the entry for iproyal.
+    pub port: u16,
+    /// The configuration of the entry for iproyal or the product for evomi.
+    pub configuration: String,
+    /// The rfc 3339 time the entry was last used for webshare.
+    pub last_used_at: Option<String>,
+}
+
+impl MockEntry {
+    /// The iproyal hash of the entry.
+    fn hash(&self) -> String {
+        format!("{:032x}", self.id)
+    }
+
+    fn webshare(&self) -> Value {
+        json!({
+            "id": self.id,
+            "ip_address": self.ip,
+            "created_at": null,
+            "last_used_at": self.last_used_at,
+        })
+    }
+
+    fn iproyale(&self) -> Value {
+        json!({
+            "hash": self.hash(),
+            "ip": self.ip,
+            "port": self.port,
+            "type": "ip",
+            "configuration": self.configuration,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    /// The next entry id.
+    next_id: u64,
+    /// The whitelist entries per provider.
+    entries: HashMap<MockProvider, Vec<MockEntry>>,
+    /// The ip reported by the ip echo endpoints.
+    server_ip: String,
+    /// The base url of the server.
+    base_url: String,
+    /// The providers where another replica wins the race for the next create.
+    races: HashSet<MockProvider>,
+    /// The amount of requests left to fail per provider.
+    failures: HashMap<MockProvider, usize>,
+    /// The providers answering their creates and deletes with an empty body.
+    bare: HashSet<MockProvider>,
+    /// The providers where the next create is rejected as invalid.
+    invalid: HashSet<MockProvider>,
+    /// The amount of requests received per provider.
+    requests: HashMap<MockProvider, usize>,
+}
+
+impl MockState {
+    fn entries(&mut self, provider: MockProvider) -> &mut Vec<MockEntry> {
+        self.entries.entry(provider).or_default()
+    }
+
+    fn insert(
+        &mut self,
+        provider: MockProvider,
+        ip: &str,
+        port: u16,
+        configuration: &str,
+    ) -> MockEntry {
+        self.next_id += 1;
+
+        let entry = MockEntry {
+            id: self.next_id,
+            ip: ip.into(),
+            port,
+            configuration: configuration.into(),
+            ..Default::default()
+        };
+
+        self.entries(provider).push(entry.clone());
+
+        entry
+    }
+
+    /// Reject the create with a 400 when it is set to be invalid, without storing the entry.
+    fn invalid(&mut self, provider: MockProvider) -> Option<Response> {
+        self.invalid.remove(&provider).then(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "detail": "Invalid request." })),
+            )
+                .into_response()
+        })
+    }
+
+    /// Store the entry first when another replica races the create, so the create fails as a duplicate.
+    fn race(&mut self, provider: MockProvider, ip: &str, port: u16, configuration: &str) {
+        if self.races.remove(&provider) {
+            self.insert(provider, ip, port, configuration);
+        }
+    }
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+/// An in-process http server emulating the provider whitelist apis. The server stops when dropped.
+///
+/// Any non-empty credentials are accepted.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: SharedState,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Start the mock server on a random local port.
+    pub async fn start() -> std::io::Result<MockServer> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = SharedState::default();
+
+        if let Ok(mut state) = state.lock() {
+            state.server_ip = "203.0.113.10".into();
+            state.base_url = format!("http://{}", addr);
+        }
+
+        let app = router(state.clone());
+        let handle = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Ok(MockServer {
+            addr,
+            state,
+            handle,
+        })
+    }
+
+    /// The base url for the provider configurations.
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The ip echo url for the proxier.
+    pub fn ip_echo_url(&self) -> String {
+        format!("http://{}/ip", self.addr)
+    }
+
+    /// Set the ip reported by the ip echo endpoints.
+    pub fn set_server_ip(&self, ip: &str) {
+        self.lock().server_ip = ip.into();
+    }
+
+    /// Whitelist an ip on the provider ahead of time. Evomi entries are added to the residential product.
+    pub fn insert(&self, provider: MockProvider, ip: &str) -> MockEntry {
+        let configuration = match provider {
+            MockProvider::Evomi => "residential",
+            _ => "",
+        };
+        self.lock().insert(provider, ip, 0, configuration)
+    }
+
+    /// Let another replica win the race for the next create on the provider.
+    /// The entry is stored and the create fails the way the provider reports duplicates.
+    pub fn race_next_create(&self, provider: MockProvider) {
+        self.lock().races.insert(provider);
+    }
+
+    /// Reject the next create on the provider with a 400 that is not about a duplicate entry.
+    pub fn reject_next_create(&self, provider: MockProvider) {
+        self.lock().invalid.insert(provider);
+    }
+
+    /// Answer the creates and deletes on the provider with an empty json body, without the entry or the success flag.
+    pub fn answer_bare(&self, provider: MockProvider) {
+        self.lock().bare.insert(provider);
+    }
+
+    /// Fail the next requests to the provider api with a 503, to exercise retries.
+    pub fn fail_requests(&self, provider: MockProvider, count: usize) {
+        self.lock().failures.insert(provider, count);
+    }
+
+    /// Set when the entries for the ip on the provider were last used.
+    pub fn set_last_used(&self, provider: MockProvider, ip: &str, last_used_at: &str) {
+        for entry in self
+            .lock()
+            .entries(provider)
+            .iter_mut()
+            .filter(|entry| entry.ip == ip)
+        {
+            entry.last_used_at = Some(last_used_at.into());
+        }
+    }
+
+    /// The amount of requests the provider api received, including the failed ones.
+    pub fn requests(&self, provider: MockProvider) -> usize {
+        self.lock()
+            .requests
+            .get(&provider)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The entries whitelisted on the provider.
+    pub fn entries(&self, provider: MockProvider) -> Vec<MockEntry> {
+        self.lock().entries(provider).clone()
+    }
+
+    /// The ips whitelisted on the provider.
+    pub fn whitelisted(&self, provider: MockProvider) -> Vec<String> {
+        self.entries(provider)
+            .into_iter()
+            .map(|entry| entry.ip)
+            .collect()
+    }
+
+    /// A webshare configuration pointing at the server.
+    pub fn webshare_configuration(&self) -> WebShareConfiguration {
+        WebShareConfiguration {
+            base_url: Some(self.base_url()),
+            credentials: Some(self.credentials()),
+            ..Default::default()
+        }
+    }
+
+    /// An iproyale configuration pointing at the server.
+    pub fn iproyale_configuration(&self) -> IPRoyaleConfiguration {
+        IPRoyaleConfiguration {
+            residential_user_hash: "mock".into(),
+            base_url: Some(self.base_url()),
+            credentials: Some(self.credentials()),
+            ..Default::default()
+        }
+    }
+
+    /// A datainpulse configuration pointing at the server.
+    pub fn datainpulse_configuration(&self) -> DatainpulseConfiguration {
+        DatainpulseConfiguration {
+            base_url: Some(self.base_url()),
+            credentials: Some(self.credentials()),
+            ..Default::default()
+        }
+    }
+
+    /// An evomi configuration pointing at the server.
+    pub fn evomi_configuration(&self) -> EvomiConfiguration {
+        EvomiConfiguration {
+            base_url: Some(self.base_url()),
+            credentials: Some(self.credentials()),
+            ..Default::default()
+        }
+    }
+
+    /// An aws metadata resolver pointing at the server.
+    pub fn aws_resolver(&self) -> AwsMetadataResolver {
+        AwsMetadataResolver {
+            base_url: Some(self.base_url()),
+            ..Default::default()
+        }
+    }
+
+    /// A gcp metadata resolver pointing at the server.
+    pub fn gcp_resolver(&self) -> GcpMetadataResolver {
+        GcpMetadataResolver {
+            base_url: Some(self.base_url()),
+            ..Default::default()
+        }
+    }
+
+    /// An azure metadata resolver pointing at the server.
+    pub fn azure_resolver(&self) -> AzureMetadataResolver {
+        AzureMetadataResolver {
+            base_url: Some(self.base_url()),
+            ..Default::default()
+        }
+    }
+
+    /// Mock credentials for all of the providers.
+    pub fn credentials(&self) -> Arc<dyn CredentialSource> {
+        Arc::new(
+            StaticCredentials::new()
+                .with(webshare::API_TOKEN_KEY, "mock")
+                .with(iproyale::API_TOKEN_KEY, "mock")
+                .with(datainpulse::USERNAME_KEY, "mock")
+                .with(datainpulse::PASSWORD_KEY, "mock")
+                .with(evomi::API_TOKEN_KEY, "mock"),
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// The state of the stun responder.
+#[derive(Debug, Default)]
+struct StunState {
+    mapped: Option<SocketAddr>,
+    dropped: usize,
+    requests: usize,
+}
+
+/// A local stun server answering binding requests with the address of the sender. The server stops when dropped.
+#[derive(Debug)]
+pub struct StunResponder {
+    addr: SocketAddr,
+    state: Arc<Mutex<StunState>>,
+    handle: JoinHandle<()>,
+}
+
+impl StunResponder {
+    /// Start the stun responder on a random local udp port.
+    pub async fn start() -> std::io::Result<StunResponder> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = socket.local_addr()?;
+        let state = Arc::new(Mutex::new(StunState::default()));
+        let shared = state.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut buf = [0; 576];
+
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                let request = &buf[..len];
+
+                if len < stun::HEADER_LEN
+                    || request[..2] != stun::BINDING_REQUEST.to_be_bytes()
+                    || request[4..8] != stun::MAGIC_COOKIE.to_be_bytes()
+                {
+                    continue;
+                }
+
+                let mapped = {
+                    let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+                    state.requests += 1;
+
+                    if state.dropped > 0 {
+                        state.dropped -= 1;
+                        continue;
+                    }
+
+                    state.mapped.unwrap_or(from)
+                };
+
+                let response = stun::binding_response(&request[8..stun::HEADER_LEN], mapped);
+                let _ = socket.send_to(&response, from).await;
+            }
+        });
+
+        Ok(StunResponder {
+            addr,
+            state,
+            handle,
+        })
+    }
+
+    /// The address of the responder.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The responder as `host:port` for the stun resolver.
+    pub fn server(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Answer with the address instead of the sender, emulating a nat.
+    pub fn set_mapped(&self, mapped: SocketAddr) {
+        self.lock().mapped = Some(mapped);
+    }
+
+    /// Ignore the next requests, to exercise retransmissions.
+    pub fn drop_requests(&self, count: usize) {
+        self.lock().dropped = count;
+    }
+
+    /// The amount of binding requests received.
+    pub fn requests(&self) -> usize {
+        self.lock().requests
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StunState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for StunResponder {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// A value stored by the redis stand-in.
+#[derive(Debug)]
+enum RedisValue {
+    /// A string with the instant it expires.
+    String(String, Option<Instant>),
+    /// The members of a sorted set with their score.
+    SortedSet(BTreeMap<String, f64>),
+}
+
+/// The state of the redis stand-in.
+#[derive(Debug, Default)]
+struct RedisState {
+    password: Option<String>,
+    values: HashMap<String, RedisValue>,
+}
+
+/// A local redis stand-in for the redis holder and lease stores. The server stops when dropped.
+///
+/// It speaks the redis protocol for the commands of the stores, including `MULTI` transactions and the lease scripts.
+#[derive(Debug)]
+pub struct MockRedis {
+    addr: SocketAddr,
+    state: Arc<Mutex<RedisState>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockRedis {
+    /// Start the redis stand-in on a random local port.
+    pub async fn start() -> std::io::Result<MockRedis> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(RedisState::default()));
+        let shared = state.clone();
+
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(redis_connection(stream, shared.clone()));
+            }
+        });
+
+        Ok(MockRedis {
+            addr,
+            state,
+            handle,
+        })
+    }
+
+    /// The stand-in as `host:port` for the redis stores.
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Require connections to authenticate with the password.
+    pub fn set_password(&self, password: &str) {
+        self.lock().password = Some(password.into());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RedisState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for MockRedis {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Answer the commands of a redis connection until it closes.
+async fn redis_connection(stream: TcpStream, state: Arc<Mutex<RedisState>>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut authenticated = false;
+    let mut queued: Option<Vec<Vec<String>>> = None;
+
+    while let Some(command) = read_command(&mut reader).await {
+        let reply = {
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            let name = command.first().map(|name| name.to_uppercase());
+
+            match (name.as_deref(), command.get(1)) {
+                (Some("AUTH"), Some(password)) => {
+                    authenticated = state.password.as_ref() == Some(password);
+                    match authenticated {
+                        true => "+OK\r\n".into(),
+                        _ => "-WRONGPASS invalid username-password pair\r\n".into(),
+                    }
+                }
+                _ if state.password.is_some() && !authenticated => {
+                    "-NOAUTH Authentication required.\r\n".into()
+                }
+                (Some("MULTI"), _) => {
+                    queued = Some(Vec::new());
+                    "+OK\r\n".into()
+                }
+                (Some("EXEC"), _) => match queued.take() {
+                    Some(commands) => {
+                        let mut reply = format!("*{}\r\n", commands.len());
+                        for command in &commands {
+                            reply.push_str(&state.execute(command));
+                        }
+                        reply
+                    }
+                    _ => "-ERR EXEC without MULTI\r\n".into(),
+                },
+                _ => match queued.as_mut() {
+                    Some(queued) => {
+                        queued.push(command);
+                        "+QUEUED\r\n".into()
+                    }
+                    _ => state.execute(&command),
+                },
+            }
+        };
+
+        if writer.write_all(reply.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Read a command sent as an array of bulk strings.
+async fn read_command<R: tokio::io::AsyncBufRead + Unpin>(reader: &mut R) -> Option<Vec<String>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+    let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+    let mut command = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).await.ok()?;
+        let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+        let mut data = vec![0; len + 2];
+        reader.read_exact(&mut data).await.ok()?;
+        data.truncate(len);
+        command.push(String::from_utf8(data).ok()?);
+    }
+
+    Some(command)
+}
+
+/// A bulk string reply.
+fn bulk(value: &str) -> String {
+    format!("${}\r\n{}\r\n", value.len(), value)
+}
+
+/// The score is within the range, where a bound starting with `(` is exclusive.
+fn in_range(score: f64, min: &str, max: &str) -> bool {
+    let bound = |bound: &str| match bound.strip_prefix('(') {
+        Some(bound) => (bound.parse().unwrap_or(f64::NAN), true),
+        _ => (bound.parse().unwrap_or(f64::NAN), false),
+    };
+    let ((min, min_open), (max, max_open)) = (bound(min), bound(max));
+
+    (score > min || (!min_open && score == min)) && (score < max || (!max_open && score == max))
+}
+
+impl RedisState {
+    /// The string of the key when it has not expired.
+    fn string(&mut self, key: &str) -> Option<&str> {
+        if let Some(RedisValue::String(_, Some(expires))) = self.values.get(key) {
+            if *expires <= Instant::now() {
+                self.values.remove(key);
+            }
+        }
+
+        match self.values.get(key) {
+            Some(RedisValue::String(value, _)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The sorted set of the key, created when missing.
+    fn sorted_set(&mut self, key: &str) -> Option<&mut BTreeMap<String, f64>> {
+        let value = self
+            .values
+            .entry(key.into())
+            .or_insert_with(|| RedisValue::SortedSet(BTreeMap::new()));
+
+        match value {
+            RedisValue::SortedSet(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    /// Drop the sorted set of the key when it is empty, as redis does.
+    fn prune(&mut self, key: &str) {
+        if matches!(self.values.get(key), Some(RedisValue::SortedSet(set)) if set.is_empty()) {
+            self.values.remove(key);
+        }
+    }
+
+    /// Run a command and build its reply.
+    fn execute(&mut self, command: &[String]) -> String {
+        let args: Vec<&str> = command.iter().map(String::as_str).collect();
+        let name = args
+            .first()
+            .map(|name| name.to_uppercase())
+            .unwrap_or_default();
+
+        match (name.as_str(), &args[1..]) {
+            ("GET", [key]) => match self.string(key) {
+                Some(value) => bulk(value),
+                _ => "$-1\r\n".into(),
+            },
+            ("SET", [key, value, rest @ ..]) => {
+                let expires = match rest {
+                    [px, ms] if px.eq_ignore_ascii_case("PX") => ms
+                        .parse()
+                        .ok()
+                        .map(|ms| Instant::now() + Duration::from_millis(ms)),
+                    _ => None,
+                };
+                self.values.insert(
+                    key.to_string(),
+                    RedisValue::String(value.to_string(), expires),
+                );
+                "+OK\r\n".into()
+            }
+            ("DEL", keys) => {
+                let removed = keys
+                    .iter()
+                    .filter(|key| self.values.remove(**key).is_some())
+                    .count();
+                format!(":{}\r\n", removed)
+            }
+            ("ZADD", [key, score, member]) => match (score.parse(), self.sorted_set(key)) {
+                (Ok(score), Some(set)) => {
+                    format!(
+                        ":{}\r\n",
+                        set.insert(member.to_string(), score).is_none() as u8
+                    )
+                }
+                _ => "-ERR invalid ZADD\r\n".into(),
+            },
+            ("ZREM", [key, member]) => {
+                let removed = self
+                    .sorted_set(key)
+                    .map_or(0, |set| set.remove(*member).is_some() as u8);
+                self.prune(key);
+                format!(":{}\r\n", removed)
+            }
+            ("ZREMRANGEBYSCORE", [key, min, max]) => {
+                let removed = self.sorted_set(key).map_or(0, |set| {
+                    let before = set.len();
+                    set.retain(|_, score| !in_range(*score, min, max));
+                    before - set.len()
+                });
+                self.prune(key);
+                format!(":{}\r\n", removed)
+            }
+            ("ZCARD", [key]) => {
+                let len = self.sorted_set(key).map_or(0, |set| set.len());
+                self.prune(key);
+                format!(":{}\r\n", len)
+            }
+            ("ZCOUNT", [key, min, max]) => {
+                let count = self.sorted_set(key).map_or(0, |set| {
+                    set.values()
+                        .filter(|score| in_range(**score, min, max))
+                        .count()
+                });
+                self.prune(key);
+                format!(":{}\r\n", count)
+            }
+            ("ZRANGEBYSCORE", [key, min, max]) => {
+                let mut members: Vec<(String, f64)> = self
+                    .sorted_set(key)
+                    .map(|set| {
+                        set.iter()
+                            .filter(|(_, score)| in_range(**score, min, max))
+                            .map(|(member, score)| (member.clone(), *score))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                self.prune(key);
+                members.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+                let mut reply = format!("*{}\r\n", members.len());
+                for (member, _) in &members {
+                    reply.push_str(&bulk(member));
+                }
+                reply
+            }
+            // the lease scripts of the redis lease store.
+            ("EVAL", [script, "1", key, holder, rest @ ..]) => {
+                let current = self.string(key).map(str::to_string);
+
+                if script.contains("PEXPIRE") {
+                    let ttl = rest.first().and_then(|ttl| ttl.parse().ok()).unwrap_or(0);
+                    let expires = Some(Instant::now() + Duration::from_millis(ttl));
+
+                    match current {
+                        Some(current) if current != *holder => ":0\r\n".into(),
+                        _ => {
+                            self.values.insert(
+                                key.to_string(),
+                                RedisValue::String(holder.to_string(), expires),
+                            );
+                            ":1\r\n".into()
+                        }
+                    }
+                } else if current.as_deref() == Some(*holder) {
+                    self.values.remove(*key);
+                    ":1\r\n".into()
+                } else {
+                    ":0\r\n".into()
+                }
+            }
+            _ => format!("-ERR unknown command '{}'\r\n", name),
+        }
+    }
+}
+
+/// The routes for all of the provider apis.
+fn router(state: SharedState) -> Router {
+    Router::new()
+        .route("/ip", get(ip_echo))
+        .route("/latest/api/token", put(aws_token))
+        .route("/latest/meta-data/public-ipv4", get(aws_public_ip))
+        .route(
+            "/computeMetadata/v1/instance/network-interfaces/0/access-configs/0/external-ip",
+            get(gcp_external_ip),
+        )
+        .route(
+            "/metadata/instance/network/interface/0/ipv4/ipAddress/0/publicIpAddress",
+            get(azure_public_ip),
+        )
+        .route(
+            "/api/v2/proxy/ipauthorization/",
+            get(webshare_list).post(webshare_create),
+        )
+        .route(
+            "/api/v2/proxy/ipauthorization/whatsmyip/",
+            get(webshare_whatsmyip),
+        )
+        .route(
+            "/api/v2/proxy/ipauthorization/{id}/",
+            delete(webshare_delete),
+        )
+        .route(
+            "/v1/residential-users/{user}/whitelist-entries",
+            get(iproyale_list).post(iproyale_create),
+        )
+        .route(
+            "/v1/residential-users/{user}/whitelist-entries/{hash}",
+            get(iproyale_get)
+                .put(iproyale_update)
+                .delete(iproyale_delete),
+        )
+        .route("/api/whitelist_ip", get(datainpulse_list))
+        .route(
+            "/api/whitelist_ip/{ip}",
+            post(datainpulse_create).delete(datainpulse_delete),
+        )
+        .route(
+            "/products/ip_whitelist",
+            get(evomi_residential_list)
+                .post(evomi_residential_create)
+                .delete(evomi_residential_delete),
+        )
+        .route(
+            "/products/{product}/ip_whitelist",
+            get(evomi_list).post(evomi_create).delete(evomi_delete),
+        )
+        .with_state(state)
+}
+
+/// Lock the shared state.
+fn lock(state: &SharedState) -> std::sync::MutexGuard<'_, MockState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The request carries the credentials the provider expects.
+fn authorized(headers: &HeaderMap, provider: MockProvider) -> bool {
+    let (name, prefix) = match provider {
+        MockProvider::Webshare => (header::AUTHORIZATION, "Token "),
+        MockProvider::IPRoyale => (header::AUTHORIZATION, "Bearer "),
+        MockProvider::Datainpulse => (header::AUTHORIZATION, "Basic "),
+        MockProvider::Evomi => (header::COOKIE, "Authorization="),
+    };
+
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix(prefix))
+        .is_some_and(|value| !value.is_empty())
+}
+
+/// The response for a request without credentials or failing on purpose, `None` to answer it.
+fn reject(state: &SharedState, headers: &HeaderMap, provider: MockProvider) -> Option<Response> {
+    let mut state = lock(state);
+    *state.requests.entry(provider).or_default() += 1;
+
+    if !authorized(headers, provider) {
+        return Some(unauthorized());
+    }
+
+    let failures = state.failures.entry(provider).or_default();
+
+    if *failures == 0 {
+        return None;
+    }
+
+    *failures -= 1;
+
+    Some(
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "detail": "Service temporarily unavailable." })),
+        )
+            .into_response(),
+    )
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({ "detail": "Invalid credentials." })),
+    )
+        .into_response()
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "detail": "Not found." })),
+    )
+        .into_response()
+}
+
+/// The page number and size from the query.
+fn page(query: &HashMap<String, String>, size_key: &str, default_size: usize) -> (usize, usize) {
+    let page = query
+        .get("page")
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(1usize)
+        .max(1);
+    let size = query
+        .get(size_key)
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(default_size)
+        .max(1);
+
+    (page, size)
+}
+
+async fn ip_echo(State(state): State<SharedState>) -> String {
+    lock(&state).server_ip.clone()
+}
+
+/// The session token handed out by the aws metadata stand-in.
+const AWS_TOKEN: &str = "mock-imds-token";
+
+async fn aws_token(headers: HeaderMap) -> Response {
+    if !headers.contains_key("x-aws-ec2-metadata-token-ttl-seconds") {
+        return (StatusCode::BAD_REQUEST, "missing token ttl").into_response();
+    }
+    AWS_TOKEN.into_response()
+}
+
+async fn aws_public_ip(State(state): State<SharedState>, headers: HeaderMap) -> Response {
+    // imdsv2 rejects requests without the session token.
+    match headers.get("x-aws-ec2-metadata-token") {
+        Some(token) if token == AWS_TOKEN => lock(&state).server_ip.clone().into_response(),
+        _ => (StatusCode::UNAUTHORIZED, "").into_response(),
+    }
+}
+
+async fn gcp_external_ip(State(state): State<SharedState>, headers: HeaderMap) -> Response {
+    match headers.get("metadata-flavor") {
+        Some(flavor) if flavor == "Google" => lock(&state).server_ip.clone().into_response(),
+        _ => (
+            StatusCode::FORBIDDEN,
+            "Missing Metadata-Flavor:Google header.",
+        )
+            .into_response(),
+    }
+}
+
+async fn azure_public_ip(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let metadata = headers.get("metadata").is_some_and(|value| value == "true");
+
+    if !metadata || !query.contains_key("api-version") {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "Bad request. Required metadata header not specified" })),
+        )
+            .into_response();
+    }
+
+    lock(&state).server_ip.clone().into_response()
+}
+
+async fn webshare_whatsmyip(State(state): State<SharedState>, headers: HeaderMap) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::Webshare) {
+        return rejected;
+    }
+    Json(json!({ "ip_address": lock(&state).server_ip })).into_response()
+}
+
+async fn webshare_list(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::Webshare) {
+        return rejected;
+    }
+    let mut state = lock(&state);
+    let (page, page_size) = page(&query, "page_size", 100);
+    let url = format!("{}/api/v2/proxy/ipauthorization/", state.base_url);
+    let entries = state.entries(MockProvider::Webshare);
+    let count = entries.len();
+    let results: Vec<Value> = entries
+        .iter()
+        .skip((page - 1) * page_size)
+        .take(page_size)
+        .map(MockEntry::webshare)
+        .collect();
+    let next = (page * page_size < count)
+        .then(|| format!("{}?page={}&page_size={}", url, page + 1, page_size));
+    let previous = (page > 1).then(|| format!("{}?page={}&page_size={}", url, page - 1, page_size));
+
+    Json(json!({
+        "count": count,
+        "next": next,
+        "previous": previous,
+        "results": results,
+    }))
+    .into_response()
+}
+
+async fn webshare_create(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::Webshare) {
+        return rejected;
+    }
+    let ip = body["ip_address"].as_str().unwrap_or_default();
+    let mut state = lock(&state);
+    if let Some(invalid) = state.invalid(MockProvider::Webshare) {
+        return invalid;
+    }
+    state.race(MockProvider::Webshare, ip, 0, "");
+
+    if state
+        .entries(MockProvider::Webshare)
+        .iter()
+        .any(|entry| entry.ip == ip)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "ip_address": ["IP address already exists."] })),
+        )
+            .into_response();
+    }
+
+    let entry = state.insert(MockProvider::Webshare, ip, 0, "");
+
+    (StatusCode::CREATED, Json(entry.webshare())).into_response()
+}
+
+async fn webshare_delete(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::Webshare) {
+        return rejected;
+    }
+    let mut state = lock(&state);
+    let entries = state.entries(MockProvider::Webshare);
+
+    match entries.iter().position(|entry| entry.id == id) {
+        Some(index) => {
+            entries.remove(index);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        _ => not_found(),
+    }
+}
+
+async fn iproyale_list(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::IPRoyale) {
+        return rejected;
+    }
+    let mut state = lock(&state);
+    let (page, per_page) = page(&query, "per_page", 15);
+    let entries = state.entries(MockProvider::IPRoyale);
+    let total = entries.len();
+    let data: Vec<Value> = entries
+        .iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .map(MockEntry::iproyale)
+        .collect();
+
+    Json(json!({
+        "data": data,
+        "meta": {
+            "current_page": page,
+            "last_page": total.div_ceil(per_page).max(1),
+            "per_page": per_page,
+            "total": total,
+        },
+    }))
+    .into_response()
+}
+
+async fn iproyale_create(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::IPRoyale) {
+        return rejected;
+    }
+    let ip = body["ip"].as_str().unwrap_or_default();
+    let port = body["port"].as_u64().unwrap_or_default() as u16;
+    let configuration = body["configuration"].as_str().unwrap_or_default();
+    let mut state = lock(&state);
+    if let Some(invalid) = state.invalid(MockProvider::IPRoyale) {
+        return invalid;
+    }
+    state.race(MockProvider::IPRoyale, ip, port, configuration);
+
+    if state
+        .entries(MockProvider::IPRoyale)
+        .iter()
+        .any(|entry| entry.ip == ip && entry.port == port)
+    {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "message": "Whitelist entry already exists." })),
+        )
+            .into_response();
+    }
+
+    let entry = state.insert(MockProvider::IPRoyale, ip, port, configuration);
+
+    (StatusCode::CREATED, Json(entry.iproyale())).into_response()
+}
+
+async fn iproyale_get(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path((_user, hash)): Path<(String, String)>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::IPRoyale) {
+        return rejected;
+    }
+    match lock(&state)
+        .entries(MockProvider::IPRoyale)
+        .iter()
+        .find(|entry| entry.hash() == hash)
+    {
+        Some(entry) => Json(entry.iproyale()).into_response(),
+        _ => not_found(),
+    }
+}
+
+async fn iproyale_update(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path((_user, hash)): Path<(String, String)>,
+    Json(body): Json<Value>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::IPRoyale) {
+        return rejected;
+    }
+    match lock(&state)
+        .entries(MockProvider::IPRoyale)
+        .iter_mut()
+        .find(|entry| entry.hash() == hash)
+    {
+        Some(entry) => {
+            entry.configuration = body["configuration"].as_str().unwrap_or_default().into();
+            Json(entry.iproyale()).into_response()
+        }
+        _ => not_found(),
+    }
+}
+
+async fn iproyale_delete(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path((_user, hash)): Path<(String, String)>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::IPRoyale) {
+        return rejected;
+    }
+    let mut state = lock(&state);
+    let entries = state.entries(MockProvider::IPRoyale);
+
+    match entries.iter().position(|entry| entry.hash() == hash) {
+        Some(index) => {
+            entries.remove(index);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        _ => not_found(),
+    }
+}
+
+async fn datainpulse_list(State(state): State<SharedState>, headers: HeaderMap) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::Datainpulse) {
+        return rejected;
+    }
+    let ips: Vec<Value> = lock(&state)
+        .entries(MockProvider::Datainpulse)
+        .iter()
+        .map(|entry| json!({ "ip": entry.ip, "created_at": null }))
+        .collect();
+
+    Json(json!({ "success": true, "ips": ips })).into_response()
+}
+
+async fn datainpulse_create(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(ip): Path<String>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::Datainpulse) {
+        return rejected;
+    }
+    let mut state = lock(&state);
+    if let Some(invalid) = state.invalid(MockProvider::Datainpulse) {
+        return invalid;
+    }
+    state.race(MockProvider::Datainpulse, &ip, 0, "");
+
+    if state
+        .entries(MockProvider::Datainpulse)
+        .iter()
+        .any(|entry| entry.ip == ip)
+    {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "success": false, "message": "IP already whitelisted." })),
+        )
+            .into_response();
+    }
+
+    state.insert(MockProvider::Datainpulse, &ip, 0, "");
+
+    datainpulse_written(&state, &ip)
+}
+
+/// The body of a datainpulse create or delete.
+fn datainpulse_written(state: &MockState, ip: &str) -> Response {
+    if state.bare.contains(&MockProvider::Datainpulse) {
+        return Json(json!({})).into_response();
+    }
+
+    Json(json!({ "success": true, "ip": ip })).into_response()
+}
+
+async fn datainpulse_delete(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(ip): Path<String>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::Datainpulse) {
+        return rejected;
+    }
+    let mut state = lock(&state);
+    let entries = state.entries(MockProvider::Datainpulse);
+
+    match entries.iter().position(|entry| entry.ip == ip) {
+        Some(index) => {
+            entries.remove(index);
+            datainpulse_written(&state, &ip)
+        }
+        _ => not_found(),
+    }
+}
+
+/// The product of the evomi endpoint without a product in the path.
+const EVOMI_DEFAULT_PRODUCT: &str = "residential";
+
+async fn evomi_residential_list(state: State<SharedState>, headers: HeaderMap) -> Response {
+    evomi_list(state, headers, Path(EVOMI_DEFAULT_PRODUCT.into())).await
+}
+
+async fn evomi_residential_create(
+    state: State<SharedState>,
+    headers: HeaderMap,
+    body: Json<Value>,
+) -> Response {
+    evomi_create(state, headers, Path(EVOMI_DEFAULT_PRODUCT.into()), body).await
+}
+
+async fn evomi_residential_delete(
+    state: State<SharedState>,
+    headers: HeaderMap,
+    body: Json<Value>,
+) -> Response {
+    evomi_delete(state, headers, Path(EVOMI_DEFAULT_PRODUCT.into()), body).await
+}
+
+async fn evomi_list(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(product): Path<String>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::Evomi) {
+        return rejected;
+    }
+    let data: Vec<Value> = lock(&state)
+        .entries(MockProvider::Evomi)
+        .iter()
+        .filter(|entry| entry.configuration == product)
+        .map(|entry| json!({ "ip": entry.ip, "product": entry.configuration }))
+        .collect();
+
+    Json(json!({ "data": data })).into_response()
+}
+
+async fn evomi_create(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(product): Path<String>,
+    Json(body): Json<Value>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::Evomi) {
+        return rejected;
+    }
+    let ip = body["ip"].as_str().unwrap_or_default();
+    let mut state = lock(&state);
+    if let Some(invalid) = state.invalid(MockProvider::Evomi) {
+        return invalid;
+    }
+    state.race(MockProvider::Evomi, ip, 0, &product);
+
+    if state
+        .entries(MockProvider::Evomi)
+        .iter()
+        .any(|entry| entry.ip == ip && entry.configuration == product)
+    {
+        // evomi fails with an internal error on duplicates.
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": "Internal Server Error" })),
+        )
+            .into_response();
+    }
+
+    let entry = state.insert(MockProvider::Evomi, ip, 0, &product);
+
+    if state.bare.contains(&MockProvider::Evomi) {
+        return Json(json!({})).into_response();
+    }
+
+    Json(json!({ "ip": entry.ip, "product": entry.configuration })).into_response()
+}
+
+async fn evomi_delete(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(product): Path<String>,
+    Json(body): Json<Value>,
+) -> Response {
+    if let Some(rejected) = reject(&state, &headers, MockProvider::Evomi) {
+        return rejected;
+    }
+    let ip = body["ip"].as_str().unwrap_or_default();
+    let mut state = lock(&state);
+    let entries = state.entries(MockProvider::Evomi);
+
+    match entries
+        .iter()
+        .position(|entry| entry.ip == ip && entry.configuration == product)
+    {
+        Some(index) => {
+            entries.remove(index);
+            Json(json!({ "success": true })).into_response()
+        }
+        _ => not_found(),
+    }
+}
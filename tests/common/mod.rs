@@ -0,0 +1,107 @@
+//! The fixtures shared by the integration tests. Each test file uses only part of them.
+#![allow(dead_code)]
+
+use proxier::proxies::{Election, Holders, LeaseStore, ProviderOutcome, Proxier, WhitelistReport};
+use proxier::testing::{MockProvider, MockServer};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The public ip of the server behind the nat.
+pub const SERVER_IP: &str = "203.0.113.10";
+
+/// Every provider the mock server stands in for.
+pub const PROVIDERS: [MockProvider; 4] = [
+    MockProvider::Webshare,
+    MockProvider::IPRoyale,
+    MockProvider::Datainpulse,
+    MockProvider::Evomi,
+];
+
+/// The name the provider reports.
+pub fn name(provider: MockProvider) -> &'static str {
+    match provider {
+        MockProvider::Webshare => "webshare",
+        MockProvider::IPRoyale => "iproyale",
+        MockProvider::Datainpulse => "datainpulse",
+        MockProvider::Evomi => "evomi",
+    }
+}
+
+/// Register the account of the provider on the mock server.
+pub fn register(proxier: &mut Proxier, server: &MockServer, provider: MockProvider) {
+    match provider {
+        MockProvider::Webshare => proxier.register(server.webshare_configuration()),
+        MockProvider::IPRoyale => proxier.register(server.iproyale_configuration()),
+        MockProvider::Datainpulse => proxier.register(server.datainpulse_configuration()),
+        MockProvider::Evomi => proxier.register(server.evomi_configuration()),
+    };
+}
+
+/// A proxier for the server ip with the providers registered, pointing at the mock server.
+pub fn proxier(
+    server: &MockServer,
+    server_ip: Option<&str>,
+    providers: &[MockProvider],
+) -> Proxier {
+    let mut proxier = Proxier::new(server_ip.and_then(|ip| ip.parse::<IpAddr>().ok()));
+
+    for provider in providers {
+        register(&mut proxier, server, *provider);
+    }
+
+    proxier
+}
+
+/// A replica behind the nat with the providers, and the holders and election it shares with the others.
+pub fn replica(
+    server: &MockServer,
+    providers: &[MockProvider],
+    holders: Option<Holders>,
+    election: Option<Election>,
+) -> Proxier {
+    let mut proxier = proxier(server, Some(SERVER_IP), providers);
+
+    if let Some(holders) = holders {
+        proxier.with_holders(holders);
+    }
+    if let Some(election) = election {
+        proxier.with_election(election);
+    }
+
+    proxier
+}
+
+/// An election in the store as the holder, polling quickly for the tests.
+pub fn election(store: impl LeaseStore + 'static, holder: &str) -> Election {
+    let mut election = Election::new(store, holder);
+    election.poll = Duration::from_millis(20);
+    election
+}
+
+/// The outcomes of the report in order.
+pub fn outcomes(report: &WhitelistReport) -> Vec<&ProviderOutcome> {
+    report
+        .providers
+        .iter()
+        .map(|report| &report.outcome)
+        .collect()
+}
+
+/// The outcome for the only provider of the report.
+pub fn outcome(report: &WhitelistReport) -> &ProviderOutcome {
+    assert_eq!(report.providers.len(), 1, "{:?}", report);
+    &report.providers[0].outcome
+}
+
+/// The report has outcomes and all of them match.
+pub fn all(report: &WhitelistReport, f: impl Fn(&ProviderOutcome) -> bool) -> bool {
+    !report.providers.is_empty() && outcomes(report).into_iter().all(f)
+}
+
+/// A file path in an empty directory of its own for the test.
+pub fn temp_path(name: &str, file: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("proxier-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir.join(file)
+}
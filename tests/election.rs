@@ -0,0 +1,464 @@
+mod common;
+
+use common::{all, election, PROVIDERS, SERVER_IP};
+use proxier::proxies::{
+    Holders, LeaseStore, MemoryHolderStore, MemoryLeaseStore, ProviderOutcome, Proxier,
+    ProxierError, PruneOptions,
+};
+use proxier::testing::{MockProvider, MockServer};
+use std::time::Duration;
+
+/// A replica on every provider sharing the holders and the election.
+fn replica(
+    server: &MockServer,
+    holders: &MemoryHolderStore,
+    leases: &MemoryLeaseStore,
+    holder: &str,
+) -> Proxier {
+    common::replica(
+        server,
+        &PROVIDERS,
+        Some(Holders::new(holders.clone(), holder)),
+        Some(election(leases.clone(), holder)),
+    )
+}
+
+#[tokio::test]
+async fn follower_delists_the_entries_of_the_leader_last() {
+    let server = MockServer::start().await.unwrap();
+    let (holders, leases) = (MemoryHolderStore::new(), MemoryLeaseStore::new());
+    let mut leader = replica(&server, &holders, &leases, "pod-a");
+    let mut follower = replica(&server, &holders, &leases, "pod-b");
+
+    let report = leader.whitelist().await;
+    assert!(
+        all(&report, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Created
+        )),
+        "{:?}",
+        report
+    );
+
+    let report = follower.whitelist().await;
+    assert!(
+        all(
+            &report,
+            |outcome| matches!(outcome, ProviderOutcome::Followed(leader) if leader == "pod-a")
+        ),
+        "{:?}",
+        report
+    );
+
+    let report = leader.delist().await;
+    assert!(report.is_success(), "{:?}", report);
+    assert!(
+        all(&report, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Retained(1)
+        )),
+        "{:?}",
+        report
+    );
+
+    for provider in PROVIDERS {
+        assert_eq!(server.whitelisted(provider), [SERVER_IP], "{:?}", provider);
+    }
+
+    // the follower never tracked the entries, but it is the last holder.
+    let report = follower.delist().await;
+    assert!(report.is_success(), "{:?}", report);
+    assert!(
+        all(&report, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Removed
+        )),
+        "{:?}",
+        report
+    );
+
+    for provider in PROVIDERS {
+        assert!(server.whitelisted(provider).is_empty(), "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn leader_delists_its_entries_last() {
+    let server = MockServer::start().await.unwrap();
+    let (holders, leases) = (MemoryHolderStore::new(), MemoryLeaseStore::new());
+    let mut leader = replica(&server, &holders, &leases, "pod-a");
+    let mut follower = replica(&server, &holders, &leases, "pod-b");
+
+    leader.whitelist().await;
+    follower.whitelist().await;
+
+    let report = follower.delist().await;
+    assert!(
+        all(&report, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Retained(1)
+        )),
+        "{:?}",
+        report
+    );
+
+    let report = leader.delist().await;
+    assert!(
+        all(&report, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Removed
+        )),
+        "{:?}",
+        report
+    );
+
+    for provider in PROVIDERS {
+        assert!(server.whitelisted(provider).is_empty(), "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn follower_delist_leaves_other_ips() {
+    let server = MockServer::start().await.unwrap();
+    let (holders, leases) = (MemoryHolderStore::new(), MemoryLeaseStore::new());
+    let mut leader = replica(&server, &holders, &leases, "pod-a");
+    let mut follower = replica(&server, &holders, &leases, "pod-b");
+
+    for provider in PROVIDERS {
+        server.insert(provider, "198.51.100.70");
+    }
+
+    leader.whitelist().await;
+    follower.whitelist().await;
+    leader.delist().await;
+    follower.delist().await;
+
+    for provider in PROVIDERS {
+        assert_eq!(
+            server.whitelisted(provider),
+            ["198.51.100.70"],
+            "{:?}",
+            provider
+        );
+    }
+}
+
+#[tokio::test]
+async fn follower_without_holders_leaves_the_entries_of_the_leader() {
+    let server = MockServer::start().await.unwrap();
+    let leases = MemoryLeaseStore::new();
+    let mut leader = common::replica(
+        &server,
+        &PROVIDERS,
+        None,
+        Some(election(leases.clone(), "pod-a")),
+    );
+    let mut follower = common::replica(
+        &server,
+        &PROVIDERS,
+        None,
+        Some(election(leases.clone(), "pod-b")),
+    );
+
+    leader.whitelist().await;
+    follower.whitelist().await;
+
+    // without the holders the follower can not tell whether other replicas still rely on the entries.
+    let report = follower.delist().await;
+    assert!(report.is_success(), "{:?}", report);
+    assert!(
+        all(&report, |outcome| matches!(
+            outcome,
+            ProviderOutcome::NotPresent
+        )),
+        "{:?}",
+        report
+    );
+
+    for provider in PROVIDERS {
+        assert_eq!(server.whitelisted(provider), [SERVER_IP], "{:?}", provider);
+    }
+
+    let report = leader.delist().await;
+    assert!(
+        all(&report, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Removed
+        )),
+        "{:?}",
+        report
+    );
+
+    for provider in PROVIDERS {
+        assert!(server.whitelisted(provider).is_empty(), "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn waiting_for_a_crashed_leader_times_out_at_the_deadline() {
+    let server = MockServer::start().await.unwrap();
+    let (holders, leases) = (MemoryHolderStore::new(), MemoryLeaseStore::new());
+
+    // the leader crashed while holding the lease, which outlives the deadline.
+    let key = format!("whitelist:{}", SERVER_IP);
+    assert!(leases
+        .acquire(&key, "pod-a", Duration::from_secs(3600))
+        .await
+        .unwrap());
+
+    let mut follower = replica(&server, &holders, &leases, "pod-b");
+    follower.with_deadline(Duration::from_millis(200));
+
+    let report = tokio::time::timeout(Duration::from_secs(5), follower.whitelist())
+        .await
+        .expect("the wait is bounded by the deadline");
+
+    assert!(!report.is_success(), "{:?}", report);
+    assert!(all(&report, ProviderOutcome::is_timed_out), "{:?}", report);
+    assert!(
+        matches!(&report.election, Some(ProxierError::Election(message)) if message.contains("timed out")),
+        "{:?}",
+        report.election
+    );
+
+    for provider in PROVIDERS {
+        assert!(server.whitelisted(provider).is_empty(), "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn prune_waiting_for_a_crashed_leader_times_out_at_the_deadline() {
+    let server = MockServer::start().await.unwrap();
+    let (holders, leases) = (MemoryHolderStore::new(), MemoryLeaseStore::new());
+
+    for provider in PROVIDERS {
+        server.insert(provider, "198.51.100.70");
+    }
+
+    let key = format!("prune:{}", SERVER_IP);
+    assert!(leases
+        .acquire(&key, "pod-a", Duration::from_secs(3600))
+        .await
+        .unwrap());
+
+    let mut follower = replica(&server, &holders, &leases, "pod-b");
+    follower.with_deadline(Duration::from_millis(200));
+
+    let report = tokio::time::timeout(
+        Duration::from_secs(5),
+        follower.prune(&PruneOptions::default()),
+    )
+    .await
+    .expect("the wait is bounded by the deadline")
+    .unwrap();
+
+    assert!(!report.is_success(), "{:?}", report);
+    assert!(report.entries.is_empty(), "{:?}", report);
+    assert!(
+        matches!(report.election, Some(ProxierError::Election(_))),
+        "{:?}",
+        report.election
+    );
+    assert!(
+        report.providers.len() == PROVIDERS.len()
+            && report
+                .providers
+                .iter()
+                .all(|report| report.outcome.is_timed_out()),
+        "{:?}",
+        report
+    );
+
+    for provider in PROVIDERS {
+        assert_eq!(
+            server.whitelisted(provider),
+            ["198.51.100.70"],
+            "{:?}",
+            provider
+        );
+    }
+}
+
+const NEXT_IP: &str = "198.51.100.71";
+
+/// A replica on every provider looking up its ip with the webshare account of the mock server.
+fn watching(
+    server: &MockServer,
+    holders: Option<&MemoryHolderStore>,
+    leases: &MemoryLeaseStore,
+    holder: &str,
+) -> Proxier {
+    let mut proxier = common::replica(
+        server,
+        &PROVIDERS,
+        holders.map(|holders| Holders::new(holders.clone(), holder)),
+        Some(election(leases.clone(), holder)),
+    );
+    // nothing listens here, so the ip has to come from the webshare account.
+    proxier.ip_echo_url = Some("http://127.0.0.1:9/ip".into());
+    proxier
+}
+
+#[tokio::test]
+async fn only_the_leader_moves_the_whitelist() {
+    let server = MockServer::start().await.unwrap();
+    server.set_server_ip(SERVER_IP);
+    let leases = MemoryLeaseStore::new();
+    let mut a = watching(&server, None, &leases, "pod-a");
+    let mut b = watching(&server, None, &leases, "pod-b");
+
+    a.whitelist().await;
+    b.whitelist().await;
+
+    server.set_server_ip(NEXT_IP);
+    let (a, b) = tokio::join!(a.refresh_server_ip(), b.refresh_server_ip());
+    let changes = [a.unwrap().unwrap(), b.unwrap().unwrap()];
+
+    let followed = |change: &&proxier::proxies::IpChange| {
+        all(&change.whitelist, |outcome| {
+            matches!(outcome, ProviderOutcome::Followed(_))
+        })
+    };
+    let (leaders, followers): (Vec<_>, Vec<_>) =
+        changes.iter().partition(|change| !followed(change));
+    assert_eq!((leaders.len(), followers.len()), (1, 1), "{:?}", changes);
+
+    let (leader, follower) = (leaders[0], followers[0]);
+    assert!(leader.whitelist.is_success(), "{:?}", leader);
+    assert!(
+        all(&leader.whitelist, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Created
+        )),
+        "{:?}",
+        leader
+    );
+    assert!(
+        all(&leader.delist, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Removed
+        )),
+        "{:?}",
+        leader
+    );
+    // the leader delisted the previous ip for every replica.
+    assert!(follower.delist.is_success(), "{:?}", follower);
+    assert!(
+        all(&follower.delist, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Followed(_)
+        )),
+        "{:?}",
+        follower
+    );
+
+    for provider in PROVIDERS {
+        assert_eq!(server.whitelisted(provider), [NEXT_IP], "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn last_holder_delists_the_previous_ip_after_the_move() {
+    let server = MockServer::start().await.unwrap();
+    server.set_server_ip(SERVER_IP);
+    let (holders, leases) = (MemoryHolderStore::new(), MemoryLeaseStore::new());
+    let mut leader = watching(&server, Some(&holders), &leases, "pod-a");
+    let mut follower = watching(&server, Some(&holders), &leases, "pod-b");
+
+    leader.whitelist().await;
+    follower.whitelist().await;
+
+    server.set_server_ip(NEXT_IP);
+    let change = leader.refresh_server_ip().await.unwrap().unwrap();
+    assert!(
+        all(&change.whitelist, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Created
+        )),
+        "{:?}",
+        change
+    );
+    // the follower still holds the previous ip.
+    assert!(
+        all(&change.delist, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Retained(1)
+        )),
+        "{:?}",
+        change
+    );
+
+    let change = follower.refresh_server_ip().await.unwrap().unwrap();
+    assert!(change.whitelist.is_success(), "{:?}", change);
+    assert!(change.delist.is_success(), "{:?}", change);
+    assert!(
+        all(
+            &change.whitelist,
+            |outcome| matches!(outcome, ProviderOutcome::Followed(leader) if leader == "pod-a")
+        ),
+        "{:?}",
+        change
+    );
+    assert!(
+        all(&change.delist, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Removed
+        )),
+        "{:?}",
+        change
+    );
+
+    for provider in PROVIDERS {
+        assert_eq!(server.whitelisted(provider), [NEXT_IP], "{:?}", provider);
+    }
+    assert!(leader.refresh_server_ip().await.unwrap().is_none());
+    assert!(follower.refresh_server_ip().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn failed_move_is_led_again_on_the_retry() {
+    let server = MockServer::start().await.unwrap();
+    server.set_server_ip(SERVER_IP);
+    let leases = MemoryLeaseStore::new();
+    let mut a = watching(&server, None, &leases, "pod-a");
+    let mut b = watching(&server, None, &leases, "pod-b");
+
+    a.whitelist().await;
+    b.whitelist().await;
+
+    server.set_server_ip(NEXT_IP);
+    server.fail_requests(MockProvider::IPRoyale, 100);
+    let change = a.refresh_server_ip().await.unwrap().unwrap();
+    assert!(!change.whitelist.is_success(), "{:?}", change);
+    server.fail_requests(MockProvider::IPRoyale, 0);
+
+    // the failed leader published nothing, so the next replica leads the move itself.
+    let change = b.refresh_server_ip().await.unwrap().unwrap();
+    assert!(change.whitelist.is_success(), "{:?}", change);
+    assert!(
+        !change
+            .whitelist
+            .providers
+            .iter()
+            .any(|report| matches!(report.outcome, ProviderOutcome::Followed(_))),
+        "{:?}",
+        change
+    );
+
+    for provider in PROVIDERS {
+        assert_eq!(server.whitelisted(provider), [NEXT_IP], "{:?}", provider);
+    }
+
+    // the failed leader retries and follows the replica that finished the move.
+    let change = a.refresh_server_ip().await.unwrap().unwrap();
+    assert!(change.whitelist.is_success(), "{:?}", change);
+    assert!(
+        all(
+            &change.whitelist,
+            |outcome| matches!(outcome, ProviderOutcome::Followed(leader) if leader == "pod-b")
+        ),
+        "{:?}",
+        change
+    );
+    assert!(a.refresh_server_ip().await.unwrap().is_none());
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use common::SERVER_IP;
+use proxier::proxies::{
+    FileHolderStore, HolderStore, Holders, MemoryHolderStore, ProviderOutcome, Proxier,
+};
+use proxier::testing::{MockProvider, MockServer};
+use std::time::Duration;
+
+/// A replica behind the nat on webshare with the holders.
+fn replica(server: &MockServer, holders: Holders) -> Proxier {
+    common::replica(server, &[MockProvider::Webshare], Some(holders), None)
+}
+
+/// Whitelist on two replicas and delist the first, which leaves the ip to the second.
+async fn last_holder_delists(server: &MockServer, holders: impl Fn(&str) -> Holders) {
+    let mut a = replica(server, holders("pod-a"));
+    let mut b = replica(server, holders("pod-b"));
+
+    assert!(a.whitelist().await.is_success());
+    assert!(b.whitelist().await.is_success());
+
+    let report = a.delist().await;
+    assert!(report.is_success(), "{:?}", report);
+    assert!(matches!(
+        report.providers[0].outcome,
+        ProviderOutcome::Retained(1)
+    ));
+    assert_eq!(server.whitelisted(MockProvider::Webshare), [SERVER_IP]);
+
+    let report = b.delist().await;
+    assert!(report.is_success(), "{:?}", report);
+    assert!(server.whitelisted(MockProvider::Webshare).is_empty());
+}
+
+#[tokio::test]
+async fn memory_holders_delist_on_the_last_release() {
+    let server = MockServer::start().await.unwrap();
+    let store = MemoryHolderStore::new();
+
+    last_holder_delists(&server, |holder| Holders::new(store.clone(), holder)).await;
+}
+
+#[tokio::test]
+async fn file_holders_delist_on_the_last_release() {
+    let server = MockServer::start().await.unwrap();
+    let path = common::temp_path("holders", "holders.json");
+
+    last_holder_delists(&server, |holder| {
+        Holders::new(FileHolderStore::new(&path), holder)
+    })
+    .await;
+
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[tokio::test]
+async fn holds_of_a_killed_replica_expire() {
+    let server = MockServer::start().await.unwrap();
+    let store = MemoryHolderStore::new();
+
+    let mut killed = Holders::random(store.clone());
+    killed.ttl = Duration::from_millis(200);
+    let mut a = replica(&server, killed);
+    let mut b = replica(&server, Holders::new(store.clone(), "pod-b"));
+
+    a.whitelist().await;
+    b.whitelist().await;
+    assert_eq!(store.count(SERVER_IP).await.unwrap(), 2);
+
+    // the replica is killed without delisting, so it never releases.
+    drop(a);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    assert_eq!(store.holders(SERVER_IP), ["pod-b"]);
+
+    let report = b.delist().await;
+    assert!(
+        matches!(report.providers[0].outcome, ProviderOutcome::Removed),
+        "{:?}",
+        report
+    );
+    assert!(server.whitelisted(MockProvider::Webshare).is_empty());
+}
+
+#[tokio::test]
+async fn refreshed_holds_do_not_expire() {
+    let server = MockServer::start().await.unwrap();
+    let store = MemoryHolderStore::new();
+
+    let mut holders = Holders::new(store.clone(), "pod-a");
+    holders.ttl = Duration::from_millis(300);
+    let mut a = replica(&server, holders);
+    a.whitelist().await;
+
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        a.refresh_holds().await.unwrap();
+    }
+
+    assert_eq!(store.holders(SERVER_IP), ["pod-a"]);
+
+    a.delist().await;
+    assert_eq!(store.count(SERVER_IP).await.unwrap(), 0);
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use async_trait::async_trait;
+use common::SERVER_IP;
+use proxier::proxies::{
+    DelistStatus, Ledger, ProviderOutcome, Proxier, ProxyEntry, ProxyProvider, Result,
+    WebShareConfiguration, WhitelistStatus,
+};
+use proxier::testing::{MockProvider, MockServer};
+use reqwest::Client;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A webshare account whose process dies right after the entry is created.
+#[derive(Debug)]
+struct Killed(WebShareConfiguration);
+
+#[async_trait]
+impl ProxyProvider for Killed {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    async fn whitelist(&mut self, client: &Client, ip: IpAddr) -> Result<WhitelistStatus> {
+        self.0.whitelist(client, ip).await?;
+        std::future::pending().await
+    }
+
+    async fn delist(&mut self, client: &Client, ip: IpAddr) -> Result<DelistStatus> {
+        self.0.delist(client, ip).await
+    }
+
+    async fn list(&self, client: &Client) -> Result<Vec<ProxyEntry>> {
+        self.0.list(client).await
+    }
+
+    fn entries(&self) -> Vec<ProxyEntry> {
+        self.0.entries()
+    }
+}
+
+/// A ledger path of its own for the test.
+fn ledger_path(name: &str) -> PathBuf {
+    common::temp_path(&format!("ledger-{}", name), "ledger.json")
+}
+
+/// A proxier for the server ip with the ledger and no provider registered.
+fn proxier(path: &Path) -> Proxier {
+    let mut proxier = Proxier::new(SERVER_IP.parse::<IpAddr>().ok());
+    proxier.with_ledger(path);
+    proxier
+}
+
+#[tokio::test]
+async fn recover_removes_entries_created_before_a_crash() {
+    let server = MockServer::start().await.unwrap();
+    let path = ledger_path("crash");
+
+    let mut crashed = proxier(&path);
+    crashed.register(Killed(server.webshare_configuration()));
+
+    // the whitelist never finishes, like a process killed between the create and the ledger update.
+    let whitelist = tokio::time::timeout(Duration::from_millis(500), crashed.whitelist()).await;
+    assert!(whitelist.is_err());
+    drop(crashed);
+
+    assert_eq!(server.whitelisted(MockProvider::Webshare), [SERVER_IP]);
+    let recorded = Ledger::new(&path).entries().unwrap();
+    assert_eq!(recorded.len(), 1);
+    assert_eq!(recorded[0].ip, SERVER_IP);
+
+    let mut restarted = proxier(&path);
+    restarted.register(server.webshare_configuration());
+    let report = restarted.recover().await.unwrap();
+
+    assert!(report.is_success(), "{:?}", report);
+    assert!(matches!(
+        report.providers[0].outcome,
+        ProviderOutcome::Removed
+    ));
+    assert!(server.whitelisted(MockProvider::Webshare).is_empty());
+    assert!(Ledger::new(&path).entries().unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[tokio::test]
+async fn whitelist_replaces_the_intents_with_the_entries() {
+    let server = MockServer::start().await.unwrap();
+    let path = ledger_path("intents");
+
+    let mut proxier = proxier(&path);
+    proxier.register(server.webshare_configuration());
+    proxier.register(server.iproyale_configuration());
+    assert!(proxier.whitelist().await.is_success());
+
+    // only the entries with their provider ids are left.
+    let recorded = Ledger::new(&path).entries().unwrap();
+    assert_eq!(recorded.len(), 2, "{:?}", recorded);
+    assert!(recorded.iter().all(|entry| entry.id.is_some()));
+
+    assert!(proxier.delist().await.is_success());
+    assert!(Ledger::new(&path).entries().unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[tokio::test]
+async fn failed_whitelist_leaves_no_intents() {
+    let server = MockServer::start().await.unwrap();
+    let path = ledger_path("failed");
+    server.fail_requests(MockProvider::Webshare, 10);
+
+    let mut proxier = proxier(&path);
+    proxier.register(server.webshare_configuration());
+    assert!(!proxier.whitelist().await.is_success());
+
+    assert!(Ledger::new(&path).entries().unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
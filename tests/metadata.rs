@@ -0,0 +1,143 @@
+use proxier::proxies::IpResolver;
+use proxier::testing::MockServer;
+use reqwest::{Client, StatusCode};
+use std::net::IpAddr;
+
+const SERVER_IP: &str = "198.51.100.20";
+
+async fn server() -> MockServer {
+    let server = MockServer::start().await.unwrap();
+    server.set_server_ip(SERVER_IP);
+    server
+}
+
+fn server_ip() -> IpAddr {
+    SERVER_IP.parse().unwrap()
+}
+
+#[tokio::test]
+async fn aws_resolves_with_an_imdsv2_session_token() {
+    let server = server().await;
+    let client = Client::new();
+
+    let ip = server.aws_resolver().resolve(&client).await.unwrap();
+
+    assert_eq!(ip, server_ip());
+}
+
+#[tokio::test]
+async fn aws_stand_in_requires_the_token_handshake() {
+    let server = server().await;
+    let client = Client::new();
+    let base = server.base_url();
+
+    // the token is only handed out with a ttl.
+    let response = client
+        .put(format!("{}/latest/api/token", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // imdsv1 requests without a token are rejected.
+    let response = client
+        .get(format!("{}/latest/meta-data/public-ipv4", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .get(format!("{}/latest/meta-data/public-ipv4", base))
+        .header("X-aws-ec2-metadata-token", "stale-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let token = client
+        .put(format!("{}/latest/api/token", base))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let ip = client
+        .get(format!("{}/latest/meta-data/public-ipv4", base))
+        .header("X-aws-ec2-metadata-token", token)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(ip, SERVER_IP);
+}
+
+#[tokio::test]
+async fn gcp_resolves_with_the_metadata_flavor_header() {
+    let server = server().await;
+    let client = Client::new();
+
+    let ip = server.gcp_resolver().resolve(&client).await.unwrap();
+    assert_eq!(ip, server_ip());
+
+    let response = client
+        .get(format!(
+            "{}/computeMetadata/v1/instance/network-interfaces/0/access-configs/0/external-ip",
+            server.base_url()
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn azure_resolves_with_the_metadata_header_and_api_version() {
+    let server = server().await;
+    let client = Client::new();
+
+    let ip = server.azure_resolver().resolve(&client).await.unwrap();
+    assert_eq!(ip, server_ip());
+
+    let url = format!(
+        "{}/metadata/instance/network/interface/0/ipv4/ipAddress/0/publicIpAddress",
+        server.base_url()
+    );
+
+    let response = client
+        .get(&url)
+        .query(&[("api-version", "2021-02-01")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = client
+        .get(&url)
+        .header("Metadata", "true")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn metadata_without_a_public_ip_fails() {
+    let server = server().await;
+    let client = Client::new();
+    // instances without a public ip get an empty answer.
+    server.set_server_ip("");
+
+    let err = server.aws_resolver().resolve(&client).await.unwrap_err();
+    assert!(err.to_string().contains("no public ip"), "{}", err);
+
+    let err = server.gcp_resolver().resolve(&client).await.unwrap_err();
+    assert!(err.to_string().contains("no public ip"), "{}", err);
+
+    let err = server.azure_resolver().resolve(&client).await.unwrap_err();
+    assert!(err.to_string().contains("no public ip"), "{}", err);
+}
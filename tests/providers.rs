@@ -0,0 +1,458 @@
+mod common;
+
+use common::{outcome, PROVIDERS, SERVER_IP};
+use futures::TryStreamExt;
+use proxier::proxies::iproyale;
+use proxier::proxies::{ProviderOutcome, Proxier, ProxierError, WebShareConfiguration};
+use proxier::testing::{MockProvider, MockServer};
+
+/// A proxier for the server ip with only the provider registered.
+fn proxier(server: &MockServer, provider: MockProvider) -> Proxier {
+    common::proxier(server, Some(SERVER_IP), &[provider])
+}
+
+#[tokio::test]
+async fn whitelist_creates_the_entry() {
+    let server = MockServer::start().await.unwrap();
+
+    for provider in PROVIDERS {
+        let report = proxier(&server, provider).whitelist().await;
+
+        assert!(report.is_success(), "{:?}: {:?}", provider, report);
+        assert!(
+            matches!(outcome(&report), ProviderOutcome::Created),
+            "{:?}: {:?}",
+            provider,
+            report
+        );
+        assert_eq!(server.whitelisted(provider), [SERVER_IP], "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn whitelist_twice_keeps_a_single_entry() {
+    let server = MockServer::start().await.unwrap();
+
+    for provider in PROVIDERS {
+        let mut proxier = proxier(&server, provider);
+        proxier.whitelist().await;
+        let report = proxier.whitelist().await;
+
+        assert!(
+            matches!(outcome(&report), ProviderOutcome::AlreadyPresent),
+            "{:?}: {:?}",
+            provider,
+            report
+        );
+        assert_eq!(server.whitelisted(provider), [SERVER_IP], "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn restart_adopts_the_existing_entry_and_delists_it() {
+    let server = MockServer::start().await.unwrap();
+
+    for provider in PROVIDERS {
+        // the entry is left by a previous run of the process.
+        server.insert(provider, SERVER_IP);
+
+        let mut proxier = proxier(&server, provider);
+        let report = proxier.whitelist().await;
+
+        assert!(
+            matches!(outcome(&report), ProviderOutcome::AlreadyPresent),
+            "{:?}: {:?}",
+            provider,
+            report
+        );
+        assert_eq!(server.whitelisted(provider), [SERVER_IP], "{:?}", provider);
+
+        let report = proxier.delist().await;
+
+        assert!(
+            matches!(outcome(&report), ProviderOutcome::Removed),
+            "{:?}: {:?}",
+            provider,
+            report
+        );
+        assert!(server.whitelisted(provider).is_empty(), "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn delist_removes_only_the_created_entry() {
+    let server = MockServer::start().await.unwrap();
+
+    for provider in PROVIDERS {
+        server.insert(provider, "198.51.100.7");
+
+        let mut proxier = proxier(&server, provider);
+        proxier.whitelist().await;
+        let report = proxier.delist().await;
+
+        assert!(report.is_success(), "{:?}: {:?}", provider, report);
+        assert!(
+            matches!(outcome(&report), ProviderOutcome::Removed),
+            "{:?}: {:?}",
+            provider,
+            report
+        );
+        assert_eq!(
+            server.whitelisted(provider),
+            ["198.51.100.7"],
+            "{:?}",
+            provider
+        );
+
+        let report = proxier.delist().await;
+
+        assert!(
+            !outcome(&report).is_failed(),
+            "{:?}: {:?}",
+            provider,
+            report
+        );
+    }
+}
+
+#[tokio::test]
+async fn conflicting_create_recovers_the_entry() {
+    let server = MockServer::start().await.unwrap();
+
+    for provider in PROVIDERS {
+        // another replica creates the entry between the lookup and the create.
+        server.race_next_create(provider);
+
+        let mut proxier = proxier(&server, provider);
+        let report = proxier.whitelist().await;
+
+        assert!(report.is_success(), "{:?}: {:?}", provider, report);
+        assert!(
+            matches!(outcome(&report), ProviderOutcome::AlreadyPresent),
+            "{:?}: {:?}",
+            provider,
+            report
+        );
+        assert_eq!(server.whitelisted(provider), [SERVER_IP], "{:?}", provider);
+
+        // the recovered entry is tracked, so it is delisted.
+        let report = proxier.delist().await;
+
+        assert!(
+            matches!(outcome(&report), ProviderOutcome::Removed),
+            "{:?}: {:?}",
+            provider,
+            report
+        );
+        assert!(server.whitelisted(provider).is_empty(), "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn datainpulse_accepts_a_success_without_the_flag() {
+    let server = MockServer::start().await.unwrap();
+    server.answer_bare(MockProvider::Datainpulse);
+
+    let mut proxier = proxier(&server, MockProvider::Datainpulse);
+    let report = proxier.whitelist().await;
+
+    assert!(report.is_success(), "{:?}", report);
+    assert!(
+        matches!(outcome(&report), ProviderOutcome::Created),
+        "{:?}",
+        report
+    );
+    assert_eq!(server.whitelisted(MockProvider::Datainpulse), [SERVER_IP]);
+
+    let report = proxier.delist().await;
+
+    assert!(report.is_success(), "{:?}", report);
+    assert!(
+        matches!(outcome(&report), ProviderOutcome::Removed),
+        "{:?}",
+        report
+    );
+    assert!(server.whitelisted(MockProvider::Datainpulse).is_empty());
+}
+
+#[tokio::test]
+async fn webshare_finds_the_entry_across_the_pages() {
+    let server = MockServer::start().await.unwrap();
+
+    // the entry of the server ip is on the third page of 100.
+    for i in 0..250 {
+        server.insert(
+            MockProvider::Webshare,
+            &format!("198.18.{}.{}", i / 250, i % 250),
+        );
+    }
+    server.insert(MockProvider::Webshare, SERVER_IP);
+
+    let mut proxier = proxier(&server, MockProvider::Webshare);
+    let listed = proxier.providers[0].list(&proxier.client).await.unwrap();
+    assert_eq!(listed.len(), 251);
+
+    let report = proxier.whitelist().await;
+    assert!(
+        matches!(outcome(&report), ProviderOutcome::AlreadyPresent),
+        "{:?}",
+        report
+    );
+
+    let report = proxier.delist().await;
+    assert!(
+        matches!(outcome(&report), ProviderOutcome::Removed),
+        "{:?}",
+        report
+    );
+
+    let whitelisted = server.whitelisted(MockProvider::Webshare);
+    assert_eq!(whitelisted.len(), 250);
+    assert!(!whitelisted.iter().any(|ip| ip == SERVER_IP));
+}
+
+#[tokio::test]
+async fn iproyale_streams_the_entries_across_the_pages() {
+    let server = MockServer::start().await.unwrap();
+
+    // the entry of the server ip is on the third page of 100.
+    for i in 0..250 {
+        server.insert(
+            MockProvider::IPRoyale,
+            &format!("198.18.{}.{}", i / 250, i % 250),
+        );
+    }
+    server.insert(MockProvider::IPRoyale, SERVER_IP);
+
+    let configuration = server.iproyale_configuration();
+    let entries: Vec<_> = iproyale::whitelist_entries(
+        &reqwest::Client::new(),
+        configuration.api_url(),
+        configuration.credentials(),
+        &configuration.residential_user_hash,
+    )
+    .try_collect()
+    .await
+    .unwrap();
+
+    assert_eq!(entries.len(), 251);
+    assert_eq!(entries[250].ip, SERVER_IP);
+    assert_eq!(server.requests(MockProvider::IPRoyale), 3);
+
+    let mut proxier = proxier(&server, MockProvider::IPRoyale);
+    let report = proxier.whitelist().await;
+    assert!(
+        matches!(outcome(&report), ProviderOutcome::AlreadyPresent),
+        "{:?}",
+        report
+    );
+
+    let report = proxier.delist().await;
+    assert!(
+        matches!(outcome(&report), ProviderOutcome::Removed),
+        "{:?}",
+        report
+    );
+
+    let whitelisted = server.whitelisted(MockProvider::IPRoyale);
+    assert_eq!(whitelisted.len(), 250);
+    assert!(!whitelisted.iter().any(|ip| ip == SERVER_IP));
+}
+
+#[tokio::test]
+async fn webshare_matches_ipv6_entries_in_any_notation() {
+    let server = MockServer::start().await.unwrap();
+    server.insert(
+        MockProvider::Webshare,
+        "2001:0db8:0000:0000:0000:0000:0000:0001",
+    );
+
+    let mut proxier = common::proxier(&server, Some("2001:db8::1"), &[MockProvider::Webshare]);
+    let report = proxier.whitelist().await;
+
+    assert!(
+        matches!(outcome(&report), ProviderOutcome::AlreadyPresent),
+        "{:?}",
+        report
+    );
+    assert_eq!(server.whitelisted(MockProvider::Webshare).len(), 1);
+
+    let report = proxier.delist().await;
+
+    assert!(
+        matches!(outcome(&report), ProviderOutcome::Removed),
+        "{:?}",
+        report
+    );
+    assert!(server.whitelisted(MockProvider::Webshare).is_empty());
+}
+
+#[tokio::test]
+async fn rejected_create_is_not_taken_for_a_duplicate() {
+    let server = MockServer::start().await.unwrap();
+
+    for provider in PROVIDERS {
+        server.reject_next_create(provider);
+
+        let mut proxier = proxier(&server, provider);
+        let report = proxier.whitelist().await;
+
+        assert!(!report.is_success(), "{:?}: {:?}", provider, report);
+        assert!(
+            matches!(outcome(&report), ProviderOutcome::Failed(ProxierError::Status { status, .. }) if status.as_u16() == 400),
+            "{:?}: {:?}",
+            provider,
+            report
+        );
+        assert!(proxier.providers[0].entries().is_empty(), "{:?}", provider);
+        assert!(server.whitelisted(provider).is_empty(), "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn evomi_create_without_the_entry_in_the_body_is_created() {
+    let server = MockServer::start().await.unwrap();
+    server.answer_bare(MockProvider::Evomi);
+
+    let mut proxier = proxier(&server, MockProvider::Evomi);
+    let report = proxier.whitelist().await;
+
+    assert!(report.is_success(), "{:?}", report);
+    assert!(
+        matches!(outcome(&report), ProviderOutcome::Created),
+        "{:?}",
+        report
+    );
+    assert_eq!(server.whitelisted(MockProvider::Evomi), [SERVER_IP]);
+
+    let report = proxier.delist().await;
+
+    assert!(
+        matches!(outcome(&report), ProviderOutcome::Removed),
+        "{:?}",
+        report
+    );
+    assert!(server.whitelisted(MockProvider::Evomi).is_empty());
+}
+
+#[tokio::test]
+async fn ipv6_server_ip_is_unsupported_on_the_ipv4_providers() {
+    let server = MockServer::start().await.unwrap();
+    let providers = [
+        MockProvider::IPRoyale,
+        MockProvider::Datainpulse,
+        MockProvider::Evomi,
+    ];
+
+    let mut proxier = common::proxier(&server, Some("2001:db8::1"), &providers);
+    let report = proxier.whitelist().await;
+
+    assert!(
+        common::all(&report, |outcome| matches!(
+            outcome,
+            ProviderOutcome::Unsupported(_)
+        )),
+        "{:?}",
+        report
+    );
+    for provider in providers {
+        assert_eq!(server.requests(provider), 0, "{:?}", provider);
+        assert!(server.whitelisted(provider).is_empty());
+    }
+}
+
+#[tokio::test]
+async fn non_public_server_ip_is_rejected_before_the_providers() {
+    let server = MockServer::start().await.unwrap();
+
+    for ip in ["10.0.0.5", "192.168.1.20", "127.0.0.1", "::1"] {
+        let mut proxier = common::proxier(&server, Some(ip), &PROVIDERS);
+        let report = proxier.whitelist().await;
+
+        assert!(
+            common::all(&report, |outcome| matches!(
+                outcome,
+                ProviderOutcome::Failed(ProxierError::InvalidIp(invalid)) if invalid == ip
+            )),
+            "{}: {:?}",
+            ip,
+            report
+        );
+    }
+
+    for provider in PROVIDERS {
+        assert_eq!(server.requests(provider), 0, "{:?}", provider);
+        assert!(server.whitelisted(provider).is_empty());
+    }
+}
+
+#[tokio::test]
+async fn accounts_of_the_same_provider_are_kept_apart() {
+    let first = MockServer::start().await.unwrap();
+    let second = MockServer::start().await.unwrap();
+    second.insert(MockProvider::Webshare, SERVER_IP);
+
+    let mut proxier = Proxier::new(SERVER_IP.parse().ok());
+    proxier.register(WebShareConfiguration {
+        account: Some("first".into()),
+        ..first.webshare_configuration()
+    });
+    proxier.register(WebShareConfiguration {
+        account: Some("second".into()),
+        ..second.webshare_configuration()
+    });
+
+    let report = proxier.whitelist().await;
+
+    assert_eq!(report.providers.len(), 2, "{:?}", report);
+    assert!(
+        matches!(
+            report.get_account("webshare", "first"),
+            Some(ProviderOutcome::Created)
+        ),
+        "{:?}",
+        report
+    );
+    assert!(
+        matches!(
+            report.get_account("webshare", "second"),
+            Some(ProviderOutcome::AlreadyPresent)
+        ),
+        "{:?}",
+        report
+    );
+    for provider in &proxier.providers {
+        let entries = provider.entries();
+        assert_eq!(entries.len(), 1, "{}", provider.account());
+        assert_eq!(entries[0].ip, SERVER_IP);
+    }
+
+    // a failing account keeps its entry while the other delists its own.
+    second.fail_requests(MockProvider::Webshare, 100);
+    let report = proxier.delist().await;
+
+    assert!(
+        matches!(
+            report.get_account("webshare", "first"),
+            Some(ProviderOutcome::Removed)
+        ),
+        "{:?}",
+        report
+    );
+    assert!(
+        report
+            .get_account("webshare", "second")
+            .is_some_and(ProviderOutcome::is_failed),
+        "{:?}",
+        report
+    );
+    assert!(first.whitelisted(MockProvider::Webshare).is_empty());
+    assert_eq!(second.whitelisted(MockProvider::Webshare), [SERVER_IP]);
+
+    let tracked: Vec<usize> = proxier
+        .providers
+        .iter()
+        .map(|provider| provider.entries().len())
+        .collect();
+    assert_eq!(tracked, [0, 1]);
+}
@@ -0,0 +1,190 @@
+mod common;
+
+use chrono::{Duration as TimeDelta, SecondsFormat, Utc};
+use common::{PROVIDERS, SERVER_IP};
+use proxier::proxies::ip::IpNet;
+use proxier::proxies::{PruneOptions, PruneOutcome, PruneReport};
+use proxier::testing::{MockProvider, MockServer};
+use std::time::Duration;
+
+const ORPHAN_IP: &str = "198.51.100.80";
+
+/// The ips of the entries selected for pruning on the provider.
+fn pruned(report: &PruneReport, provider: MockProvider) -> Vec<String> {
+    report
+        .entries
+        .iter()
+        .filter(|pruned| pruned.provider == common::name(provider))
+        .map(|pruned| pruned.entry.ip.clone())
+        .collect()
+}
+
+/// The time the duration ago, as the providers report it.
+fn ago(duration: TimeDelta) -> String {
+    (Utc::now() - duration).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[tokio::test]
+async fn prune_removes_the_orphaned_entries_only() {
+    let server = MockServer::start().await.unwrap();
+    let mut proxier = common::proxier(&server, Some(SERVER_IP), &PROVIDERS);
+    proxier.whitelist().await;
+
+    for provider in PROVIDERS {
+        server.insert(provider, ORPHAN_IP);
+    }
+
+    let report = proxier.prune(&PruneOptions::new()).await.unwrap();
+
+    assert!(report.is_success(), "{:?}", report);
+    assert_eq!(report.removed().count(), PROVIDERS.len(), "{:?}", report);
+    for provider in PROVIDERS {
+        assert_eq!(server.whitelisted(provider), [SERVER_IP], "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn allowlist_keeps_the_entry() {
+    let server = MockServer::start().await.unwrap();
+    let mut proxier = common::proxier(&server, Some(SERVER_IP), &PROVIDERS);
+
+    for provider in PROVIDERS {
+        server.insert(provider, ORPHAN_IP);
+        server.insert(provider, "2001:db8::80");
+    }
+
+    // the allowlist matches the ip regardless of its notation.
+    let options = PruneOptions::new()
+        .allow(ORPHAN_IP)
+        .allow("2001:0db8:0000:0000:0000:0000:0000:0080");
+    let report = proxier.prune(&options).await.unwrap();
+
+    assert!(report.is_success(), "{:?}", report);
+    assert!(report.entries.is_empty(), "{:?}", report);
+    for provider in PROVIDERS {
+        assert_eq!(
+            server.whitelisted(provider),
+            [ORPHAN_IP, "2001:db8::80"],
+            "{:?}",
+            provider
+        );
+    }
+}
+
+#[tokio::test]
+async fn dry_run_removes_nothing() {
+    let server = MockServer::start().await.unwrap();
+    let mut proxier = common::proxier(&server, Some(SERVER_IP), &PROVIDERS);
+
+    for provider in PROVIDERS {
+        server.insert(provider, ORPHAN_IP);
+    }
+
+    let options = PruneOptions {
+        dry_run: true,
+        ..Default::default()
+    };
+    let report = proxier.prune(&options).await.unwrap();
+
+    assert!(report.is_success(), "{:?}", report);
+    assert_eq!(report.entries.len(), PROVIDERS.len(), "{:?}", report);
+    assert!(report
+        .entries
+        .iter()
+        .all(|pruned| matches!(pruned.outcome, PruneOutcome::WouldRemove)));
+    for provider in PROVIDERS {
+        assert_eq!(pruned(&report, provider), [ORPHAN_IP], "{:?}", provider);
+        assert_eq!(server.whitelisted(provider), [ORPHAN_IP], "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn max_idle_keeps_the_recently_used_entries() {
+    let server = MockServer::start().await.unwrap();
+    let mut proxier = common::proxier(&server, Some(SERVER_IP), &[MockProvider::Webshare]);
+
+    server.insert(MockProvider::Webshare, "198.51.100.81");
+    server.insert(MockProvider::Webshare, "198.51.100.82");
+    server.set_last_used(
+        MockProvider::Webshare,
+        "198.51.100.81",
+        &ago(TimeDelta::hours(1)),
+    );
+    server.set_last_used(
+        MockProvider::Webshare,
+        "198.51.100.82",
+        &ago(TimeDelta::days(3)),
+    );
+
+    let options = PruneOptions {
+        max_idle: Some(Duration::from_secs(24 * 60 * 60)),
+        ..Default::default()
+    };
+    let report = proxier.prune(&options).await.unwrap();
+
+    assert!(report.is_success(), "{:?}", report);
+    assert_eq!(pruned(&report, MockProvider::Webshare), ["198.51.100.82"]);
+    assert_eq!(
+        server.whitelisted(MockProvider::Webshare),
+        ["198.51.100.81"]
+    );
+}
+
+#[tokio::test]
+async fn max_idle_keeps_the_entries_without_a_reported_usage() {
+    let server = MockServer::start().await.unwrap();
+    let mut proxier = common::proxier(&server, Some(SERVER_IP), &PROVIDERS);
+
+    for provider in PROVIDERS {
+        server.insert(provider, ORPHAN_IP);
+    }
+
+    let options = PruneOptions {
+        max_idle: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    let report = proxier.prune(&options).await.unwrap();
+
+    assert!(report.is_success(), "{:?}", report);
+    assert!(report.entries.is_empty(), "{:?}", report);
+    for provider in PROVIDERS {
+        assert_eq!(server.whitelisted(provider), [ORPHAN_IP], "{:?}", provider);
+    }
+}
+
+#[tokio::test]
+async fn server_ranges_keep_their_entries() {
+    let server = MockServer::start().await.unwrap();
+    let mut proxier = common::proxier(&server, Some(SERVER_IP), &PROVIDERS);
+    proxier
+        .server_ips
+        .push("198.51.100.0/24".parse::<IpNet>().unwrap());
+
+    for provider in PROVIDERS {
+        server.insert(provider, "198.51.100.80");
+        server.insert(provider, "192.0.2.80");
+    }
+
+    let report = proxier.prune(&PruneOptions::new()).await.unwrap();
+
+    assert!(report.is_success(), "{:?}", report);
+    for provider in PROVIDERS {
+        assert_eq!(pruned(&report, provider), ["192.0.2.80"], "{:?}", provider);
+        assert_eq!(
+            server.whitelisted(provider),
+            ["198.51.100.80"],
+            "{:?}",
+            provider
+        );
+    }
+}
+
+#[tokio::test]
+async fn prune_requires_a_server_ip() {
+    let server = MockServer::start().await.unwrap();
+    let mut proxier = common::proxier(&server, None, &PROVIDERS);
+    server.insert(MockProvider::Webshare, ORPHAN_IP);
+
+    assert!(proxier.prune(&PruneOptions::new()).await.is_err());
+    assert_eq!(server.whitelisted(MockProvider::Webshare), [ORPHAN_IP]);
+}
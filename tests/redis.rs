@@ -0,0 +1,174 @@
+mod common;
+
+use common::{election, SERVER_IP};
+use proxier::proxies::{
+    HolderStore, Holders, LeaseStore, ProviderOutcome, Proxier, ProxierError, RedisHolderStore,
+    RedisLeaseStore,
+};
+use proxier::testing::{MockProvider, MockRedis, MockServer};
+use std::time::Duration;
+
+fn holder_store(redis: &MockRedis) -> RedisHolderStore {
+    let mut store = RedisHolderStore::new(&redis.addr());
+    store.password = Some("secret".into());
+    store
+}
+
+fn lease_store(redis: &MockRedis) -> RedisLeaseStore {
+    let mut store = RedisLeaseStore::new(&redis.addr());
+    store.password = Some("secret".into());
+    store
+}
+
+async fn redis() -> MockRedis {
+    let redis = MockRedis::start().await.unwrap();
+    redis.set_password("secret");
+    redis
+}
+
+#[tokio::test]
+async fn holder_store_counts_the_holders() {
+    let redis = redis().await;
+    let store = holder_store(&redis);
+    let ttl = Duration::from_secs(60);
+
+    assert_eq!(store.acquire(SERVER_IP, "pod-a", ttl).await.unwrap(), 1);
+    assert_eq!(store.acquire(SERVER_IP, "pod-b", ttl).await.unwrap(), 2);
+    // acquiring again only extends the hold.
+    assert_eq!(store.acquire(SERVER_IP, "pod-a", ttl).await.unwrap(), 2);
+    assert_eq!(store.count(SERVER_IP).await.unwrap(), 2);
+    // the holders are ordered by the expiry of their hold.
+    let mut holders = store.holders(SERVER_IP).await.unwrap();
+    holders.sort();
+    assert_eq!(holders, ["pod-a", "pod-b"]);
+
+    assert_eq!(store.release(SERVER_IP, "pod-a").await.unwrap(), 1);
+    assert_eq!(store.release(SERVER_IP, "pod-a").await.unwrap(), 1);
+    assert_eq!(store.release(SERVER_IP, "pod-b").await.unwrap(), 0);
+    assert_eq!(store.count(SERVER_IP).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn holder_store_drops_expired_holds() {
+    let redis = redis().await;
+    let store = holder_store(&redis);
+
+    store
+        .acquire(SERVER_IP, "killed", Duration::from_millis(100))
+        .await
+        .unwrap();
+    store
+        .acquire(SERVER_IP, "pod-b", Duration::from_secs(60))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    assert_eq!(store.count(SERVER_IP).await.unwrap(), 1);
+    assert_eq!(store.holders(SERVER_IP).await.unwrap(), ["pod-b"]);
+    assert_eq!(store.release(SERVER_IP, "pod-b").await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn wrong_password_fails_with_the_store_error() {
+    let redis = redis().await;
+    let mut holders = holder_store(&redis);
+    holders.password = Some("wrong".into());
+    let mut leases = lease_store(&redis);
+    leases.password = None;
+
+    let err = holders.count(SERVER_IP).await.unwrap_err();
+    assert!(matches!(&err, ProxierError::Holders(message) if message.starts_with("WRONGPASS")));
+
+    let err = leases.state("whitelist").await.unwrap_err();
+    assert!(matches!(&err, ProxierError::Election(message) if message.starts_with("NOAUTH")));
+}
+
+#[tokio::test]
+async fn unreachable_store_times_out() {
+    // a listener that never answers.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut store = RedisHolderStore::new(&listener.local_addr().unwrap().to_string());
+    store.timeout = Duration::from_millis(100);
+
+    let err = store.count(SERVER_IP).await.unwrap_err();
+
+    assert!(err.to_string().contains("did not respond"), "{}", err);
+}
+
+#[tokio::test]
+async fn lease_store_elects_a_single_holder() {
+    let redis = redis().await;
+    let store = lease_store(&redis);
+    let ttl = Duration::from_secs(60);
+
+    assert!(store.acquire("whitelist", "pod-a", ttl).await.unwrap());
+    assert!(!store.acquire("whitelist", "pod-b", ttl).await.unwrap());
+    assert!(store.acquire("whitelist", "pod-a", ttl).await.unwrap());
+
+    // only the holder gives up the lease.
+    store.release("whitelist", "pod-b").await.unwrap();
+    assert!(!store.acquire("whitelist", "pod-b", ttl).await.unwrap());
+    store.release("whitelist", "pod-a").await.unwrap();
+    assert!(store.acquire("whitelist", "pod-b", ttl).await.unwrap());
+
+    assert_eq!(store.state("whitelist").await.unwrap(), None);
+    store.publish("whitelist", "done", ttl).await.unwrap();
+    assert_eq!(
+        store.state("whitelist").await.unwrap().as_deref(),
+        Some("done")
+    );
+    store.clear("whitelist").await.unwrap();
+    assert_eq!(store.state("whitelist").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn lease_store_expires_the_lease_and_state() {
+    let redis = redis().await;
+    let store = lease_store(&redis);
+    let ttl = Duration::from_millis(100);
+
+    assert!(store.acquire("prune", "crashed", ttl).await.unwrap());
+    store.publish("prune", "done", ttl).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    assert_eq!(store.state("prune").await.unwrap(), None);
+    assert!(store
+        .acquire("prune", "pod-b", Duration::from_secs(60))
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn replicas_share_the_redis_stores() {
+    let server = MockServer::start().await.unwrap();
+    let redis = redis().await;
+
+    let mut replicas: Vec<Proxier> = ["pod-a", "pod-b", "pod-c"]
+        .into_iter()
+        .map(|holder| {
+            common::replica(
+                &server,
+                &[MockProvider::Webshare],
+                Some(Holders::new(holder_store(&redis), holder)),
+                Some(election(lease_store(&redis), holder)),
+            )
+        })
+        .collect();
+
+    let reports =
+        futures::future::join_all(replicas.iter_mut().map(|proxier| proxier.whitelist())).await;
+
+    let created = reports
+        .iter()
+        .filter(|report| matches!(report.providers[0].outcome, ProviderOutcome::Created))
+        .count();
+    assert_eq!(created, 1, "{:?}", reports);
+    assert!(reports.iter().all(|report| report.is_success()));
+    assert_eq!(server.whitelisted(MockProvider::Webshare), [SERVER_IP]);
+
+    for proxier in &mut replicas {
+        assert!(proxier.delist().await.is_success());
+    }
+
+    assert!(server.whitelisted(MockProvider::Webshare).is_empty());
+}
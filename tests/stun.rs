@@ -0,0 +1,74 @@
+use proxier::proxies::stun;
+use proxier::testing::StunResponder;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test]
+async fn binding_maps_the_sender_address() {
+    let responder = StunResponder::start().await.unwrap();
+
+    let binding = stun::binding(&responder.server(), TIMEOUT).await.unwrap();
+
+    assert_eq!(binding.server, responder.addr());
+    assert_eq!(binding.mapped, binding.local);
+    assert!(!binding.is_nat());
+    assert!(binding.preserves_port());
+    assert_eq!(responder.requests(), 1);
+}
+
+#[tokio::test]
+async fn set_mapped_emulates_a_nat() {
+    let responder = StunResponder::start().await.unwrap();
+    let mapped: SocketAddr = "203.0.113.10:40000".parse().unwrap();
+    responder.set_mapped(mapped);
+
+    let binding = stun::binding(&responder.server(), TIMEOUT).await.unwrap();
+
+    assert_eq!(binding.mapped, mapped);
+    assert_eq!(binding.ip(), mapped.ip());
+    assert!(binding.is_nat());
+    assert!(!binding.preserves_port());
+}
+
+#[tokio::test]
+async fn set_mapped_answers_with_an_ipv6_address() {
+    let responder = StunResponder::start().await.unwrap();
+    let mapped: SocketAddr = "[2001:db8::10]:3478".parse().unwrap();
+    responder.set_mapped(mapped);
+
+    let binding = stun::mapped_address(&responder.server(), TIMEOUT)
+        .await
+        .unwrap();
+
+    assert_eq!(binding, mapped);
+}
+
+#[tokio::test]
+async fn retransmits_after_a_dropped_request() {
+    let responder = StunResponder::start().await.unwrap();
+    responder.drop_requests(1);
+
+    let started = Instant::now();
+    let binding = stun::binding(&responder.server(), TIMEOUT).await.unwrap();
+
+    assert_eq!(binding.mapped, binding.local);
+    assert_eq!(responder.requests(), 2);
+    // the retransmission waits for the initial timeout of 500ms.
+    assert!(started.elapsed() >= Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn times_out_when_every_request_is_dropped() {
+    let responder = StunResponder::start().await.unwrap();
+    responder.drop_requests(usize::MAX);
+
+    let err = stun::binding(&responder.server(), Duration::from_secs(2))
+        .await
+        .unwrap_err();
+
+    assert!(err.to_string().contains("did not respond"), "{}", err);
+    // sent at 0ms, 500ms and 1500ms.
+    assert_eq!(responder.requests(), 3);
+}
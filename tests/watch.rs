@@ -0,0 +1,155 @@
+mod common;
+
+use common::outcomes;
+use proxier::proxies::{Holders, MemoryHolderStore, ProviderOutcome, Proxier};
+use proxier::testing::{MockProvider, MockServer};
+use std::net::IpAddr;
+use std::time::Duration;
+
+const PREVIOUS_IP: &str = "203.0.113.50";
+const CURRENT_IP: &str = "198.51.100.50";
+const NEXT_IP: &str = "192.0.2.50";
+
+/// A proxier looking up its ip with the webshare account of the mock server only.
+fn proxier(server: &MockServer, server_ip: Option<&str>) -> Proxier {
+    let mut proxier = common::proxier(server, server_ip, &[MockProvider::Webshare]);
+    // nothing listens here, so the ip has to come from the webshare account.
+    proxier.ip_echo_url = Some("http://127.0.0.1:9/ip".into());
+    proxier
+}
+
+#[tokio::test]
+async fn refresh_looks_up_the_ip_with_the_registered_webshare_account() {
+    let server = MockServer::start().await.unwrap();
+    server.set_server_ip(CURRENT_IP);
+    let mut proxier = proxier(&server, None);
+
+    let change = proxier.refresh_server_ip().await.unwrap().unwrap();
+
+    assert!(change.previous.is_empty());
+    assert_eq!(change.current, CURRENT_IP.parse::<IpAddr>().unwrap());
+    assert!(change.whitelist.is_success(), "{:?}", change);
+    assert_eq!(server.whitelisted(MockProvider::Webshare), [CURRENT_IP]);
+    assert!(proxier.refresh_server_ip().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn failed_move_is_retried_on_the_next_refresh() {
+    let server = MockServer::start().await.unwrap();
+    server.set_server_ip(PREVIOUS_IP);
+    let mut proxier = proxier(&server, Some(PREVIOUS_IP));
+    proxier.register(server.iproyale_configuration());
+    assert!(proxier.whitelist().await.is_success());
+
+    server.set_server_ip(CURRENT_IP);
+    server.fail_requests(MockProvider::IPRoyale, 1);
+
+    let change = proxier.refresh_server_ip().await.unwrap().unwrap();
+    assert!(!change.whitelist.is_success());
+    assert!(matches!(
+        outcomes(&change.whitelist)[..],
+        [ProviderOutcome::Created, ProviderOutcome::Failed(_)]
+    ));
+    assert!(server.whitelisted(MockProvider::IPRoyale).is_empty());
+
+    // the ip did not change again, but the move did not finish.
+    let change = proxier.refresh_server_ip().await.unwrap().unwrap();
+    assert_eq!(change.previous, [PREVIOUS_IP.parse::<IpAddr>().unwrap()]);
+    assert!(change.whitelist.is_success(), "{:?}", change);
+    assert!(change.delist.is_success(), "{:?}", change);
+    assert!(matches!(
+        outcomes(&change.whitelist)[..],
+        [ProviderOutcome::AlreadyPresent, ProviderOutcome::Created]
+    ));
+
+    assert_eq!(server.whitelisted(MockProvider::Webshare), [CURRENT_IP]);
+    assert_eq!(server.whitelisted(MockProvider::IPRoyale), [CURRENT_IP]);
+    assert!(proxier.refresh_server_ip().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn change_before_the_retry_delists_every_unfinished_ip() {
+    let server = MockServer::start().await.unwrap();
+    server.set_server_ip(PREVIOUS_IP);
+    let mut proxier = proxier(&server, Some(PREVIOUS_IP));
+    proxier.register(server.iproyale_configuration());
+    assert!(proxier.whitelist().await.is_success());
+
+    // the move to the current ip fails on iproyale, so the previous ip stays whitelisted there.
+    server.set_server_ip(CURRENT_IP);
+    server.fail_requests(MockProvider::IPRoyale, 100);
+    let change = proxier.refresh_server_ip().await.unwrap().unwrap();
+    assert!(!change.whitelist.is_success());
+    assert!(!change.delist.is_success());
+    assert_eq!(server.whitelisted(MockProvider::IPRoyale), [PREVIOUS_IP]);
+    server.fail_requests(MockProvider::IPRoyale, 0);
+
+    // the ip changes again before the retry.
+    server.set_server_ip(NEXT_IP);
+    let change = proxier.refresh_server_ip().await.unwrap().unwrap();
+
+    let mut previous = change.previous.clone();
+    previous.sort();
+    let mut expected: Vec<IpAddr> = [PREVIOUS_IP, CURRENT_IP]
+        .iter()
+        .map(|ip| ip.parse().unwrap())
+        .collect();
+    expected.sort();
+    assert_eq!(previous, expected);
+    assert!(change.whitelist.is_success(), "{:?}", change);
+    assert!(change.delist.is_success(), "{:?}", change);
+
+    assert_eq!(server.whitelisted(MockProvider::Webshare), [NEXT_IP]);
+    assert_eq!(server.whitelisted(MockProvider::IPRoyale), [NEXT_IP]);
+    assert!(proxier.refresh_server_ip().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn moving_back_keeps_the_ip_whitelisted() {
+    let server = MockServer::start().await.unwrap();
+    server.set_server_ip(PREVIOUS_IP);
+    let mut proxier = proxier(&server, Some(PREVIOUS_IP));
+    proxier.register(server.iproyale_configuration());
+    assert!(proxier.whitelist().await.is_success());
+
+    server.set_server_ip(CURRENT_IP);
+    server.fail_requests(MockProvider::IPRoyale, 1);
+    assert!(!proxier
+        .refresh_server_ip()
+        .await
+        .unwrap()
+        .unwrap()
+        .whitelist
+        .is_success());
+
+    // the ip moves back before the retry, so only the unfinished current ip is delisted.
+    server.set_server_ip(PREVIOUS_IP);
+    let change = proxier.refresh_server_ip().await.unwrap().unwrap();
+    assert_eq!(change.previous, [CURRENT_IP.parse::<IpAddr>().unwrap()]);
+    assert!(change.delist.is_success(), "{:?}", change);
+
+    assert_eq!(server.whitelisted(MockProvider::Webshare), [PREVIOUS_IP]);
+    assert_eq!(server.whitelisted(MockProvider::IPRoyale), [PREVIOUS_IP]);
+}
+
+#[tokio::test]
+async fn watcher_refreshes_the_holds() {
+    let server = MockServer::start().await.unwrap();
+    server.set_server_ip(CURRENT_IP);
+    let store = MemoryHolderStore::new();
+    let mut holders = Holders::new(store.clone(), "pod-a");
+    holders.ttl = Duration::from_millis(300);
+
+    let mut proxier = proxier(&server, Some(CURRENT_IP));
+    proxier.with_holders(holders);
+    proxier.whitelist().await;
+
+    let watcher = proxier.watch(Duration::from_millis(50));
+    tokio::time::sleep(Duration::from_millis(700)).await;
+
+    assert_eq!(store.holders(CURRENT_IP), ["pod-a"]);
+
+    let mut proxier = watcher.stop().await;
+    assert!(proxier.delist().await.is_success());
+    assert!(store.holders(CURRENT_IP).is_empty());
+}